@@ -0,0 +1,25 @@
+use robotica::{LogConfig, Node};
+use std::time::Duration;
+
+// Bridges a topic between two separate zenoh networks, each reached through its own router, by
+// running one node per network in the same process.
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let robot = Node::builder("bridge_robot")
+        .logging(LogConfig::new())
+        .multicast_scouting(false)
+        .connect(["tcp/192.168.1.10:7447"])
+        .build()
+        .await?;
+    let ground = Node::builder("bridge_ground")
+        .multicast_scouting(false)
+        .connect(["tcp/10.0.0.1:7447"])
+        .build()
+        .await?;
+    let _relay = robot
+        .relay("test_topic", &ground, "robot/test_topic")
+        .await?;
+    loop {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
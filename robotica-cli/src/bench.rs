@@ -0,0 +1,49 @@
+use robotica::Node;
+use std::time::{Duration, SystemTime};
+
+const BENCH_TYPE_URL: &str = "type.googleapis.com/robotica.StringMessage";
+
+pub async fn bench_cmd(
+    node: Node,
+    topic: String,
+    samples: usize,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    // The subscriber must be declared before we start publishing so we don't miss any samples
+    let mut subscriber = node.subscribe_untyped(&topic).await?;
+    let publisher = node.publish_untyped(&topic, BENCH_TYPE_URL).await?;
+    println!("Benchmarking latency on \"{topic}\" over {samples} sample(s)...");
+
+    let mut latencies = Vec::with_capacity(samples);
+    let mut lost: usize = 0;
+    for idx in 0..samples {
+        publisher
+            .send(serde_json::json!({ "data": idx.to_string() }))
+            .await?;
+        let Ok(msg) = tokio::time::timeout(timeout, subscriber.recv()).await else {
+            lost += 1;
+            continue;
+        };
+        let sent_at: SystemTime = msg?
+            .header
+            .message_timestamp
+            .ok_or_else(|| anyhow::anyhow!("received message without a timestamp"))?
+            .try_into()?;
+        latencies.push(SystemTime::now().duration_since(sent_at)?);
+    }
+
+    if latencies.is_empty() {
+        anyhow::bail!("no messages were received back on \"{topic}\"");
+    }
+    latencies.sort();
+    println!("Received {} sample(s), {lost} lost", latencies.len());
+    for p in [50, 95, 99] {
+        println!("p{p}: {:?}", percentile(&latencies, p));
+    }
+    Ok(())
+}
+
+/// Returns the `p`th percentile of an already sorted, non-empty slice of durations.
+fn percentile(sorted: &[Duration], p: usize) -> Duration {
+    sorted[(sorted.len() - 1) * p / 100]
+}
@@ -0,0 +1,104 @@
+use super::GraphFormat;
+use robotica::{Node, SystemGraph};
+use std::collections::{BTreeSet, HashMap};
+
+const PUBLISHER_COLOR: &str = "blue";
+const SUBSCRIBER_COLOR: &str = "darkgreen";
+
+pub async fn graph_cmd(node: Node, format: GraphFormat) -> anyhow::Result<()> {
+    let graph = node.graph().await?;
+    match format {
+        GraphFormat::Dot => print!("{}", to_dot(&graph)),
+        GraphFormat::Text => print!("{}", to_text(&graph)),
+    }
+    Ok(())
+}
+
+/// Renders the graph in Graphviz format, with nodes as boxes and topics as ellipses. Publishers are
+/// drawn as edges from nodes to topics, labeled with the type they publish, and subscribers as
+/// edges from topics to nodes, each in its own color.
+fn to_dot(graph: &SystemGraph) -> String {
+    let names = node_names(graph);
+    let topics: BTreeSet<&str> = graph
+        .publishers
+        .iter()
+        .map(|p| p.topic.as_str())
+        .chain(graph.subscribers.iter().map(|s| s.topic.as_str()))
+        .collect();
+
+    let mut dot = String::from("digraph robotica {\n    rankdir=LR;\n");
+    for node in &graph.nodes {
+        dot += &format!(
+            "    \"node:{}\" [shape=box, label=\"{}\"];\n",
+            escape(&node.replier_id),
+            escape(&node.name)
+        );
+    }
+    for topic in topics {
+        dot += &format!(
+            "    \"topic:{}\" [shape=ellipse, label=\"{}\"];\n",
+            escape(topic),
+            escape(topic)
+        );
+    }
+    for publisher in &graph.publishers {
+        dot += &format!(
+            "    \"node:{}\" -> \"topic:{}\" [color={PUBLISHER_COLOR}, label=\"{}\"];\n",
+            escape(&publisher.node_id),
+            escape(&publisher.topic),
+            escape(&publisher.type_url)
+        );
+    }
+    for subscriber in &graph.subscribers {
+        dot += &format!(
+            "    \"topic:{}\" -> \"node:{}\" [color={SUBSCRIBER_COLOR}];\n",
+            escape(&subscriber.topic),
+            escape(&subscriber.node_id)
+        );
+    }
+    // Nodes that publish or subscribe but didn't answer the node query are labeled by their ID
+    for id in graph
+        .publishers
+        .iter()
+        .map(|p| &p.node_id)
+        .chain(graph.subscribers.iter().map(|s| &s.node_id))
+        .filter(|id| !names.contains_key(id.as_str()))
+        .collect::<BTreeSet<_>>()
+    {
+        dot += &format!("    \"node:{}\" [shape=box];\n", escape(id));
+    }
+    dot += "}\n";
+    dot
+}
+
+/// Renders the edges of the graph as one line per publisher and subscriber pair.
+fn to_text(graph: &SystemGraph) -> String {
+    let names = node_names(graph);
+    let name = |id: &str| names.get(id).copied().unwrap_or(id).to_string();
+    graph
+        .edges
+        .iter()
+        .map(|edge| {
+            format!(
+                "{} -> {} on {} ({})\n",
+                name(&edge.publisher_id),
+                name(&edge.subscriber_id),
+                edge.topic,
+                edge.type_url
+            )
+        })
+        .collect()
+}
+
+fn node_names(graph: &SystemGraph) -> HashMap<&str, &str> {
+    graph
+        .nodes
+        .iter()
+        .map(|node| (node.replier_id.as_str(), node.name.as_str()))
+        .collect()
+}
+
+/// Escapes a string to be used inside a quoted Graphviz ID.
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -1,7 +1,9 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use robotica::{log::LevelFilter, LogConfig, Node};
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
+mod bench;
+mod graph;
 mod topic;
 
 #[derive(Parser, Debug)]
@@ -19,6 +21,26 @@ enum Commands {
         #[command(subcommand)]
         command: TopicCommands,
     },
+    /// Measures end-to-end latency by publishing timestamped messages on a topic and receiving
+    /// them back
+    Bench {
+        /// Name of the topic to benchmark on
+        topic_name: String,
+        /// How many messages to send and time
+        #[arg(short = 'n', long, default_value_t = 1000)]
+        samples: usize,
+        /// How long to wait for each message to come back before counting it as lost, in seconds
+        #[arg(short, long, default_value_t = 1.)]
+        timeout_s: f32,
+    },
+    /// Prints the pub/sub graph of the whole system: every node, and the topics they publish and
+    /// subscribe to
+    Graph {
+        /// The format to print the graph in. Use dot to render it with Graphviz, e.g. by piping it
+        /// to `dot -Tpng -o graph.png`
+        #[arg(long, value_enum, default_value_t = GraphFormat::Text)]
+        format: GraphFormat,
+    },
 }
 
 /// A collection of all commands relating to listing, printing, and managing topics.
@@ -34,8 +56,11 @@ enum TopicCommands {
         topic_name: String,
         /// The type of the message we're sending
         topic_type: String,
-        /// The JSON data to send
+        /// The data to send, as JSON or YAML depending on the format
         data: String,
+        /// The format of the data to send
+        #[arg(long, value_enum, default_value_t = DataFormat::Json)]
+        format: DataFormat,
         /// Frequency at which to send the data
         #[arg(short, long, default_value_t = 1.)]
         frequency_hz: f32,
@@ -43,8 +68,37 @@ enum TopicCommands {
         #[arg(short, long)]
         repetitions: Option<usize>,
     },
+    /// Prints a message of the given type with every field set to its default value, to use as a
+    /// starting point for the data given to pub
+    Template {
+        /// The type of the message, as a type URL
+        topic_type: String,
+        /// The format to print the message in
+        #[arg(long, value_enum, default_value_t = DataFormat::Json)]
+        format: DataFormat,
+    },
     /// Lists out all topics currently active and publishing
-    List,
+    List {
+        /// Print topics as a tree, grouped by their `/`-delimited prefixes
+        #[arg(long)]
+        tree: bool,
+    },
+}
+
+/// The formats the system graph can be printed in.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum GraphFormat {
+    /// One line per publisher and subscriber pair
+    Text,
+    /// Graphviz DOT
+    Dot,
+}
+
+/// The formats messages can be given in on the command line.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum DataFormat {
+    Json,
+    Yaml,
 }
 
 #[tokio::main]
@@ -65,5 +119,19 @@ async fn main() -> anyhow::Result<()> {
 
     match args.command {
         Commands::Topic { command } => topic::topic_cmd(node, command).await,
+        Commands::Bench {
+            topic_name,
+            samples,
+            timeout_s,
+        } => {
+            bench::bench_cmd(
+                node,
+                topic_name,
+                samples,
+                Duration::from_secs_f32(timeout_s),
+            )
+            .await
+        }
+        Commands::Graph { format } => graph::graph_cmd(node, format).await,
     }
 }
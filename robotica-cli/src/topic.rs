@@ -1,16 +1,24 @@
-use super::TopicCommands;
+use super::{DataFormat, TopicCommands};
 use robotica::Node;
-use std::time::Duration;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+    time::Duration,
+};
 
 #[allow(clippy::module_name_repetitions)]
 pub async fn topic_cmd(node: Node, command: TopicCommands) -> anyhow::Result<()> {
     match command {
-        TopicCommands::List => topic_list().await,
+        TopicCommands::List { tree } => topic_list(&node, tree).await,
+        TopicCommands::Template { topic_type, format } => {
+            topic_template(&node, &topic_type, format)
+        }
         TopicCommands::Sub { topic_name } => topic_sub(node, topic_name).await,
         TopicCommands::Pub {
             topic_name,
             topic_type,
             data,
+            format,
             frequency_hz,
             repetitions,
         } => {
@@ -19,6 +27,7 @@ pub async fn topic_cmd(node: Node, command: TopicCommands) -> anyhow::Result<()>
                 topic_name,
                 topic_type,
                 data,
+                format,
                 Duration::from_secs_f32(1. / frequency_hz),
                 repetitions,
             )
@@ -27,9 +36,60 @@ pub async fn topic_cmd(node: Node, command: TopicCommands) -> anyhow::Result<()>
     }
 }
 
-#[allow(clippy::unused_async)]
-async fn topic_list() -> anyhow::Result<()> {
-    unimplemented!();
+async fn topic_list(node: &Node, tree: bool) -> anyhow::Result<()> {
+    let topics: BTreeSet<String> = node
+        .graph()
+        .await?
+        .publishers
+        .into_iter()
+        .map(|publisher| publisher.topic)
+        .collect();
+    if tree {
+        print!("{}", to_tree(&topics));
+    } else {
+        for topic in &topics {
+            println!("{topic}");
+        }
+    }
+    Ok(())
+}
+
+/// A level of the topic hierarchy, holding the next segment of every topic under it.
+#[derive(Default)]
+struct TopicTree {
+    children: BTreeMap<String, TopicTree>,
+}
+
+/// Renders topics as an indented tree, with one line per `/`-delimited segment. Topics sharing a
+/// prefix are grouped under it.
+fn to_tree(topics: &BTreeSet<String>) -> String {
+    let mut root = TopicTree::default();
+    for topic in topics {
+        let mut level = &mut root;
+        for segment in topic.split('/') {
+            level = level.children.entry(segment.into()).or_default();
+        }
+    }
+    let mut out = String::new();
+    write_tree(&root, 0, &mut out);
+    out
+}
+
+fn write_tree(tree: &TopicTree, depth: usize, out: &mut String) {
+    for (segment, subtree) in &tree.children {
+        let suffix = if subtree.children.is_empty() { "" } else { "/" };
+        let _ = writeln!(out, "{:indent$}{segment}{suffix}", "", indent = depth * 2);
+        write_tree(subtree, depth + 1, out);
+    }
+}
+
+fn topic_template(node: &Node, type_url: &str, format: DataFormat) -> anyhow::Result<()> {
+    let template = node.message_template(type_url)?;
+    match format {
+        DataFormat::Json => println!("{}", serde_json::to_string_pretty(&template)?),
+        DataFormat::Yaml => print!("{}", serde_yaml::to_string(&template)?),
+    }
+    Ok(())
 }
 
 async fn topic_sub(node: Node, name: String) -> anyhow::Result<()> {
@@ -45,11 +105,21 @@ async fn topic_pub(
     topic: String,
     type_url: String,
     data: String,
+    format: DataFormat,
     period: Duration,
     repetitions: Option<usize>,
 ) -> anyhow::Result<()> {
+    let json_value: serde_json::Value = match format {
+        DataFormat::Json => serde_json::from_str(&data)?,
+        DataFormat::Yaml => serde_yaml::from_str(&data)?,
+    };
+    if repetitions == Some(1) {
+        println!("Sending {json_value}...");
+        node.publish_json_once(topic, type_url, json_value).await?;
+        return Ok(());
+    }
+
     let publisher = node.publish_untyped(topic, type_url).await?;
-    let json_value: serde_json::Value = serde_json::from_str(&data)?;
     let repetition_str = if let Some(repetitions) = &repetitions {
         format!(" {repetitions} time(s)")
     } else {
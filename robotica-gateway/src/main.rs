@@ -0,0 +1,213 @@
+use clap::Parser;
+use futures::{SinkExt, StreamExt};
+use protocol::{ClientMessage, ServerMessage};
+use robotica::{Error, LogConfig, Node, UntypedPublisher, UntypedSubscriber};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, error::TrySendError},
+    task::JoinHandle,
+};
+use tokio_tungstenite::tungstenite::Message;
+
+mod protocol;
+
+/// How many messages can be queued for a client before new ones are dropped.
+const OUTGOING_QUEUE_SIZE: usize = 256;
+/// How long to wait before receiving again after a subscriber fails, doubled on every consecutive
+/// failure up to [`MAX_RECV_BACKOFF`].
+const MIN_RECV_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_RECV_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Exposes robotica topics to WebSocket clients, such as browser dashboards, as JSON.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    /// Address to listen for WebSocket connections on
+    #[arg(long, short, default_value = "127.0.0.1:9090")]
+    bind: SocketAddr,
+    #[arg(long, short)]
+    file_descriptors_paths: Vec<PathBuf>,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Cli::parse();
+    let mut node = Node::new_with_logging("gateway", LogConfig::new()).await?;
+    for path in args.file_descriptors_paths {
+        node.add_file_descriptors(&std::fs::read(path)?);
+    }
+    let node = Arc::new(node);
+
+    let listener = TcpListener::bind(args.bind).await?;
+    println!("Listening for WebSocket connections on {}...", args.bind);
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let node = node.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(&node, stream).await {
+                eprintln!("Connection with {addr} failed: {e}");
+            }
+        });
+    }
+}
+
+/// Serves a single WebSocket client until it disconnects.
+async fn handle_connection(node: &Node, stream: TcpStream) -> anyhow::Result<()> {
+    let (mut sink, mut frames) = tokio_tungstenite::accept_async(stream).await?.split();
+    let (outgoing_tx, mut outgoing_rx) = mpsc::channel(OUTGOING_QUEUE_SIZE);
+    let mut connection = Connection {
+        node,
+        outgoing_tx,
+        subscriptions: HashMap::new(),
+        publishers: HashMap::new(),
+    };
+
+    // Requests are handled concurrently with sending messages, so a slow request doesn't hold up
+    // delivery to the client
+    let send_messages = async {
+        while let Some(msg) = outgoing_rx.recv().await {
+            let text = serde_json::to_string(&msg)?;
+            sink.send(Message::Text(text)).await?;
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+    let handle_requests = async {
+        loop {
+            match frames.next().await {
+                Some(Ok(Message::Text(text))) => connection.handle_request(&text).await,
+                Some(Ok(Message::Close(_))) | None => return Ok(()),
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(anyhow::Error::from(e)),
+            }
+        }
+    };
+    let result = tokio::select! {
+        result = send_messages => result,
+        result = handle_requests => result,
+    };
+    for task in connection.subscriptions.into_values() {
+        task.abort();
+    }
+    result
+}
+
+/// The state of a single client connection.
+struct Connection<'a> {
+    node: &'a Node,
+    outgoing_tx: mpsc::Sender<ServerMessage>,
+    /// Tasks forwarding messages to the client, by topic
+    subscriptions: HashMap<String, JoinHandle<()>>,
+    /// Publishers used for messages from the client, by topic, along with their type URL
+    publishers: HashMap<String, (String, UntypedPublisher<'a>)>,
+}
+
+impl<'a> Connection<'a> {
+    /// Handles a request from the client, sending an error back if it fails.
+    async fn handle_request(&mut self, text: &str) {
+        let result = match serde_json::from_str(text) {
+            Ok(request) => self.handle_message(request).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            self.send_error(e.to_string());
+        }
+    }
+
+    async fn handle_message(&mut self, request: ClientMessage) -> anyhow::Result<()> {
+        match request {
+            ClientMessage::Subscribe { topic } => {
+                if !self.subscriptions.contains_key(&topic) {
+                    let subscriber = self.node.subscribe_untyped(&topic).await?;
+                    let task =
+                        tokio::spawn(forward_to_client(subscriber, self.outgoing_tx.clone()));
+                    self.subscriptions.insert(topic, task);
+                }
+            }
+            ClientMessage::Unsubscribe { topic } => {
+                if let Some(task) = self.subscriptions.remove(&topic) {
+                    task.abort();
+                }
+            }
+            ClientMessage::Publish {
+                topic,
+                type_url,
+                message,
+            } => {
+                let reuse = self
+                    .publishers
+                    .get(&topic)
+                    .is_some_and(|(publisher_type_url, _)| *publisher_type_url == type_url);
+                if !reuse {
+                    let publisher = self.node.publish_untyped(&topic, &type_url).await?;
+                    self.publishers.insert(topic.clone(), (type_url, publisher));
+                }
+                let (_, publisher) = &self.publishers[&topic];
+                publisher.send(message).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn send_error(&self, error: String) {
+        // This only fails if the connection is closing, in which case there's no one to tell, or
+        // if the client is too slow to keep up, in which case it misses the error like any message
+        let _ = self.outgoing_tx.try_send(ServerMessage::Error { error });
+    }
+}
+
+/// Sends every message received by the subscriber to the client, until the client disconnects.
+/// Messages that fail to be received or serialized are reported to the client and skipped, backing
+/// off while receiving keeps failing. Messages are dropped while the client's queue is full, so a
+/// slow client only misses messages instead of making the gateway buffer them.
+async fn forward_to_client(
+    mut subscriber: UntypedSubscriber,
+    outgoing_tx: mpsc::Sender<ServerMessage>,
+) {
+    let mut backoff = MIN_RECV_BACKOFF;
+    loop {
+        let msg = match subscriber.recv().await {
+            Ok(msg) => {
+                backoff = MIN_RECV_BACKOFF;
+                match serde_json::to_value(&msg.message) {
+                    Ok(message) => ServerMessage::Message {
+                        topic: subscriber.topic().into(),
+                        type_url: msg.header.type_url,
+                        timestamp: msg
+                            .header
+                            .message_timestamp
+                            .and_then(|timestamp| SystemTime::try_from(timestamp).ok())
+                            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                            .map(|since_epoch| since_epoch.as_secs_f64()),
+                        message,
+                    },
+                    Err(e) => ServerMessage::Error {
+                        error: format!("failed to serialize message: {e}"),
+                    },
+                }
+            }
+            // The subscription only closes along with the node, so there's nothing left to forward
+            Err(e) if matches!(e.root(), Error::Flume(_)) => return,
+            Err(e) => {
+                let msg = ServerMessage::Error {
+                    error: format!("failed to receive on \"{}\": {e}", subscriber.topic()),
+                };
+                if let Err(TrySendError::Closed(_)) = outgoing_tx.try_send(msg) {
+                    return;
+                }
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_RECV_BACKOFF);
+                continue;
+            }
+        };
+        if let Err(TrySendError::Closed(_)) = outgoing_tx.try_send(msg) {
+            return;
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Requests sent by clients to the gateway, as JSON text frames.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// Start receiving the messages published on a topic
+    Subscribe { topic: String },
+    /// Stop receiving the messages published on a topic
+    Unsubscribe { topic: String },
+    /// Publish a message on a topic. The message must follow the protobuf JSON mapping for the
+    /// given type.
+    Publish {
+        topic: String,
+        type_url: String,
+        message: Value,
+    },
+}
+
+/// Messages sent by the gateway to clients, as JSON text frames.
+#[derive(Serialize, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// A message received on a topic the client subscribed to
+    Message {
+        topic: String,
+        type_url: String,
+        /// When the message was sent, in seconds since the UNIX epoch
+        timestamp: Option<f64>,
+        message: Value,
+    },
+    /// A request from the client failed, or a subscription stopped
+    Error { error: String },
+}
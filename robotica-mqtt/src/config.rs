@@ -0,0 +1,51 @@
+use serde::Deserialize;
+
+/// The configuration of the bridge, read from a JSON file.
+#[derive(Deserialize, Debug)]
+pub struct Config {
+    /// How to connect to the MQTT broker
+    pub mqtt: MqttConfig,
+    /// Robotica topics whose messages get republished to MQTT
+    #[serde(default)]
+    pub to_mqtt: Vec<ToMqtt>,
+    /// MQTT topics whose messages get republished to robotica
+    #[serde(default)]
+    pub from_mqtt: Vec<FromMqtt>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MqttConfig {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default = "default_client_id")]
+    pub client_id: String,
+}
+
+/// Republishes every message received on a robotica topic to an MQTT topic, serialized as JSON.
+/// The type of the robotica topic is detected from the messages themselves.
+#[derive(Deserialize, Debug)]
+pub struct ToMqtt {
+    pub topic: String,
+    pub mqtt_topic: String,
+    /// Whether the MQTT broker should retain the latest message for new subscribers
+    #[serde(default)]
+    pub retain: bool,
+}
+
+/// Republishes every JSON message received on an MQTT topic to a robotica topic, as the given
+/// type. MQTT wildcards are not supported, since each MQTT topic maps to a single robotica one.
+#[derive(Deserialize, Debug)]
+pub struct FromMqtt {
+    pub mqtt_topic: String,
+    pub topic: String,
+    pub type_url: String,
+}
+
+fn default_port() -> u16 {
+    1883
+}
+
+fn default_client_id() -> String {
+    "robotica-mqtt".into()
+}
@@ -0,0 +1,155 @@
+use clap::Parser;
+use config::Config;
+use robotica::{Error, LogConfig, Node, UntypedSubscriber};
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Packet, Publish, QoS};
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+use tokio::sync::mpsc;
+
+mod config;
+
+/// How many requests can be queued for the MQTT client before publishing waits for them to go out,
+/// and how many messages received from MQTT can be queued before being published.
+const MQTT_QUEUE_SIZE: usize = 64;
+/// How long to wait before polling the MQTT connection again after it fails.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Bridges messages between robotica topics and an MQTT broker, as JSON.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    /// Path to the JSON config file describing the broker and which topics to bridge
+    config: PathBuf,
+    #[arg(long, short)]
+    file_descriptors_paths: Vec<PathBuf>,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Cli::parse();
+    let config: Config = serde_json::from_slice(&std::fs::read(&args.config)?)?;
+    let mut node = Node::new_with_logging("mqtt_bridge", LogConfig::new()).await?;
+    for path in args.file_descriptors_paths {
+        node.add_file_descriptors(&std::fs::read(path)?);
+    }
+
+    let mut options = MqttOptions::new(&config.mqtt.client_id, &config.mqtt.host, config.mqtt.port);
+    options.set_keep_alive(Duration::from_secs(5));
+    let (client, event_loop) = AsyncClient::new(options, MQTT_QUEUE_SIZE);
+
+    // The event loop must be polling before anything is sent through the client, or requests
+    // beyond the queue size wait forever
+    let mqtt_topics = config
+        .from_mqtt
+        .iter()
+        .map(|bridge| bridge.mqtt_topic.clone())
+        .collect();
+    let (publish_sender, mut publish_receiver) = mpsc::channel(MQTT_QUEUE_SIZE);
+    tokio::spawn(poll_mqtt(
+        event_loop,
+        client.clone(),
+        mqtt_topics,
+        publish_sender,
+    ));
+
+    for bridge in config.to_mqtt {
+        let subscriber = node.subscribe_untyped(&bridge.topic).await?;
+        let client = client.clone();
+        tokio::spawn(async move {
+            if let Err(e) =
+                forward_to_mqtt(subscriber, &client, &bridge.mqtt_topic, bridge.retain).await
+            {
+                eprintln!("Stopped forwarding \"{}\" to MQTT: {e}", bridge.topic);
+            }
+        });
+    }
+
+    let mut publishers = HashMap::new();
+    for bridge in &config.from_mqtt {
+        let publisher = node
+            .publish_untyped(&bridge.topic, &bridge.type_url)
+            .await?;
+        publishers.insert(bridge.mqtt_topic.as_str(), publisher);
+    }
+
+    while let Some(publish) = publish_receiver.recv().await {
+        let Some(publisher) = publishers.get(publish.topic.as_str()) else {
+            continue;
+        };
+        let result = match serde_json::from_slice(&publish.payload) {
+            Ok(json_value) => publisher
+                .send(json_value)
+                .await
+                .map_err(anyhow::Error::from),
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            eprintln!(
+                "Failed to forward message from MQTT topic \"{}\": {e}",
+                publish.topic
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Polls the MQTT connection, passing every message received to `publish_sender`. The topics to
+/// bridge from MQTT are subscribed to whenever the broker accepts a connection without a stored
+/// session, so subscriptions survive reconnections.
+async fn poll_mqtt(
+    mut event_loop: EventLoop,
+    client: AsyncClient,
+    mqtt_topics: Vec<String>,
+    publish_sender: mpsc::Sender<Publish>,
+) {
+    loop {
+        let event = match event_loop.poll().await {
+            Ok(event) => event,
+            Err(e) => {
+                // The event loop reconnects on the next poll, so we just wait a bit before that
+                eprintln!("MQTT connection error: {e}");
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        match event {
+            Event::Incoming(Packet::ConnAck(conn_ack)) if !conn_ack.session_present => {
+                // We can't wait on the client here, since this loop is what drains its requests
+                for mqtt_topic in &mqtt_topics {
+                    if let Err(e) = client.try_subscribe(mqtt_topic, QoS::AtLeastOnce) {
+                        eprintln!("Failed to subscribe to MQTT topic \"{mqtt_topic}\": {e}");
+                    }
+                }
+            }
+            Event::Incoming(Packet::Publish(publish)) => {
+                if publish_sender.send(publish).await.is_err() {
+                    return;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Republishes every message received by the subscriber to an MQTT topic, serialized as JSON.
+async fn forward_to_mqtt(
+    mut subscriber: UntypedSubscriber,
+    client: &AsyncClient,
+    mqtt_topic: &str,
+    retain: bool,
+) -> anyhow::Result<()> {
+    loop {
+        let msg = match subscriber.recv().await {
+            Ok(msg) => msg,
+            // The subscription only closes along with the node
+            Err(e) if matches!(e.root(), Error::Flume(_)) => return Err(e.into()),
+            Err(e) => {
+                eprintln!("Failed to receive message to forward to MQTT: {e}");
+                continue;
+            }
+        };
+        let payload = serde_json::to_vec(&msg.message)?;
+        client
+            .publish(mqtt_topic, QoS::AtLeastOnce, retain, payload)
+            .await?;
+    }
+}
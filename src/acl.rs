@@ -0,0 +1,129 @@
+use crate::{Error, Result};
+use zenoh::key_expr::{keyexpr, OwnedKeyExpr};
+
+/// The topics a node is allowed to publish or subscribe to. Patterns are zenoh key expressions,
+/// so `*` matches a single chunk of a topic and `**` matches any number of chunks.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TopicAcl {
+    allow: Option<Vec<OwnedKeyExpr>>,
+    deny: Vec<OwnedKeyExpr>,
+}
+
+impl TopicAcl {
+    /// Creates an access control list from the given patterns. If `allow` is `None`, all topics
+    /// not denied are allowed.
+    pub(crate) fn new(allow: Option<&[String]>, deny: &[String]) -> Result<TopicAcl> {
+        Ok(TopicAcl {
+            allow: allow.map(parse_patterns).transpose()?,
+            deny: parse_patterns(deny)?,
+        })
+    }
+
+    /// Returns an error if the topic is not a valid topic name, if it is denied, or if there is an
+    /// allowlist and the topic isn't in it.
+    pub(crate) fn check(&self, topic: &str) -> Result<()> {
+        let key_expr = validate_topic(topic)?;
+        let allowed = self
+            .allow
+            .as_ref()
+            .map_or(true, |allow| allow.iter().any(|p| p.includes(key_expr)));
+        let denied = self.deny.iter().any(|p| p.includes(key_expr));
+        if allowed && !denied {
+            Ok(())
+        } else {
+            Err(Error::TopicNotPermitted(topic.into()))
+        }
+    }
+}
+
+/// Checks that a topic name is a valid zenoh key expression without wildcards, since a wildcard
+/// would silently turn a publisher or subscriber into one for a whole set of topics.
+fn validate_topic(topic: &str) -> Result<&keyexpr> {
+    let key_expr = keyexpr::new(topic).map_err(|e| Error::InvalidTopic {
+        topic: topic.into(),
+        reason: e.to_string(),
+    })?;
+    if key_expr.is_wild() {
+        return Err(Error::InvalidTopic {
+            topic: topic.into(),
+            reason: "topic names cannot contain wildcards ('*', '**' or '$*')".into(),
+        });
+    }
+    Ok(key_expr)
+}
+
+fn parse_patterns(patterns: &[String]) -> Result<Vec<OwnedKeyExpr>> {
+    Ok(patterns
+        .iter()
+        .map(|p| OwnedKeyExpr::new(p.as_str()))
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TopicAcl;
+    use crate::Error;
+
+    fn patterns(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn everything_is_allowed_by_default() {
+        let acl = TopicAcl::default();
+        assert!(acl.check("robot/camera").is_ok());
+    }
+
+    #[test]
+    fn allowlist_restricts_topics() {
+        let allow = patterns(&["robot/*", "sensors/**"]);
+        let acl = TopicAcl::new(Some(&allow), &[]).unwrap();
+        assert!(acl.check("robot/camera").is_ok());
+        assert!(acl.check("sensors/lidar/front").is_ok());
+        assert!(matches!(
+            acl.check("robot/camera/left"),
+            Err(Error::TopicNotPermitted(topic)) if topic == "robot/camera/left"
+        ));
+        assert!(acl.check("motors").is_err());
+    }
+
+    #[test]
+    fn deny_takes_precedence_over_allow() {
+        let allow = patterns(&["robot/**"]);
+        let deny = patterns(&["robot/motors/**"]);
+        let acl = TopicAcl::new(Some(&allow), &deny).unwrap();
+        assert!(acl.check("robot/camera").is_ok());
+        assert!(matches!(
+            acl.check("robot/motors/left"),
+            Err(Error::TopicNotPermitted(_))
+        ));
+
+        let acl = TopicAcl::new(None, &deny).unwrap();
+        assert!(acl.check("sensors/lidar").is_ok());
+        assert!(acl.check("robot/motors/left").is_err());
+    }
+
+    #[test]
+    fn wildcard_topics_are_rejected() {
+        let acl = TopicAcl::default();
+        for topic in ["robot/*", "robot/**", "robot/cam$*"] {
+            assert!(
+                matches!(acl.check(topic), Err(Error::InvalidTopic { topic: t, .. }) if t == topic),
+                "{topic}"
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_key_expressions_are_rejected() {
+        let acl = TopicAcl::default();
+        for topic in ["", "/robot", "robot/", "robot//camera", "robot?"] {
+            assert!(
+                matches!(acl.check(topic), Err(Error::InvalidTopic { .. })),
+                "{topic}"
+            );
+        }
+        assert!(TopicAcl::new(Some(&patterns(&["robot//camera"])), &[]).is_err());
+        assert!(TopicAcl::new(None, &patterns(&["/robot"])).is_err());
+    }
+}
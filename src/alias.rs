@@ -0,0 +1,90 @@
+use crate::Result;
+use std::sync::Arc;
+use tracing::warn;
+use zenoh::{prelude::r#async::*, subscriber::Subscriber};
+
+/// An alias between two topics, created with [`Node::alias_topic`](crate::Node::alias_topic).
+/// Messages published on one topic are republished as-is on the other, so the same data can be
+/// reached by both names. Aliasing stops when this is dropped.
+pub struct TopicAlias {
+    _forward: Subscriber<'static, ()>,
+    _backward: Option<Subscriber<'static, ()>>,
+}
+
+impl TopicAlias {
+    pub(crate) async fn new(
+        session: &Arc<Session>,
+        from: &str,
+        to: &str,
+        bidirectional: bool,
+    ) -> Result<TopicAlias> {
+        // When aliasing both ways, each republished message would be picked up again by the other
+        // direction, so we only forward messages published by other sessions
+        let origin = if bidirectional {
+            Locality::Remote
+        } else {
+            Locality::Any
+        };
+        let forward = republish(session, session, from, to, origin).await?;
+        let backward = if bidirectional {
+            Some(republish(session, session, to, from, origin).await?)
+        } else {
+            None
+        };
+        Ok(TopicAlias {
+            _forward: forward,
+            _backward: backward,
+        })
+    }
+}
+
+/// A relay from a topic of one node to a topic of another (or the same) node, created with
+/// [`Node::relay`](crate::Node::relay). Every message received on the source topic is republished
+/// verbatim on the target topic, header included, without being decoded. Relaying stops when this
+/// is dropped.
+pub struct TopicRelay {
+    _forward: Subscriber<'static, ()>,
+}
+
+impl TopicRelay {
+    pub(crate) async fn new(
+        source: &Arc<Session>,
+        target: &Arc<Session>,
+        from: &str,
+        to: &str,
+    ) -> Result<TopicRelay> {
+        Ok(TopicRelay {
+            _forward: republish(source, target, from, to, Locality::Any).await?,
+        })
+    }
+}
+
+/// Declares a subscriber on the source session that republishes every sample received on one topic
+/// to another on the target session. Attachments are republished along with the payload, since
+/// they may carry the header of the message.
+async fn republish(
+    source: &Arc<Session>,
+    target: &Arc<Session>,
+    from: &str,
+    to: &str,
+    origin: Locality,
+) -> Result<Subscriber<'static, ()>> {
+    let callback_session = target.clone();
+    let to = to.to_string();
+    Ok(source
+        .declare_subscriber(from)
+        .allowed_origin(origin)
+        .callback(move |sample| {
+            use zenoh::prelude::sync::SyncResolve;
+
+            let mut put = callback_session.put(&to, sample.value.clone());
+            if let Some(attachment) = sample.attachment() {
+                put = put.with_attachment(attachment.clone());
+            }
+            if let Err(e) = put.res_sync() {
+                warn!(msg = "alias_republish_failed", topic = to, error = %e);
+            }
+        })
+        .res()
+        .await?)
+}
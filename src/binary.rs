@@ -0,0 +1,159 @@
+use crate::{
+    clock::Clock,
+    publisher::{sample_value, PublisherOptions},
+    subscriber::{check_compression, read_header, PayloadBuf, ReceivedMessage},
+    subscription::{SharedSubscription, Subscriptions},
+    Result, ResultExt,
+};
+use bytes::Buf;
+use prost::Message;
+use prost_types::Timestamp;
+use robotica_types::Header;
+use std::sync::{atomic::AtomicBool, Arc};
+use tracing::{info, instrument};
+use zenoh::prelude::r#async::*;
+
+/// This struct represents a publisher of raw binary data to a topic, such as JPEG frames, instead
+/// of protobuf messages. Each payload is sent after a [`Header`] carrying its content type (usually
+/// a MIME type), with no protobuf framing. Note that you cannot create this struct directly, but
+/// must instead fetch one from a [`Node`](crate::Node).
+pub struct BytesPublisher<'a> {
+    publisher: zenoh::publication::Publisher<'a>,
+    content_type: String,
+    clock: Arc<dyn Clock>,
+    log_traffic: bool,
+}
+
+impl<'a> BytesPublisher<'a> {
+    pub(crate) async fn new_from_session<S: AsRef<str>, S2: AsRef<str>>(
+        session: &'a Session,
+        topic: S,
+        content_type: S2,
+        clock: Arc<dyn Clock>,
+        log_traffic: bool,
+    ) -> Result<BytesPublisher<'a>> {
+        let publisher = PublisherOptions::default()
+            .declare(session, topic.as_ref())
+            .await?;
+        Ok(BytesPublisher {
+            publisher,
+            content_type: content_type.as_ref().into(),
+            clock,
+            log_traffic,
+        })
+    }
+
+    /// Returns the zenoh key expression this publisher sends data on. See
+    /// [`Publisher::key_expr`](crate::Publisher::key_expr).
+    #[must_use]
+    pub fn key_expr(&self) -> &str {
+        self.publisher.key_expr().as_str()
+    }
+
+    /// This function sends binary data to the topic we're publishing to. The data will be received
+    /// as-is by all subscribers to this topic created with
+    /// [`Node::subscribe_bytes`](crate::Node::subscribe_bytes).
+    ///
+    /// # Errors
+    /// This function will return an error if the data cannot be sent for any reason. In practice,
+    /// this means there was an error returned by zenoh when sending down the channel.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn send(&self, data: &[u8]) -> Result<()> {
+        self.put(data).await.context(self.key_expr(), "send")
+    }
+
+    /// Puts the data on the topic after its header, see [`BytesPublisher::send`].
+    async fn put(&self, data: &[u8]) -> Result<()> {
+        let header = Header {
+            message_timestamp: Some(Timestamp::from(self.clock.now())),
+            content_type: self.content_type.clone(),
+            ..Header::default()
+        };
+        let mut buf = header.encode_length_delimited_to_vec();
+        buf.extend_from_slice(data);
+        if self.log_traffic {
+            info!(
+                msg = "message_sent",
+                topic = self.key_expr(),
+                content_type = self.content_type,
+                bytes = buf.len(),
+            );
+        }
+        self.publisher.put(sample_value(buf)).res().await?;
+        Ok(())
+    }
+}
+
+/// This struct represents a subscriber to a topic of raw binary data, sent by a
+/// [`BytesPublisher`]. Note that you cannot create this struct directly, but must instead fetch
+/// one from a [`Node`](crate::Node).
+pub struct BytesSubscriber {
+    subscription: Arc<SharedSubscription>,
+    receiver: flume::Receiver<Sample>,
+    log_traffic: bool,
+}
+
+impl BytesSubscriber {
+    pub(crate) async fn new_from_session<S: AsRef<str>>(
+        session: &Arc<Session>,
+        subscriptions: &Subscriptions,
+        topic: S,
+        log_traffic: bool,
+    ) -> Result<BytesSubscriber> {
+        let (subscription, receiver) = subscriptions
+            .subscribe(
+                session,
+                topic.as_ref(),
+                None,
+                Arc::new(AtomicBool::new(false)),
+            )
+            .await?;
+        Ok(BytesSubscriber {
+            subscription,
+            receiver,
+            log_traffic,
+        })
+    }
+
+    /// Returns the zenoh key expression this subscriber receives data on. See
+    /// [`Subscriber::key_expr`](crate::Subscriber::key_expr).
+    #[must_use]
+    pub fn key_expr(&self) -> &str {
+        self.subscription.key_expr()
+    }
+
+    /// This function blocks until data is received on the topic we're subscribed to. The content
+    /// type it was sent with is in the `content_type` field of the header. Note that the data
+    /// following the header is returned as-is, so protobuf messages sent on the same topic are
+    /// returned still encoded.
+    ///
+    /// # Errors
+    /// This function will return an error if the data cannot be received for any reason. In
+    /// practice, this means either an error was returned by zenoh, or we failed to decode the
+    /// header.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn recv(&self) -> Result<ReceivedMessage<Vec<u8>>> {
+        self.next_data().await.context(self.key_expr(), "recv")
+    }
+
+    /// Waits for the next sample and splits it into its header and data, see
+    /// [`BytesSubscriber::recv`].
+    async fn next_data(&self) -> Result<ReceivedMessage<Vec<u8>>> {
+        let sample = self.receiver.recv_async().await?;
+        let mut buf = PayloadBuf::new(&sample.value.payload);
+        let header = read_header(&sample, &mut buf)?;
+        check_compression(&header)?;
+        if self.log_traffic {
+            info!(
+                msg = "message_received",
+                topic = self.key_expr(),
+                content_type = header.content_type,
+                bytes = sample.value.payload.len(),
+            );
+        }
+        Ok(ReceivedMessage {
+            header,
+            message: buf.copy_to_bytes(buf.remaining()).to_vec(),
+        })
+    }
+}
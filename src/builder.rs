@@ -0,0 +1,497 @@
+use crate::{
+    acl::TopicAcl,
+    configure_logging,
+    info::{node_descriptors_key, node_metadata_key, node_name_key},
+    last_will::{LastWillConfig, LastWillRegistration},
+    proto::{merge_file_descriptors, resolve_file_descriptors},
+    subscription::Subscriptions,
+    Error, LogConfig, Node, Result, SystemClock,
+};
+use prost::Message;
+use robotica_types::NodeMetadata;
+use std::{
+    sync::{Arc, Mutex, PoisonError, RwLock},
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+use zenoh::{config::EndPoint, prelude::r#async::*};
+
+/// This struct is used to configure a [`Node`] before creating it. Fetch one with
+/// [`Node::builder`], set any options you need, and call [`NodeBuilder::build`].
+#[allow(clippy::module_name_repetitions)]
+pub struct NodeBuilder {
+    node_name: String,
+    log_config: Option<LogConfig>,
+    publish_descriptors: bool,
+    name_collision: NameCollision,
+    descriptor_registry: Option<String>,
+    allowed_topics: Option<Vec<String>>,
+    denied_topics: Vec<String>,
+    last_will: Option<LastWillConfig>,
+    multicast_scouting: Option<bool>,
+    gossip_scouting: Option<bool>,
+    scouting_timeout: Option<Duration>,
+    connect_endpoints: Vec<String>,
+    descriptor_merge: DescriptorMerge,
+    default_descriptors: bool,
+}
+
+impl NodeBuilder {
+    pub(crate) fn new<S: AsRef<str>>(node_name: S) -> NodeBuilder {
+        NodeBuilder {
+            node_name: node_name.as_ref().into(),
+            log_config: None,
+            publish_descriptors: false,
+            name_collision: NameCollision::Allow,
+            descriptor_registry: None,
+            allowed_topics: None,
+            denied_topics: Vec::new(),
+            last_will: None,
+            multicast_scouting: None,
+            gossip_scouting: None,
+            scouting_timeout: None,
+            connect_endpoints: Vec::new(),
+            descriptor_merge: DescriptorMerge::FirstWins,
+            default_descriptors: true,
+        }
+    }
+
+    /// Sets up logging with the given config when the node is built. See
+    /// [`Node::new_with_logging`] for details on how logging gets initialized.
+    #[must_use]
+    pub fn logging(mut self, log_config: LogConfig) -> NodeBuilder {
+        self.log_config = Some(log_config);
+        self
+    }
+
+    /// Sets whether the node should answer queries for its file descriptors. When enabled, the
+    /// node declares a queryable that replies with all its file descriptor sets merged into one,
+    /// so tools can decode messages without having the descriptors locally. Disabled by default.
+    #[must_use]
+    pub fn publish_descriptors(mut self, publish_descriptors: bool) -> NodeBuilder {
+        self.publish_descriptors = publish_descriptors;
+        self
+    }
+
+    /// Sets what to do if another node with the same name is already running when the node is
+    /// built. Checking requires querying the network for the name, which delays node creation, so
+    /// collisions are allowed by default. See [`NameCollision`] for the available options.
+    #[must_use]
+    pub fn name_collision(mut self, name_collision: NameCollision) -> NodeBuilder {
+        self.name_collision = name_collision;
+        self
+    }
+
+    /// Fetches file descriptors from a registry when the node is built, as if they were passed to
+    /// [`Node::add_file_descriptors`]. The registry is any zenoh queryable that replies with a
+    /// serialized `FileDescriptorSet` on the given key expression, such as another node built with
+    /// [`NodeBuilder::publish_descriptors`]. If several registries reply, all their descriptors
+    /// are added.
+    #[must_use]
+    pub fn descriptor_registry<S: AsRef<str>>(mut self, key_expr: S) -> NodeBuilder {
+        self.descriptor_registry = Some(key_expr.as_ref().into());
+        self
+    }
+
+    /// Sets how the node resolves a type defined by more than one of its file descriptor sets,
+    /// such as when descriptors from a registry disagree with the ones added locally. By default,
+    /// the first set that defines the type wins. See [`DescriptorMerge`] for the available
+    /// options.
+    #[must_use]
+    pub fn descriptor_merge(mut self, descriptor_merge: DescriptorMerge) -> NodeBuilder {
+        self.descriptor_merge = descriptor_merge;
+        self
+    }
+
+    /// Only allows the node to publish or subscribe to topics matching any of the given patterns.
+    /// Patterns are zenoh key expressions, so `*` matches a single chunk of a topic (e.g.
+    /// `robot/*/odom`) and `**` matches any number of them (e.g. `robot/**`). Calling this again
+    /// adds to the allowed patterns. By default, all topics are allowed.
+    ///
+    /// This is a guardrail against accidental cross-talk between subsystems, not a security
+    /// boundary. Use zenoh's access control for that.
+    #[must_use]
+    pub fn allow_topics<I: IntoIterator<Item = S>, S: AsRef<str>>(
+        mut self,
+        patterns: I,
+    ) -> NodeBuilder {
+        self.allowed_topics
+            .get_or_insert_with(Vec::new)
+            .extend(patterns.into_iter().map(|p| p.as_ref().to_string()));
+        self
+    }
+
+    /// Prevents the node from publishing or subscribing to topics matching any of the given
+    /// patterns, even if they are allowed by [`NodeBuilder::allow_topics`]. Patterns follow the
+    /// same syntax. Calling this again adds to the denied patterns.
+    #[must_use]
+    pub fn deny_topics<I: IntoIterator<Item = S>, S: AsRef<str>>(
+        mut self,
+        patterns: I,
+    ) -> NodeBuilder {
+        self.denied_topics
+            .extend(patterns.into_iter().map(|p| p.as_ref().to_string()));
+        self
+    }
+
+    /// Sets a message to publish on a topic when the node dies, like MQTT's last will. The message
+    /// is given as JSON, following the same mapping as [`UntypedPublisher::send`], and is published
+    /// by any node running a [`LastWillMonitor`] once this node's zenoh liveliness token goes
+    /// away, either because the node was dropped or because it stopped responding. This is useful
+    /// to report nodes going offline on a status topic.
+    ///
+    /// [`UntypedPublisher::send`]: crate::UntypedPublisher::send
+    /// [`LastWillMonitor`]: crate::LastWillMonitor
+    #[must_use]
+    pub fn last_will<S: AsRef<str>, S2: AsRef<str>>(
+        mut self,
+        topic: S,
+        type_url: S2,
+        json_value: serde_json::Value,
+    ) -> NodeBuilder {
+        self.last_will = Some(LastWillConfig {
+            topic: topic.as_ref().into(),
+            type_url: type_url.as_ref().into(),
+            json_value,
+        });
+        self
+    }
+
+    /// Builds the node without the file descriptors of robotica's built-in types, which are
+    /// otherwise always included. Untyped publishers and subscribers, and other reflection
+    /// functions, then only know about the types added with [`Node::add_file_descriptors`] or
+    /// [`NodeBuilder::descriptor_registry`]. This keeps robotica's types from clashing with
+    /// application types of the same name, and from being published along with the node's
+    /// descriptors. Typed publishers and subscribers of built-in types keep working.
+    #[must_use]
+    pub fn without_default_descriptors(mut self) -> NodeBuilder {
+        self.default_descriptors = false;
+        self
+    }
+
+    /// Sets whether the node discovers other nodes by multicast scouting. Zenoh enables it by
+    /// default, but multicast is often blocked on locked-down networks, in which case you'll want
+    /// to disable it and use [`NodeBuilder::connect`] or gossip scouting instead.
+    #[must_use]
+    pub fn multicast_scouting(mut self, enabled: bool) -> NodeBuilder {
+        self.multicast_scouting = Some(enabled);
+        self
+    }
+
+    /// Sets whether the node discovers other nodes through the ones it's already connected to,
+    /// which works without multicast. Zenoh enables it by default.
+    #[must_use]
+    pub fn gossip_scouting(mut self, enabled: bool) -> NodeBuilder {
+        self.gossip_scouting = Some(enabled);
+        self
+    }
+
+    /// Sets how long the node waits for scouting to find other nodes when it's built. Zenoh waits
+    /// up to 3 seconds by default.
+    #[must_use]
+    pub fn scouting_timeout(mut self, timeout: Duration) -> NodeBuilder {
+        self.scouting_timeout = Some(timeout);
+        self
+    }
+
+    /// Connects the node to the given zenoh endpoints when it's built, such as
+    /// `tcp/192.168.1.10:7447` for a router or another node. This lets nodes find each other
+    /// without scouting. Calling this again adds to the endpoints.
+    #[must_use]
+    pub fn connect<I: IntoIterator<Item = S>, S: AsRef<str>>(
+        mut self,
+        endpoints: I,
+    ) -> NodeBuilder {
+        self.connect_endpoints
+            .extend(endpoints.into_iter().map(|e| e.as_ref().to_string()));
+        self
+    }
+
+    /// Creates the node with the configured options.
+    ///
+    /// # Errors
+    /// This function will return an error if the node name is empty or contains `/` or zenoh
+    /// wildcard characters, if any of the allowed or denied topic patterns is not a valid key
+    /// expression, if any of the connect endpoints or scouting options is invalid, if the zenoh
+    /// session cannot be created, if any of the node's queryables cannot be declared, or if the
+    /// node name is already taken and the builder was configured with [`NameCollision::Error`].
+    /// If a descriptor registry was set, this also returns an error if no registry replies, or if
+    /// any of them replies with an error. If a last will was set, this also returns an error if
+    /// its message cannot be encoded.
+    pub async fn build(mut self) -> Result<Node> {
+        validate_node_name(&self.node_name)?;
+        let topic_acl = TopicAcl::new(self.allowed_topics.as_deref(), &self.denied_topics)?;
+        if let Some(log_config) = &self.log_config {
+            configure_logging(log_config);
+        }
+        let zenoh_session = zenoh::open(self.zenoh_config()?).res().await?.into_arc();
+        if self.name_collision != NameCollision::Allow
+            && node_name_taken(&zenoh_session, &self.node_name).await?
+        {
+            if self.name_collision == NameCollision::Error {
+                return Err(Error::NodeNameTaken(self.node_name));
+            }
+            let unique_name = format!("{}_{}", self.node_name, zenoh_session.zid());
+            warn!(
+                msg = "node_name_taken",
+                name = self.node_name,
+                new_name = unique_name
+            );
+            self.node_name = unique_name;
+        }
+        // We default to use our own file descriptor
+        let mut file_descriptors = Vec::new();
+        if self.default_descriptors {
+            file_descriptors.push(robotica_types::DESCRIPTOR_SET_BYTES.to_vec());
+        }
+        if let Some(registry) = &self.descriptor_registry {
+            file_descriptors.extend(fetch_registry_descriptors(&zenoh_session, registry).await?);
+        }
+        let file_descriptor = Arc::new(RwLock::new(file_descriptors));
+
+        let descriptors_queryable = if self.publish_descriptors {
+            let callback_file_descriptor = file_descriptor.clone();
+            Some(
+                zenoh_session
+                    .declare_queryable(node_descriptors_key(&self.node_name))
+                    .callback(move |query| {
+                        use zenoh::prelude::sync::SyncResolve;
+
+                        let descriptors = merge_file_descriptors(
+                            &callback_file_descriptor
+                                .read()
+                                .unwrap_or_else(PoisonError::into_inner),
+                        );
+                        let reply = match descriptors {
+                            Ok(descriptors) => {
+                                Ok(Sample::new(query.key_expr().clone(), descriptors))
+                            }
+                            Err(e) => Err(e.to_string().into()),
+                        };
+                        if let Err(e) = query.reply(reply).res_sync() {
+                            warn!(msg = "descriptors_reply_failed", error = %e);
+                        }
+                    })
+                    .res()
+                    .await?,
+            )
+        } else {
+            None
+        };
+
+        let callback_node_name = self.node_name.clone();
+        let node_name_queryable = zenoh_session
+            .declare_queryable(node_name_key(&self.node_name))
+            .callback(move |query| {
+                use zenoh::prelude::sync::SyncResolve;
+
+                let sample = Sample::new(query.key_expr().clone(), callback_node_name.clone());
+                if let Err(e) = query.reply(Ok(sample)).res_sync() {
+                    warn!(msg = "node_name_reply_failed", error = %e);
+                }
+            })
+            .res()
+            .await?;
+
+        let started_at = Instant::now();
+        let callback_node_name = self.node_name.clone();
+        let metadata_queryable = zenoh_session
+            .declare_queryable(node_metadata_key(&self.node_name))
+            .callback(move |query| {
+                use zenoh::prelude::sync::SyncResolve;
+
+                let metadata = NodeMetadata {
+                    node_name: callback_node_name.clone(),
+                    uptime: prost_types::Duration::try_from(started_at.elapsed()).ok(),
+                    robotica_version: env!("CARGO_PKG_VERSION").into(),
+                    process_id: std::process::id(),
+                };
+                let sample = Sample::new(query.key_expr().clone(), metadata.encode_to_vec());
+                if let Err(e) = query.reply(Ok(sample)).res_sync() {
+                    warn!(msg = "node_metadata_reply_failed", error = %e);
+                }
+            })
+            .res()
+            .await?;
+
+        let last_will = match &self.last_will {
+            Some(config) => Some(
+                LastWillRegistration::new(
+                    &zenoh_session,
+                    &self.node_name,
+                    config,
+                    &resolve_file_descriptors(
+                        file_descriptor
+                            .read()
+                            .unwrap_or_else(PoisonError::into_inner)
+                            .clone(),
+                        self.descriptor_merge,
+                    )?,
+                )
+                .await?,
+            ),
+            None => None,
+        };
+
+        info!(msg = "node_created", name = self.node_name);
+        Ok(Node {
+            node_name: self.node_name,
+            zenoh_session,
+            file_descriptor,
+            registered_types: Mutex::default(),
+            descriptor_merge: self.descriptor_merge,
+            clock: Arc::new(SystemClock),
+            sim_time_subscriber: None,
+            _descriptors_queryable: descriptors_queryable,
+            _node_name_queryable: node_name_queryable,
+            _metadata_queryable: metadata_queryable,
+            _last_will: last_will,
+            subscriptions: Subscriptions::default(),
+            topic_acl,
+            log_traffic: self
+                .log_config
+                .as_ref()
+                .is_some_and(|log_config| log_config.log_traffic),
+        })
+    }
+
+    /// Returns the zenoh config for the node's session, with the configured scouting options and
+    /// endpoints.
+    fn zenoh_config(&self) -> Result<Config> {
+        let mut config = config::default();
+        if let Some(enabled) = self.multicast_scouting {
+            config
+                .scouting
+                .multicast
+                .set_enabled(Some(enabled))
+                .map_err(|_| invalid_config("scouting/multicast/enabled"))?;
+        }
+        if let Some(enabled) = self.gossip_scouting {
+            config
+                .scouting
+                .gossip
+                .set_enabled(Some(enabled))
+                .map_err(|_| invalid_config("scouting/gossip/enabled"))?;
+        }
+        if let Some(timeout) = self.scouting_timeout {
+            let timeout_ms = u64::try_from(timeout.as_millis()).unwrap_or(u64::MAX);
+            config
+                .scouting
+                .set_timeout(Some(timeout_ms))
+                .map_err(|_| invalid_config("scouting/timeout"))?;
+        }
+        for endpoint in &self.connect_endpoints {
+            let endpoint = endpoint
+                .parse::<EndPoint>()
+                .map_err(|e| Error::Zenoh(e.into()))?;
+            config.connect.endpoints.push(endpoint);
+        }
+        Ok(config)
+    }
+}
+
+/// How a node resolves a type defined by more than one of its file descriptor sets. See
+/// [`NodeBuilder::descriptor_merge`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DescriptorMerge {
+    /// Use the definition from the set added first, such as the node's built-in types before any
+    /// added with [`Node::add_file_descriptors`]
+    #[default]
+    FirstWins,
+    /// Use the definition from the set added last, so newly added descriptors override older ones
+    LastWins,
+    /// Merge all sets into a single one, failing with [`Error::ConflictingDescriptors`] (or a
+    /// descriptor error) if any file or type is defined differently by two of them
+    ErrorOnConflict,
+}
+
+/// What a [`NodeBuilder`] does when another node with the same name is already running.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NameCollision {
+    /// Create the node anyway, without checking for other nodes with the same name
+    #[default]
+    Allow,
+    /// Fail to build the node with [`Error::NodeNameTaken`]
+    Error,
+    /// Append the zenoh ID of the node's session to its name, which is unique across the system
+    AppendSuffix,
+}
+
+/// Returns the error for a value zenoh rejected when setting the given key of its config.
+fn invalid_config(key: &str) -> Error {
+    Error::Zenoh(format!("invalid value for zenoh config key \"{key}\"").into())
+}
+
+/// Checks that a node name can be used as a single chunk of the key expressions the node declares
+/// its queryables on, such as `robotica/node/{name}/descriptors`.
+fn validate_node_name(node_name: &str) -> Result<()> {
+    if node_name.is_empty() || node_name.contains(['/', '*', '$', '?', '#']) {
+        return Err(Error::InvalidNodeName(node_name.into()));
+    }
+    Ok(())
+}
+
+/// Returns whether any running node answers queries for the given name.
+async fn node_name_taken(session: &Session, node_name: &str) -> Result<bool> {
+    let replies = session.get(node_name_key(node_name)).res().await?;
+    Ok(replies.recv_async().await.is_ok())
+}
+
+/// Queries a descriptor registry, returning the file descriptor sets of every registry that
+/// replied.
+async fn fetch_registry_descriptors(session: &Session, registry: &str) -> Result<Vec<Vec<u8>>> {
+    let replies = session
+        .get(registry)
+        .consolidation(ConsolidationMode::None)
+        .res()
+        .await?;
+    let mut file_descriptors = Vec::new();
+    while let Ok(reply) = replies.recv_async().await {
+        let sample = reply
+            .sample
+            .map_err(|value| Error::QueryReply(value.to_string()))?;
+        file_descriptors.push(sample.value.payload.contiguous().to_vec());
+    }
+    if file_descriptors.is_empty() {
+        return Err(Error::DescriptorRegistryNotFound(registry.into()));
+    }
+    info!(
+        msg = "registry_descriptors_fetched",
+        registry = registry,
+        count = file_descriptors.len()
+    );
+    Ok(file_descriptors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::validate_node_name;
+    use crate::Error;
+
+    #[test]
+    fn valid_node_names_are_accepted() {
+        for node_name in ["camera", "camera_driver-2", "left.camera"] {
+            assert!(validate_node_name(node_name).is_ok(), "{node_name}");
+        }
+    }
+
+    #[test]
+    fn invalid_node_names_are_rejected() {
+        for node_name in [
+            "",
+            "robot/camera",
+            "camera*",
+            "camera$",
+            "camera?",
+            "camera#",
+        ] {
+            assert!(
+                matches!(
+                    validate_node_name(node_name),
+                    Err(Error::InvalidNodeName(name)) if name == node_name
+                ),
+                "{node_name}"
+            );
+        }
+    }
+}
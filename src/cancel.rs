@@ -0,0 +1,17 @@
+use crate::{Error, Result};
+use futures::future::{select, Either};
+use std::future::Future;
+use tokio_util::sync::CancellationToken;
+
+/// Runs a future until it completes or the token is cancelled, whichever happens first. If the
+/// token is cancelled, the future is dropped, which releases any zenoh query or receiver it holds,
+/// and [`Error::Cancelled`] is returned.
+pub(crate) async fn cancellable<T, F: Future<Output = Result<T>>>(
+    token: &CancellationToken,
+    future: F,
+) -> Result<T> {
+    match select(Box::pin(future), Box::pin(token.cancelled())).await {
+        Either::Left((result, _)) => result,
+        Either::Right(_) => Err(Error::Cancelled),
+    }
+}
@@ -0,0 +1,49 @@
+use std::{
+    sync::{Arc, PoisonError, RwLock},
+    time::SystemTime,
+};
+
+/// A source of time used to stamp the headers of outgoing messages. Implement this trait to
+/// drive timestamps from something other than the wall clock, such as a simulator or a recording
+/// being replayed.
+pub trait Clock: Send + Sync {
+    /// Returns the current time according to this clock.
+    fn now(&self) -> SystemTime;
+}
+
+/// The default clock used by every [`Node`](crate::Node), backed by the system wall clock.
+#[derive(Clone, Copy, Debug, Default)]
+#[allow(clippy::module_name_repetitions)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that follows the time published on a clock topic by a simulator. Until the first time
+/// is received, this clock reports the UNIX epoch.
+#[derive(Clone, Debug)]
+pub(crate) struct SimClock {
+    latest: Arc<RwLock<SystemTime>>,
+}
+
+impl SimClock {
+    pub(crate) fn new() -> SimClock {
+        SimClock {
+            latest: Arc::new(RwLock::new(SystemTime::UNIX_EPOCH)),
+        }
+    }
+
+    /// Updates the time reported by this clock.
+    pub(crate) fn set(&self, time: SystemTime) {
+        *self.latest.write().unwrap_or_else(PoisonError::into_inner) = time;
+    }
+}
+
+impl Clock for SimClock {
+    fn now(&self) -> SystemTime {
+        *self.latest.read().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,58 @@
+use crate::Result;
+use bytes::Buf;
+
+/// This trait encodes messages of type M for robotica, and identifies their type in message
+/// headers. Robotica only handles the header, topic and discovery machinery, so messages of any
+/// serialization format can be sent through it given a codec for them. Codecs used by subscribers
+/// also implement [`Decoder`]. Publishers and subscribers use [`ProstCodec`] unless told otherwise.
+pub trait Codec<M> {
+    /// Returns the type URL identifying messages of type M. Subscribers refuse messages whose
+    /// header carries a different type URL.
+    fn type_url() -> String;
+
+    /// Returns how many bytes the message takes once encoded.
+    fn encoded_len(message: &M) -> usize;
+
+    /// Encodes the message.
+    fn encode(message: &M) -> Vec<u8>;
+}
+
+/// This trait decodes messages of type M encoded by a [`Codec`]. It's separate from encoding, since
+/// decoding may need more from the message type, such as protobuf messages needing a default value
+/// to decode into.
+pub trait Decoder<M>: Codec<M> {
+    /// Decodes a message from the given buffer, which holds exactly one encoded message. The
+    /// buffer is a [`bytes::Buf`], re-exported as [`crate::bytes`], so messages split across
+    /// several zenoh slices are decoded without copying them.
+    ///
+    /// # Errors
+    /// This function should return an error if the data is not a valid message of type M. Codecs
+    /// for other formats than protobuf can use [`Error::Codec`](crate::Error::Codec).
+    fn decode<B: Buf>(buf: B) -> Result<M>;
+}
+
+/// The default codec, for protobuf messages generated by prost. Messages are identified by their
+/// fully qualified protobuf name.
+#[derive(Clone, Copy, Debug, Default)]
+#[allow(clippy::module_name_repetitions)]
+pub struct ProstCodec;
+
+impl<M: prost::Message + prost::Name> Codec<M> for ProstCodec {
+    fn type_url() -> String {
+        M::type_url()
+    }
+
+    fn encoded_len(message: &M) -> usize {
+        message.encoded_len()
+    }
+
+    fn encode(message: &M) -> Vec<u8> {
+        message.encode_to_vec()
+    }
+}
+
+impl<M: prost::Message + prost::Name + Default> Decoder<M> for ProstCodec {
+    fn decode<B: Buf>(buf: B) -> Result<M> {
+        Ok(M::decode(buf)?)
+    }
+}
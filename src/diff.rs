@@ -0,0 +1,222 @@
+use prost_reflect::{DynamicMessage, FieldDescriptor, MapKey, Value};
+use std::borrow::Cow;
+
+/// A field that differs between two messages, as found by [`message_diff`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldChange {
+    /// The path of the field, made of field names separated by dots going through nested messages,
+    /// with the index of list items or the key of map entries in brackets, like
+    /// `joints[2].position` or `labels["side"]`
+    pub path: String,
+    /// The value of the field in the old message, or `None` if it wasn't set
+    pub old: Option<Value>,
+    /// The value of the field in the new message, or `None` if it wasn't set
+    pub new: Option<Value>,
+}
+
+/// This function compares two messages through reflection and returns every field that differs
+/// between them, such as between two consecutive messages of a state topic. Nested messages, lists
+/// and maps are compared recursively, so only the innermost fields that changed are reported, in
+/// the order they're defined. If the messages are of different types, a single change with an
+/// empty path is returned, holding both messages whole.
+#[must_use]
+pub fn message_diff(old: &DynamicMessage, new: &DynamicMessage) -> Vec<FieldChange> {
+    let mut changes = Vec::new();
+    if old.descriptor() == new.descriptor() {
+        diff_messages(old, new, "", &mut changes);
+    } else {
+        changes.push(FieldChange {
+            path: String::new(),
+            old: Some(Value::Message(old.clone())),
+            new: Some(Value::Message(new.clone())),
+        });
+    }
+    changes
+}
+
+fn diff_messages(
+    old: &DynamicMessage,
+    new: &DynamicMessage,
+    path: &str,
+    changes: &mut Vec<FieldChange>,
+) {
+    for field in old.descriptor().fields() {
+        let field_path = if path.is_empty() {
+            field.name().to_string()
+        } else {
+            format!("{path}.{}", field.name())
+        };
+        diff_values(
+            field_value(old, &field).as_deref(),
+            field_value(new, &field).as_deref(),
+            &field_path,
+            changes,
+        );
+    }
+}
+
+/// Returns the value of a field, or `None` if it's not set. Fields without presence always have a
+/// value, which may be the default one.
+fn field_value<'a>(message: &'a DynamicMessage, field: &FieldDescriptor) -> Option<Cow<'a, Value>> {
+    if field.supports_presence() && !message.has_field(field) {
+        return None;
+    }
+    Some(message.get_field(field))
+}
+
+fn diff_values(
+    old: Option<&Value>,
+    new: Option<&Value>,
+    path: &str,
+    changes: &mut Vec<FieldChange>,
+) {
+    match (old, new) {
+        (Some(Value::Message(old)), Some(Value::Message(new))) => {
+            diff_messages(old, new, path, changes);
+        }
+        (Some(Value::List(old)), Some(Value::List(new))) => {
+            for i in 0..old.len().max(new.len()) {
+                diff_values(old.get(i), new.get(i), &format!("{path}[{i}]"), changes);
+            }
+        }
+        (Some(Value::Map(old)), Some(Value::Map(new))) => {
+            let mut keys: Vec<&MapKey> = old
+                .keys()
+                .chain(new.keys().filter(|key| !old.contains_key(*key)))
+                .collect();
+            keys.sort_by_key(|key| map_key_string(key));
+            for key in keys {
+                diff_values(
+                    old.get(key),
+                    new.get(key),
+                    &format!("{path}[{}]", map_key_string(key)),
+                    changes,
+                );
+            }
+        }
+        _ if old != new => changes.push(FieldChange {
+            path: path.into(),
+            old: old.cloned(),
+            new: new.cloned(),
+        }),
+        _ => {}
+    }
+}
+
+fn map_key_string(key: &MapKey) -> String {
+    match key {
+        MapKey::Bool(value) => value.to_string(),
+        MapKey::I32(value) => value.to_string(),
+        MapKey::I64(value) => value.to_string(),
+        MapKey::U32(value) => value.to_string(),
+        MapKey::U64(value) => value.to_string(),
+        MapKey::String(value) => format!("{value:?}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{message_diff, FieldChange};
+    use crate::test_util::{message, robot_pool};
+    use prost_reflect::{DynamicMessage, Value};
+
+    fn robot(text: &str) -> DynamicMessage {
+        message(&robot_pool(), "test.Robot", text)
+    }
+
+    fn paths(changes: &[FieldChange]) -> Vec<&str> {
+        changes.iter().map(|change| change.path.as_str()).collect()
+    }
+
+    #[test]
+    fn equal_messages_have_no_changes() {
+        let text = r#"name: "r1" base { position: 1 } gains { key: "p" value: 2 }"#;
+        assert!(message_diff(&robot(text), &robot(text)).is_empty());
+    }
+
+    #[test]
+    fn nested_messages_report_innermost_fields() {
+        let changes = message_diff(
+            &robot(r#"base { name: "base" position: 1 }"#),
+            &robot(r#"base { name: "base" position: 2 }"#),
+        );
+        assert_eq!(
+            changes,
+            [FieldChange {
+                path: "base.position".into(),
+                old: Some(Value::F64(1.)),
+                new: Some(Value::F64(2.)),
+            }]
+        );
+    }
+
+    #[test]
+    fn lists_of_different_lengths_report_missing_items() {
+        let changes = message_diff(
+            &robot(r#"joints { name: "a" } values: [1, 2]"#),
+            &robot(r#"joints { name: "a" } joints { name: "b" } values: [1]"#),
+        );
+        assert_eq!(paths(&changes), ["joints[1]", "values[1]"]);
+        assert!(changes[0].old.is_none());
+        assert!(matches!(changes[0].new, Some(Value::Message(_))));
+        assert_eq!(changes[1].old, Some(Value::F64(2.)));
+        assert_eq!(changes[1].new, None);
+    }
+
+    #[test]
+    fn maps_report_added_and_removed_keys() {
+        let changes = message_diff(
+            &robot(r#"gains { key: "p" value: 1 } gains { key: "i" value: 2 }"#),
+            &robot(r#"gains { key: "p" value: 1 } gains { key: "d" value: 3 }"#),
+        );
+        assert_eq!(paths(&changes), [r#"gains["d"]"#, r#"gains["i"]"#]);
+        assert_eq!(changes[0].old, None);
+        assert_eq!(changes[0].new, Some(Value::F64(3.)));
+        assert_eq!(changes[1].old, Some(Value::F64(2.)));
+        assert_eq!(changes[1].new, None);
+
+        let changes = message_diff(
+            &robot(r#"tools { key: "gripper" value { position: 1 } }"#),
+            &robot(r#"tools { key: "gripper" value { position: 2 } }"#),
+        );
+        assert_eq!(paths(&changes), [r#"tools["gripper"].position"#]);
+    }
+
+    #[test]
+    fn fields_without_presence_compare_their_default() {
+        let changes = message_diff(&robot(""), &robot(r#"name: "r1""#));
+        assert_eq!(
+            changes,
+            [FieldChange {
+                path: "name".into(),
+                old: Some(Value::String(String::new())),
+                new: Some(Value::String("r1".into())),
+            }]
+        );
+    }
+
+    #[test]
+    fn fields_with_presence_report_being_unset() {
+        let changes = message_diff(&robot(""), &robot("speed: 0 base {}"));
+        assert_eq!(paths(&changes), ["base", "speed"]);
+        assert_eq!(changes[0].old, None);
+        assert_eq!(changes[1].old, None);
+        assert_eq!(changes[1].new, Some(Value::F64(0.)));
+    }
+
+    #[test]
+    fn different_types_are_a_single_change() {
+        let pool = robot_pool();
+        let robot = message(&pool, "test.Robot", r#"name: "r1""#);
+        let joint = message(&pool, "test.Joint", r#"name: "r1""#);
+        let changes = message_diff(&robot, &joint);
+        assert_eq!(
+            changes,
+            [FieldChange {
+                path: String::new(),
+                old: Some(Value::Message(robot)),
+                new: Some(Value::Message(joint)),
+            }]
+        );
+    }
+}
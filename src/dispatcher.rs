@@ -0,0 +1,87 @@
+use crate::{
+    codec::ProstCodec,
+    subscriber::{decode_header, decode_sample, ReceivedMessage},
+    Result, UntypedSubscriber,
+};
+use prost_reflect::DynamicMessage;
+use std::collections::HashMap;
+use tracing::instrument;
+use zenoh::prelude::Sample;
+
+type TypedHandler = Box<dyn FnMut(&Sample) -> Result<()> + Send>;
+type DynamicHandler = Box<dyn FnMut(ReceivedMessage<DynamicMessage>) + Send>;
+
+/// This struct routes the messages of an untyped subscriber to handlers registered by type. Each
+/// message whose type URL has a handler is decoded into the concrete prost type of that handler,
+/// and any other message is decoded as a [`DynamicMessage`] and passed to the default handler, if
+/// there is one. This is useful for tools that handle a few known types specially, like an
+/// inspector that renders images but prints everything else.
+#[allow(clippy::module_name_repetitions)]
+pub struct TypedDispatcher {
+    subscriber: UntypedSubscriber,
+    handlers: HashMap<String, TypedHandler>,
+    default_handler: Option<DynamicHandler>,
+}
+
+impl TypedDispatcher {
+    /// Creates a dispatcher for the messages of the given subscriber, with no handlers.
+    #[must_use]
+    pub fn new(subscriber: UntypedSubscriber) -> TypedDispatcher {
+        TypedDispatcher {
+            subscriber,
+            handlers: HashMap::new(),
+            default_handler: None,
+        }
+    }
+
+    /// Registers a handler for messages of type M. Registering another handler for the same type
+    /// replaces the previous one.
+    #[must_use]
+    pub fn on<M, F>(mut self, mut handler: F) -> TypedDispatcher
+    where
+        M: prost::Message + prost::Name + Default,
+        F: FnMut(ReceivedMessage<M>) + Send + 'static,
+    {
+        self.handlers.insert(
+            M::type_url(),
+            Box::new(move |sample| {
+                handler(decode_sample::<M, ProstCodec>(sample)?);
+                Ok(())
+            }),
+        );
+        self
+    }
+
+    /// Registers the handler for messages of any type without a handler of their own. Without a
+    /// default handler, those messages are dropped.
+    #[must_use]
+    pub fn on_other<F>(mut self, handler: F) -> TypedDispatcher
+    where
+        F: FnMut(ReceivedMessage<DynamicMessage>) + Send + 'static,
+    {
+        self.default_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// This function blocks until a message is received on the subscriber, and passes it to the
+    /// handler for its type. Call this in a loop to keep handling messages.
+    ///
+    /// # Errors
+    /// This function will return an error if the message cannot be received or decoded. See
+    /// [`UntypedSubscriber::recv`] for details.
+    ///
+    /// # Panics
+    /// This function will only panic if a u64 cannot be converted to a usize on your system.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn dispatch(&mut self) -> Result<()> {
+        let sample = self.subscriber.next_sample().await?;
+        let type_url = decode_header(&sample)?.type_url;
+        if let Some(handler) = self.handlers.get_mut(&type_url) {
+            return handler(&sample);
+        }
+        if let Some(handler) = &mut self.default_handler {
+            handler(self.subscriber.decode_dynamic(&sample)?);
+        }
+        Ok(())
+    }
+}
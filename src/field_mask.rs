@@ -0,0 +1,132 @@
+use prost_reflect::{DynamicMessage, Value};
+use std::collections::HashMap;
+
+/// The fields to keep when projecting a message, as a tree of field names. See
+/// [`UntypedSubscriber::with_field_mask`](crate::UntypedSubscriber::with_field_mask).
+#[derive(Clone, Debug, Default)]
+pub(crate) struct FieldMask {
+    /// The fields to keep, along with the mask to apply to them, or `None` to keep them whole
+    fields: HashMap<String, Option<FieldMask>>,
+}
+
+impl FieldMask {
+    /// Creates a mask keeping the given fields, each given as a path of field names separated by
+    /// dots, going through nested messages.
+    pub(crate) fn new<I: IntoIterator<Item = S>, S: AsRef<str>>(paths: I) -> FieldMask {
+        let mut mask = FieldMask::default();
+        for path in paths {
+            mask.insert(&path.as_ref().split('.').collect::<Vec<_>>());
+        }
+        mask
+    }
+
+    fn insert(&mut self, names: &[&str]) {
+        let [name, rest @ ..] = names else {
+            return;
+        };
+        if rest.is_empty() {
+            self.fields.insert((*name).into(), None);
+            return;
+        }
+        // A field kept whole stays whole, even if some of its subfields are listed too
+        if let Some(mask) = self
+            .fields
+            .entry((*name).into())
+            .or_insert_with(|| Some(FieldMask::default()))
+        {
+            mask.insert(rest);
+        }
+    }
+
+    /// Clears every field of the message not in the mask. Nested messages, including those in
+    /// lists and maps, are projected with the mask of the field holding them.
+    pub(crate) fn apply(&self, message: &mut DynamicMessage) {
+        for field in message.descriptor().fields() {
+            match self.fields.get(field.name()) {
+                None => message.clear_field(&field),
+                Some(None) => {}
+                Some(Some(mask)) => {
+                    if message.has_field(&field) {
+                        mask.apply_to_value(message.get_field_mut(&field));
+                    }
+                }
+            }
+        }
+    }
+
+    fn apply_to_value(&self, value: &mut Value) {
+        match value {
+            Value::Message(message) => self.apply(message),
+            Value::List(items) => items.iter_mut().for_each(|item| self.apply_to_value(item)),
+            Value::Map(entries) => entries
+                .values_mut()
+                .for_each(|entry| self.apply_to_value(entry)),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FieldMask;
+    use crate::test_util::{message, robot_pool};
+    use prost_reflect::DynamicMessage;
+
+    const ROBOT: &str = r#"
+        name: "r1"
+        base { name: "base" position: 1 }
+        joints { name: "shoulder" position: 2 }
+        joints { name: "elbow" position: 3 }
+        gains { key: "p" value: 4 }
+        tools { key: "gripper" value { name: "gripper" position: 5 } }
+        values: [6, 7]
+    "#;
+
+    fn robot(text: &str) -> DynamicMessage {
+        message(&robot_pool(), "test.Robot", text)
+    }
+
+    fn masked(paths: &[&str]) -> DynamicMessage {
+        let mut message = robot(ROBOT);
+        FieldMask::new(paths).apply(&mut message);
+        message
+    }
+
+    #[test]
+    fn unlisted_fields_are_cleared() {
+        assert_eq!(
+            masked(&["name", "values"]),
+            robot(r#"name: "r1" values: [6, 7]"#)
+        );
+        assert_eq!(masked(&["unknown", "name"]), robot(r#"name: "r1""#));
+        assert_eq!(masked(&[]), robot(""));
+    }
+
+    #[test]
+    fn nested_fields_are_projected() {
+        assert_eq!(masked(&["base.position"]), robot("base { position: 1 }"));
+    }
+
+    #[test]
+    fn a_field_kept_whole_stays_whole() {
+        let expected = robot(r#"base { name: "base" position: 1 }"#);
+        assert_eq!(masked(&["base", "base.position"]), expected);
+        assert_eq!(masked(&["base.position", "base"]), expected);
+    }
+
+    #[test]
+    fn lists_are_projected_item_by_item() {
+        assert_eq!(
+            masked(&["joints.position"]),
+            robot("joints { position: 2 } joints { position: 3 }")
+        );
+    }
+
+    #[test]
+    fn maps_are_projected_value_by_value() {
+        assert_eq!(
+            masked(&["tools.position", "gains"]),
+            robot(r#"gains { key: "p" value: 4 } tools { key: "gripper" value { position: 5 } }"#)
+        );
+    }
+}
@@ -0,0 +1,337 @@
+use crate::{
+    proto::{parse_file_descriptors, search_file_descriptors},
+    subscriber::{check_compression, check_len, header_attachment, ReceivedMessage},
+    Result,
+};
+use prost::Message;
+use prost_reflect::{DescriptorPool, DynamicMessage};
+use robotica_types::Header;
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+    sync::Arc,
+    thread::{self, JoinHandle},
+};
+use tracing::warn;
+use zenoh::{prelude::r#async::*, subscriber::Subscriber};
+
+/// This struct appends every message received on a topic to a compact log file, created with
+/// [`Node::record_to_file`](crate::Node::record_to_file). Each record is the length of the message
+/// as a varint, followed by the message exactly as sent on the wire: its length-delimited header,
+/// then the length-delimited message. No schema is stored, so the file descriptors of the messages
+/// must be provided separately to read them back with a [`FileSource`]. Recording stops when this
+/// is dropped.
+pub struct FileSink {
+    // Declared first so that it's dropped, and no more records come in, before the writer stops
+    _subscriber: Subscriber<'static, ()>,
+    writer: RecordWriter,
+}
+
+impl FileSink {
+    pub(crate) async fn new(session: &Arc<Session>, topic: &str, path: &Path) -> Result<FileSink> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let writer = RecordWriter::new(file, topic.to_string())?;
+        let commands = writer.commands.clone();
+        let subscriber = session
+            .declare_subscriber(topic)
+            .callback(move |sample| {
+                // This only fails once the sink is dropped, when records are discarded anyway
+                let _ = commands.send(WriterCommand::Record(wire_bytes(&sample)));
+            })
+            .res()
+            .await?;
+        Ok(FileSink {
+            _subscriber: subscriber,
+            writer,
+        })
+    }
+
+    /// Writes the records received so far to the file. Records are otherwise written in batches,
+    /// and only flushed once the sink is dropped.
+    ///
+    /// # Errors
+    /// This function will return an error if the file cannot be written to.
+    pub fn flush(&self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// A request to the thread writing the records of a [`FileSink`].
+enum WriterCommand {
+    /// Appends a record to the file
+    Record(Vec<u8>),
+    /// Flushes the file, and sends back the result
+    Flush(flume::Sender<std::io::Result<()>>),
+    /// Flushes the file and stops the thread
+    Stop,
+}
+
+/// Writes records to a file on a thread of its own, so that zenoh callbacks never wait on the
+/// file. Records are written in the order they're sent, and the file is flushed when this is
+/// dropped.
+struct RecordWriter {
+    commands: flume::Sender<WriterCommand>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RecordWriter {
+    fn new(file: File, topic: String) -> std::io::Result<RecordWriter> {
+        let (commands, receiver) = flume::unbounded();
+        let thread = thread::Builder::new()
+            .name("robotica-file-sink".into())
+            .spawn(move || {
+                let mut writer = BufWriter::new(file);
+                for command in receiver {
+                    match command {
+                        WriterCommand::Record(record) => {
+                            if let Err(e) = write_record(&mut writer, &record) {
+                                warn!(msg = "file_sink_write_failed", topic = topic, error = %e);
+                            }
+                        }
+                        WriterCommand::Flush(result) => {
+                            let _ = result.send(writer.flush());
+                        }
+                        WriterCommand::Stop => break,
+                    }
+                }
+                if let Err(e) = writer.flush() {
+                    warn!(msg = "file_sink_write_failed", topic = topic, error = %e);
+                }
+            })?;
+        Ok(RecordWriter {
+            commands,
+            thread: Some(thread),
+        })
+    }
+
+    fn flush(&self) -> std::io::Result<()> {
+        let stopped = || std::io::Error::new(ErrorKind::BrokenPipe, "file sink writer stopped");
+        let (result, receiver) = flume::bounded(1);
+        self.commands
+            .send(WriterCommand::Flush(result))
+            .map_err(|_| stopped())?;
+        receiver.recv().map_err(|_| stopped())?
+    }
+}
+
+impl Drop for RecordWriter {
+    fn drop(&mut self) {
+        let _ = self.commands.send(WriterCommand::Stop);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Returns the payload of a sample as it's framed on the wire, with the header in front of the
+/// message. Samples carrying their header as an attachment are framed the same way.
+fn wire_bytes(sample: &Sample) -> Vec<u8> {
+    let payload = sample.value.payload.contiguous();
+    let Some(header_bytes) = header_attachment(sample) else {
+        return payload.to_vec();
+    };
+    let header = &header_bytes[..];
+    let mut buf = Vec::with_capacity(header.len() + payload.len() + 20);
+    let header_len = u64::try_from(header.len()).expect("usize should always fit in u64");
+    prost::encoding::encode_varint(header_len, &mut buf);
+    buf.extend_from_slice(header);
+    let payload_len = u64::try_from(payload.len()).expect("usize should always fit in u64");
+    prost::encoding::encode_varint(payload_len, &mut buf);
+    buf.extend_from_slice(&payload);
+    buf
+}
+
+fn write_record<W: Write>(writer: &mut W, record: &[u8]) -> std::io::Result<()> {
+    let mut len = Vec::with_capacity(prost::length_delimiter_len(record.len()));
+    let record_len = u64::try_from(record.len()).expect("usize should always fit in u64");
+    prost::encoding::encode_varint(record_len, &mut len);
+    writer.write_all(&len)?;
+    writer.write_all(record)
+}
+
+/// This struct reads back the messages in a log file written by a [`FileSink`], in the order they
+/// were recorded. Messages are decoded using the given file descriptors, since the file doesn't
+/// store them. To replay a recording, send the messages read with an
+/// [`UntypedPublisher`](crate::UntypedPublisher).
+pub struct FileSource {
+    reader: BufReader<File>,
+    file_descriptor_pools: Vec<DescriptorPool>,
+}
+
+impl FileSource {
+    /// Opens a log file for reading. File descriptors that fail to parse are logged and skipped,
+    /// so only their types are unavailable.
+    ///
+    /// # Errors
+    /// This function will return an error if the file cannot be opened.
+    pub fn open<P: AsRef<Path>>(path: P, file_descriptors_bytes: &[Vec<u8>]) -> Result<FileSource> {
+        Ok(FileSource {
+            reader: BufReader::new(File::open(path)?),
+            file_descriptor_pools: parse_file_descriptors(file_descriptors_bytes),
+        })
+    }
+
+    /// Returns the next record of the file still encoded, exactly as it was sent on the wire, or
+    /// `None` once the end of the file is reached.
+    ///
+    /// # Errors
+    /// This function will return an error if the file cannot be read, or ends in the middle of a
+    /// record.
+    pub fn next_record(&mut self) -> Result<Option<Vec<u8>>> {
+        let Some(len) = self.read_len()? else {
+            return Ok(None);
+        };
+        let len = usize::try_from(len)
+            .map_err(|_| std::io::Error::new(ErrorKind::InvalidData, "record too long"))?;
+        let mut record = vec![0; len];
+        self.reader.read_exact(&mut record)?;
+        Ok(Some(record))
+    }
+
+    /// Returns the next message of the file, decoded along with its header, or `None` once the
+    /// end of the file is reached.
+    ///
+    /// # Errors
+    /// This function will return an error if the file cannot be read, if the type of the message
+    /// is not in the file descriptors, or if the message cannot be decoded, including when the
+    /// record is shorter than the message length it holds.
+    pub fn next_message(&mut self) -> Result<Option<ReceivedMessage<DynamicMessage>>> {
+        let Some(record) = self.next_record()? else {
+            return Ok(None);
+        };
+        let mut buf = record.as_slice();
+        let header = Header::decode_length_delimited(&mut buf)?;
+        check_compression(&header)?;
+        let message_descriptor =
+            search_file_descriptors(&self.file_descriptor_pools, &header.type_url)?;
+        let len = prost::encoding::decode_length_delimiter(&mut buf)?;
+        check_len(len, buf.len())?;
+        let message = DynamicMessage::decode(message_descriptor, &buf[..len])?;
+        Ok(Some(ReceivedMessage { header, message }))
+    }
+
+    /// Reads the varint length of the next record, or returns `None` if the file ends before it.
+    fn read_len(&mut self) -> Result<Option<u64>> {
+        let mut len = 0;
+        for i in 0..10 {
+            let mut byte = [0];
+            if let Err(e) = self.reader.read_exact(&mut byte) {
+                return match e.kind() {
+                    ErrorKind::UnexpectedEof if i == 0 => Ok(None),
+                    _ => Err(e.into()),
+                };
+            }
+            len |= u64::from(byte[0] & 0x7f) << (7 * i);
+            if byte[0] & 0x80 == 0 {
+                return Ok(Some(len));
+            }
+        }
+        Err(std::io::Error::new(ErrorKind::InvalidData, "invalid record length").into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{wire_bytes, FileSource, RecordWriter, WriterCommand};
+    use crate::{publisher::frame, subscriber::HEADER_ATTACHMENT_KEY};
+    use prost::{Message, Name};
+    use robotica_types::{Header, StringMessage, DESCRIPTOR_SET_BYTES};
+    use std::{
+        fs::{File, OpenOptions},
+        path::{Path, PathBuf},
+    };
+    use zenoh::{prelude::r#async::*, sample::AttachmentBuilder};
+
+    /// Returns a path for a log file that doesn't exist yet.
+    fn log_path(name: &str) -> PathBuf {
+        let file_name = format!("robotica-{}-{name}.log", std::process::id());
+        let path = std::env::temp_dir().join(file_name);
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn text_sample(text: &str, header_in_attachment: bool) -> Sample {
+        let header = Header {
+            type_url: StringMessage::type_url(),
+            ..Header::default()
+        };
+        let message = StringMessage { data: text.into() }.encode_to_vec();
+        let key_expr = KeyExpr::try_from("test/topic").unwrap();
+        if !header_in_attachment {
+            return Sample::new(key_expr, frame(&header, &message));
+        }
+        let mut attachment = AttachmentBuilder::new();
+        attachment.insert(HEADER_ATTACHMENT_KEY, &header.encode_to_vec());
+        Sample::new(key_expr, message).with_attachment(attachment.build())
+    }
+
+    fn writer(path: &Path) -> RecordWriter {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        RecordWriter::new(file, "test/topic".into()).unwrap()
+    }
+
+    fn write(writer: &RecordWriter, sample: &Sample) {
+        let record = WriterCommand::Record(wire_bytes(sample));
+        writer.commands.send(record).unwrap();
+    }
+
+    fn source(path: &Path) -> FileSource {
+        FileSource::open(path, &[DESCRIPTOR_SET_BYTES.to_vec()]).unwrap()
+    }
+
+    fn next_text(source: &mut FileSource) -> Option<String> {
+        let received = source.next_message().unwrap()?;
+        assert_eq!(received.header.type_url, StringMessage::type_url());
+        let data = received.message.get_field_by_name("data").unwrap();
+        Some(data.as_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn records_are_read_back_in_order() {
+        let path = log_path("read-back");
+        {
+            let writer = writer(&path);
+            write(&writer, &text_sample("a", false));
+            write(&writer, &text_sample("b", true));
+        }
+        let mut source = source(&path);
+        assert_eq!(next_text(&mut source).as_deref(), Some("a"));
+        assert_eq!(next_text(&mut source).as_deref(), Some("b"));
+        assert_eq!(next_text(&mut source), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn flushed_records_are_readable_while_recording() {
+        let path = log_path("flush");
+        let writer = writer(&path);
+        write(&writer, &text_sample("a", false));
+        writer.flush().unwrap();
+        let mut source = source(&path);
+        assert_eq!(next_text(&mut source).as_deref(), Some("a"));
+        assert_eq!(next_text(&mut source), None);
+        drop(writer);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn truncated_records_are_errors() {
+        let path = log_path("truncated");
+        {
+            let writer = writer(&path);
+            write(&writer, &text_sample("a", false));
+            write(&writer, &text_sample("b", false));
+        }
+        let file = File::options().write(true).open(&path).unwrap();
+        file.set_len(file.metadata().unwrap().len() - 1).unwrap();
+        let mut source = source(&path);
+        assert_eq!(next_text(&mut source).as_deref(), Some("a"));
+        assert!(source.next_record().is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,85 @@
+use crate::{clock::Clock, Error, Publisher, PublisherOptions, Result};
+use robotica_types::Heartbeat;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::task::JoinHandle;
+use tracing::warn;
+use zenoh::prelude::r#async::*;
+
+/// A task periodically publishing heartbeats for a node, created with
+/// [`Node::start_heartbeat`](crate::Node::start_heartbeat). Heartbeats stop when this is dropped.
+#[must_use = "heartbeats stop as soon as the task is dropped"]
+pub struct HeartbeatTask {
+    task: JoinHandle<()>,
+}
+
+impl HeartbeatTask {
+    pub(crate) async fn start<F: Fn() -> String + Send + 'static>(
+        session: Arc<Session>,
+        topic: String,
+        node_name: String,
+        clock: Arc<dyn Clock>,
+        log_traffic: bool,
+        period: Duration,
+        status_fn: F,
+    ) -> Result<HeartbeatTask> {
+        if period.is_zero() {
+            return Err(Error::InvalidArgument(
+                "heartbeat period must be non-zero".into(),
+            ));
+        }
+        // The publisher borrows the session, so it's declared on the task that owns it, which then
+        // reports whether that worked
+        let (declared_sender, declared_receiver) = flume::bounded(1);
+        let task = tokio::spawn(async move {
+            let publisher = Publisher::<Heartbeat>::new_from_session(
+                &session,
+                &topic,
+                clock,
+                &PublisherOptions::default(),
+                log_traffic,
+            )
+            .await;
+            let publisher = match publisher {
+                Ok(publisher) => {
+                    let _ = declared_sender.send(Ok(()));
+                    publisher
+                }
+                Err(e) => {
+                    let _ = declared_sender.send(Err(e));
+                    return;
+                }
+            };
+            let start = Instant::now();
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                let heartbeat = Heartbeat {
+                    node_name: node_name.clone(),
+                    uptime: prost_types::Duration::try_from(start.elapsed()).ok(),
+                    status: status_fn(),
+                };
+                if let Err(e) = publisher.send(&heartbeat).await {
+                    warn!(msg = "heartbeat_failed", topic = topic, error = %e);
+                }
+            }
+        });
+        // The task only drops the sender without reporting if it panicked
+        declared_receiver.recv_async().await??;
+        Ok(HeartbeatTask { task })
+    }
+
+    /// Stops publishing heartbeats. This is the same as dropping the task, but makes the intent
+    /// explicit.
+    pub fn stop(self) {
+        self.task.abort();
+    }
+}
+
+impl Drop for HeartbeatTask {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
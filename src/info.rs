@@ -0,0 +1,179 @@
+use crate::{clock::Clock, Error, Result};
+use prost::Message;
+use prost_types::Any;
+use robotica_types::{Header, TopicInfo};
+use std::{
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, SystemTime},
+};
+use tracing::warn;
+use zenoh::{prelude::r#async::*, query::Reply, queryable::Queryable};
+
+/// Returns the zenoh key under which publishers of a topic answer queries about it.
+pub(crate) fn topic_info_key(topic: &str) -> String {
+    format!("robotica/topic_info/{topic}")
+}
+
+/// The selector used to query the info of every topic with publishers. See
+/// [`Node::publish_by_type`](crate::Node::publish_by_type).
+pub(crate) const TOPIC_INFO_SELECTOR: &str = "robotica/topic_info/**";
+
+/// Decodes the info of a topic from a publisher's reply to a query on its [`topic_info_key`].
+pub(crate) fn decode_topic_info(reply: Reply) -> Result<TopicInfo> {
+    let sample = reply
+        .sample
+        .map_err(|value| Error::QueryReply(value.to_string()))?;
+    Ok(TopicInfo::decode(&*sample.value.payload.contiguous())?)
+}
+
+/// Returns the topic whose publishers answer queries about it under the given key, if it's one of
+/// those keys.
+pub(crate) fn topic_from_info_key(key: &str) -> Option<&str> {
+    key.strip_prefix("robotica/topic_info/")
+}
+
+/// Returns the zenoh key under which nodes subscribed to a topic answer queries about it. Replies
+/// are empty, the subscribing node is identified by the zenoh ID of the replier.
+pub(crate) fn topic_subscribers_key(topic: &str) -> String {
+    format!("robotica/topic_subscribers/{topic}")
+}
+
+/// The selector used to query the subscribers of every topic. See
+/// [`Node::graph`](crate::Node::graph).
+pub(crate) const TOPIC_SUBSCRIBERS_SELECTOR: &str = "robotica/topic_subscribers/**";
+
+/// Returns the topic whose subscribers answer queries under the given key, if it's one of those
+/// keys.
+pub(crate) fn topic_from_subscribers_key(key: &str) -> Option<&str> {
+    key.strip_prefix("robotica/topic_subscribers/")
+}
+
+/// The selector used to query the names of all running nodes. See
+/// [`Node::list_nodes`](crate::Node::list_nodes).
+pub(crate) const NODE_NAMES_SELECTOR: &str = "robotica/node_names/**";
+
+/// Returns the zenoh key under which a node answers queries for its name.
+pub(crate) fn node_name_key(node_name: &str) -> String {
+    format!("robotica/node_names/{node_name}")
+}
+
+/// Returns the zenoh key under which a node answers queries for its file descriptors, if it was
+/// built with [`NodeBuilder::publish_descriptors`](crate::NodeBuilder::publish_descriptors).
+pub(crate) fn node_descriptors_key(node_name: &str) -> String {
+    format!("robotica/node/{node_name}/descriptors")
+}
+
+/// Returns the zenoh key under which a node answers queries for its metadata. See
+/// [`Node::node_info`](crate::Node::node_info).
+pub(crate) fn node_metadata_key(node_name: &str) -> String {
+    format!("robotica/node/{node_name}/info")
+}
+
+/// The selector used to query the file descriptors of all nodes that publish them. See
+/// [`Node::discover_types`](crate::Node::discover_types).
+pub(crate) const NODE_DESCRIPTORS_SELECTOR: &str = "robotica/node/**/descriptors";
+
+/// Returns the name of the node that answers queries for its file descriptors under the given key,
+/// if it's one of those keys.
+pub(crate) fn node_name_from_descriptors_key(key: &str) -> Option<&str> {
+    key.strip_prefix("robotica/node/")?
+        .strip_suffix("/descriptors")
+}
+
+/// A queryable declared by each publisher that answers with the type of the topic and the latest
+/// message sent on it, if any. See [`Node::describe_topic`](crate::Node::describe_topic).
+pub(crate) struct TopicInfoQueryable<'a> {
+    _queryable: Queryable<'a, ()>,
+    latest_payload: Arc<Mutex<Option<LatestPayload>>>,
+}
+
+/// The payload of the latest message sent on a topic, which may or may not start with its header.
+/// See [`PublisherOptions::header_in_attachment`](crate::PublisherOptions::header_in_attachment).
+struct LatestPayload {
+    payload: Arc<Vec<u8>>,
+    has_header: bool,
+    expires_at: Option<SystemTime>,
+}
+
+impl<'a> TopicInfoQueryable<'a> {
+    pub(crate) async fn new(
+        session: &'a Session,
+        topic: &str,
+        type_url: String,
+        clock: Arc<dyn Clock>,
+    ) -> Result<TopicInfoQueryable<'a>> {
+        let latest_payload: Arc<Mutex<Option<LatestPayload>>> = Arc::default();
+        let callback_payload = latest_payload.clone();
+        let queryable = session
+            .declare_queryable(topic_info_key(topic))
+            .callback(move |query| {
+                use zenoh::prelude::sync::SyncResolve;
+
+                let latest_message = callback_payload
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .as_ref()
+                    .filter(|latest| {
+                        latest
+                            .expires_at
+                            .map_or(true, |expires_at| clock.now() < expires_at)
+                    })
+                    .and_then(message_bytes)
+                    .map(|value| Any {
+                        type_url: type_url.clone(),
+                        value,
+                    });
+                let info = TopicInfo {
+                    type_url: type_url.clone(),
+                    latest_message,
+                };
+                let sample = Sample::new(query.key_expr().clone(), info.encode_to_vec());
+                if let Err(e) = query.reply(Ok(sample)).res_sync() {
+                    warn!(msg = "topic_info_reply_failed", error = %e);
+                }
+            })
+            .res()
+            .await?;
+        Ok(TopicInfoQueryable {
+            _queryable: queryable,
+            latest_payload,
+        })
+    }
+
+    /// Records the payload of the latest message sent on the topic, along with its header. The
+    /// payload starts with the header if `has_header` is set, and holds only the message otherwise.
+    /// The message stops being reported once the TTL in its header, if any, has passed.
+    pub(crate) fn set_latest_payload(
+        &self,
+        payload: Arc<Vec<u8>>,
+        header: &Header,
+        has_header: bool,
+    ) {
+        *self
+            .latest_payload
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(LatestPayload {
+            payload,
+            has_header,
+            expires_at: expires_at(header),
+        });
+    }
+}
+
+/// Returns when a message with the given header expires, or `None` if it doesn't.
+fn expires_at(header: &Header) -> Option<SystemTime> {
+    let sent_at = SystemTime::try_from(header.message_timestamp?).ok()?;
+    let ttl = Duration::try_from(header.ttl?).ok()?;
+    sent_at.checked_add(ttl)
+}
+
+/// Extracts the encoded message from a payload, skipping the header in front of it if there is one.
+fn message_bytes(latest: &LatestPayload) -> Option<Vec<u8>> {
+    let mut payload = latest.payload.as_slice();
+    if !latest.has_header {
+        return Some(payload.to_vec());
+    }
+    Header::decode_length_delimited(&mut payload).ok()?;
+    let len = usize::try_from(prost::encoding::decode_varint(&mut payload).ok()?).ok()?;
+    payload.get(..len).map(<[u8]>::to_vec)
+}
@@ -0,0 +1,209 @@
+use crate::{
+    clock::Clock,
+    proto::{parse_file_descriptors, search_file_descriptors},
+    publisher::sample_value,
+    Error, Result,
+};
+use prost::Message;
+use prost_reflect::DynamicMessage;
+use prost_types::Timestamp;
+use robotica_types::{Header, LastWill};
+use serde_json::Value;
+use std::{collections::HashMap, sync::Arc};
+use tracing::{info, warn};
+use zenoh::{
+    liveliness::LivelinessToken, prelude::r#async::*, queryable::Queryable, subscriber::Subscriber,
+};
+
+/// The prefix of the liveliness tokens declared by nodes with a last will.
+const LIVELINESS_PREFIX: &str = "robotica/liveliness/";
+
+/// Returns the zenoh key of the liveliness token of a node with a last will.
+fn liveliness_key(node_name: &str) -> String {
+    format!("{LIVELINESS_PREFIX}{node_name}")
+}
+
+/// Returns the zenoh key under which a node answers queries for its last will.
+fn last_will_key(node_name: &str) -> String {
+    format!("robotica/node/{node_name}/last_will")
+}
+
+/// The message a node wants published when it dies, as configured with
+/// [`NodeBuilder::last_will`](crate::NodeBuilder::last_will).
+pub(crate) struct LastWillConfig {
+    pub(crate) topic: String,
+    pub(crate) type_url: String,
+    pub(crate) json_value: Value,
+}
+
+/// Keeps a node's last will registered for as long as the node is alive. Monitors see the node's
+/// liveliness token disappear when it's dropped, or when the node stops responding.
+pub(crate) struct LastWillRegistration {
+    _queryable: Queryable<'static, ()>,
+    _token: LivelinessToken<'static>,
+}
+
+impl LastWillRegistration {
+    pub(crate) async fn new(
+        session: &Arc<Session>,
+        node_name: &str,
+        config: &LastWillConfig,
+        file_descriptors_bytes: &[Vec<u8>],
+    ) -> Result<LastWillRegistration> {
+        // We encode the message up front, so monitors don't need the descriptors to publish it
+        let file_descriptor_pools = parse_file_descriptors(file_descriptors_bytes);
+        let message_descriptor = search_file_descriptors(&file_descriptor_pools, &config.type_url)?;
+        let message = DynamicMessage::deserialize(message_descriptor, &config.json_value)?;
+        let last_will = LastWill {
+            topic: config.topic.clone(),
+            type_url: config.type_url.clone(),
+            message: message.encode_to_vec(),
+        }
+        .encode_to_vec();
+
+        let queryable = session
+            .declare_queryable(last_will_key(node_name))
+            .callback(move |query| {
+                use zenoh::prelude::sync::SyncResolve;
+
+                let sample = Sample::new(query.key_expr().clone(), last_will.clone());
+                if let Err(e) = query.reply(Ok(sample)).res_sync() {
+                    warn!(msg = "last_will_reply_failed", error = %e);
+                }
+            })
+            .res()
+            .await?;
+        let token = session
+            .liveliness()
+            .declare_token(liveliness_key(node_name))
+            .res()
+            .await?;
+        Ok(LastWillRegistration {
+            _queryable: queryable,
+            _token: token,
+        })
+    }
+}
+
+/// This struct watches the nodes that registered a last will, and publishes it when they die.
+/// Note that you cannot create this struct directly, but must instead fetch one from a
+/// [`Node`](crate::Node). Wills are only published while [`LastWillMonitor::recv`] is being
+/// awaited, so usually you want to call it in a loop on a dedicated task.
+pub struct LastWillMonitor {
+    session: Arc<Session>,
+    clock: Arc<dyn Clock>,
+    subscriber: Subscriber<'static, flume::Receiver<Sample>>,
+    wills: HashMap<String, LastWill>,
+}
+
+impl LastWillMonitor {
+    pub(crate) async fn new(
+        session: &Arc<Session>,
+        clock: Arc<dyn Clock>,
+    ) -> Result<LastWillMonitor> {
+        let selector = format!("{LIVELINESS_PREFIX}**");
+        let subscriber = session
+            .liveliness()
+            .declare_subscriber(&selector)
+            .with(flume::unbounded())
+            .res()
+            .await?;
+        let mut monitor = LastWillMonitor {
+            session: session.clone(),
+            clock,
+            subscriber,
+            wills: HashMap::new(),
+        };
+        // Nodes that were already alive won't show up on the subscriber, so we fetch them now
+        let replies = session.liveliness().get(&selector).res().await?;
+        while let Ok(reply) = replies.recv_async().await {
+            if let Ok(sample) = reply.sample {
+                monitor.track(sample.key_expr.as_str()).await;
+            }
+        }
+        Ok(monitor)
+    }
+
+    /// This function blocks until a node with a last will dies, publishes its will, and returns
+    /// the name of the node.
+    ///
+    /// # Errors
+    /// This function will return an error if the liveliness subscriber fails, or if the will
+    /// cannot be published. This usually means an error from zenoh.
+    pub async fn recv(&mut self) -> Result<String> {
+        loop {
+            let sample = self.subscriber.recv_async().await?;
+            let key_expr = sample.key_expr.as_str();
+            if sample.kind == SampleKind::Put {
+                self.track(key_expr).await;
+                continue;
+            }
+            let Some(node_name) = key_expr.strip_prefix(LIVELINESS_PREFIX) else {
+                continue;
+            };
+            let Some(will) = self.wills.remove(node_name) else {
+                continue;
+            };
+            let header = Header {
+                message_timestamp: Some(Timestamp::from(self.clock.now())),
+                type_url: will.type_url,
+                ..Header::default()
+            };
+            let mut buf = header.encode_length_delimited_to_vec();
+            let len = u64::try_from(will.message.len()).expect("usize should always fit in u64");
+            prost::encoding::encode_varint(len, &mut buf);
+            buf.extend_from_slice(&will.message);
+            self.session
+                .put(&will.topic, sample_value(buf))
+                .res()
+                .await?;
+            info!(
+                msg = "last_will_published",
+                node = node_name,
+                topic = will.topic
+            );
+            return Ok(node_name.into());
+        }
+    }
+
+    /// Fetches and stores the last will of a node, given the key of its liveliness token. Nodes
+    /// whose will can't be fetched are ignored.
+    async fn track(&mut self, key_expr: &str) {
+        let Some(node_name) = key_expr.strip_prefix(LIVELINESS_PREFIX) else {
+            return;
+        };
+        match self.fetch_will(node_name).await {
+            Ok(will) => {
+                self.wills.insert(node_name.into(), will);
+            }
+            Err(e) => warn!(msg = "last_will_fetch_failed", node = node_name, error = %e),
+        }
+    }
+
+    async fn fetch_will(&self, node_name: &str) -> Result<LastWill> {
+        let replies = self
+            .session
+            .get(last_will_key(node_name))
+            .consolidation(ConsolidationMode::None)
+            .res()
+            .await?;
+        let reply = replies.recv_async().await?;
+        // Several replies mean several running nodes share the name, and we can't tell which of
+        // them the liveliness token belonged to
+        let mut others = 0;
+        while replies.recv_async().await.is_ok() {
+            others += 1;
+        }
+        if others > 0 {
+            warn!(
+                msg = "duplicate_last_wills",
+                node = node_name,
+                replies = others + 1,
+            );
+        }
+        let sample = reply
+            .sample
+            .map_err(|value| Error::QueryReply(value.to_string()))?;
+        Ok(LastWill::decode(&*sample.value.payload.contiguous())?)
+    }
+}
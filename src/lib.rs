@@ -1,77 +1,870 @@
+use crate::{
+    acl::TopicAcl, clock::SimClock, last_will::LastWillRegistration, subscription::Subscriptions,
+};
 use log::LevelFilter;
+use prost::Message;
+use robotica_types::{ClockMessage, NodeMetadata, TopicInfo};
 use simple_logger::SimpleLogger;
-use tracing::info;
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    path::Path,
+    sync::{Arc, Mutex, Once, PoisonError, RwLock},
+    time::{Duration, SystemTime},
+};
+use tracing::{info, warn};
 use zenoh::prelude::r#async::*;
 
+pub use bytes;
 pub use log;
+pub use tokio_util::sync::CancellationToken;
 pub use tracing;
+pub use zenoh::publication::{CongestionControl, Priority};
 
+mod acl;
+mod alias;
+mod binary;
+mod builder;
+mod cancel;
+mod clock;
+mod codec;
+mod diff;
+mod dispatcher;
+mod field_mask;
+mod file_log;
+mod heartbeat;
+mod info;
+mod last_will;
+mod monitor;
 mod proto;
 mod publisher;
+mod recorder;
 mod subscriber;
+mod subscription;
+mod supervisor;
+mod sync;
+#[cfg(test)]
+mod test_util;
+#[cfg(feature = "validate")]
+mod validate;
+mod window;
 
-pub use crate::publisher::{Publisher, UntypedPublisher};
-pub use crate::subscriber::{Subscriber, UntypedSubscriber};
+pub use crate::alias::{TopicAlias, TopicRelay};
+pub use crate::binary::{BytesPublisher, BytesSubscriber};
+pub use crate::builder::{DescriptorMerge, NameCollision, NodeBuilder};
+pub use crate::clock::{Clock, SystemClock};
+pub use crate::codec::{Codec, Decoder, ProstCodec};
+pub use crate::diff::{message_diff, FieldChange};
+pub use crate::dispatcher::TypedDispatcher;
+pub use crate::file_log::{FileSink, FileSource};
+pub use crate::heartbeat::HeartbeatTask;
+pub use crate::last_will::LastWillMonitor;
+pub use crate::monitor::{FieldMonitor, FieldStats};
+pub use crate::proto::unpack_any;
+pub use crate::publisher::{
+    ChangePublisher, HeaderOverrides, Publisher, PublisherGroup, PublisherOptions,
+    UntypedPublisher, SAMPLE_ENCODING,
+};
+pub use crate::recorder::Recorder;
+pub use crate::subscriber::{
+    History, LazyMessage, Subscriber, SubscriberOptions, UntypedSubscriber,
+};
+pub use crate::supervisor::SupervisedTask;
+pub use crate::sync::TimeSynchronizer;
+pub use crate::window::WindowSubscriber;
 
 /// This struct represents a node in the robotica system. This is the basic unit of interaction.
 /// This is the basic unit of interaction with robotica. Use this to create channels (publishers,
 /// subscribers, etc.), interact with the environment, and generally setup your application.
+///
+/// A process can run any number of nodes. Each node opens its own zenoh session with its own
+/// configuration, and keeps its own file descriptors, clock and subscriptions, so nodes don't
+/// interfere with each other. The logger is the only thing they share: it's installed by the
+/// first node built with a [`LogConfig`], and the configs of later nodes are ignored. For instance,
+/// a bridge between two separate zenoh networks builds one node per network, each with scouting
+/// disabled and connected to its own routers with [`NodeBuilder::connect`], and forwards topics
+/// between them with [`Node::relay`].
 pub struct Node {
     node_name: String,
-    zenoh_session: Session,
-    file_descriptor: Vec<Vec<u8>>,
+    zenoh_session: Arc<Session>,
+    file_descriptor: Arc<RwLock<Vec<Vec<u8>>>>,
+    /// The full names of the types already checked by [`Node::register_type`]
+    registered_types: Mutex<HashSet<String>>,
+    descriptor_merge: DescriptorMerge,
+    clock: Arc<dyn Clock>,
+    sim_time_subscriber: Option<zenoh::subscriber::Subscriber<'static, ()>>,
+    _descriptors_queryable: Option<zenoh::queryable::Queryable<'static, ()>>,
+    _node_name_queryable: zenoh::queryable::Queryable<'static, ()>,
+    _metadata_queryable: zenoh::queryable::Queryable<'static, ()>,
+    _last_will: Option<LastWillRegistration>,
+    subscriptions: Subscriptions,
+    topic_acl: TopicAcl,
+    log_traffic: bool,
 }
 
 impl Node {
-    /// Creates a new node with logging enabled and a given name.
+    /// Creates a new node with logging enabled and a given name. Logging is only set up once per
+    /// process, so if a previous node already set it up, the provided config is ignored. If the
+    /// host application installed its own logger, that one is kept and a warning is logged
+    /// instead.
     ///
     /// # Errors
-    /// This function will return an error if the zenoh session cannot be created, or if logging
-    /// setup fails.
+    /// This function will return an error if the node name is invalid, or if the zenoh session
+    /// cannot be created.
     pub async fn new_with_logging<S: AsRef<str>>(node_name: S, logging: LogConfig) -> Result<Node> {
-        configure_logging(&logging)?;
-        Self::new(node_name).await
+        Self::builder(node_name).logging(logging).build().await
     }
 
-    /// Creates a new node with a given name.
+    /// Creates a new node with a given name. This does not set up any logging, so any logs will go
+    /// to whatever logger the host application has configured, if any.
     ///
     /// # Errors
-    /// This function will return an error if the zenoh session cannot be created.
+    /// This function will return an error if the node name is invalid, or if the zenoh session
+    /// cannot be created.
     pub async fn new<S: AsRef<str>>(node_name: S) -> Result<Node> {
-        let zenoh_session = zenoh::open(config::default()).res().await?;
-        info!(msg = "node_created", name = node_name.as_ref());
-        Ok(Node {
-            node_name: node_name.as_ref().into(),
-            zenoh_session,
-            // We default to use our own file descriptor
-            file_descriptor: vec![robotica_types::DESCRIPTOR_SET_BYTES.to_vec()],
-        })
+        Self::builder(node_name).build().await
+    }
+
+    /// Returns a [`NodeBuilder`] to create a node with a given name and additional options.
+    #[must_use]
+    pub fn builder<S: AsRef<str>>(node_name: S) -> NodeBuilder {
+        NodeBuilder::new(node_name)
     }
 
     /// This function allows you to override the file descriptor data used for untyped publishers
     /// and subscribers, as well as other relevant reflection functions.
     pub fn add_file_descriptors(&mut self, file_descriptors_bytes: &[u8]) {
-        self.file_descriptor.push(file_descriptors_bytes.to_vec());
+        self.file_descriptor
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(file_descriptors_bytes.to_vec());
+    }
+
+    /// This function adds the file descriptors of a statically-typed message to this node's, so
+    /// the type can be found by reflection and introspection even if it's only used by typed
+    /// publishers and subscribers, which don't register their types on their own. This includes
+    /// the descriptors published with [`NodeBuilder::publish_descriptors`], and so
+    /// [`Node::discover_types`] on other nodes. Types already in the node's file descriptors aren't
+    /// added again, and registering the same type more than once is cheap. Messages generated with
+    /// `prost-reflect-build`, such as the ones in `robotica_types`, carry their descriptors. Unlike
+    /// [`Node::add_file_descriptors`], this can be called while publishers and subscribers created
+    /// from this node exist.
+    pub fn register_type<M: prost_reflect::ReflectMessage + Default>(&self) {
+        let descriptor = M::default().descriptor();
+        let newly_seen = self
+            .registered_types
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(descriptor.full_name().to_string());
+        if !newly_seen {
+            return;
+        }
+        let mut file_descriptors = self
+            .file_descriptor
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        let registered = proto::parse_file_descriptors(&file_descriptors)
+            .iter()
+            .any(|pool| pool.get_message_by_name(descriptor.full_name()).is_some());
+        if !registered {
+            file_descriptors.push(descriptor.parent_pool().encode_to_vec());
+            info!(
+                msg = "type_registered",
+                name = self.node_name,
+                type_name = descriptor.full_name(),
+            );
+        }
+    }
+
+    /// This function decodes the message packed in a `google.protobuf.Any`, looking up its type in
+    /// this node's file descriptors. See [`unpack_any`] for details.
+    ///
+    /// # Errors
+    /// This function will return an error if the type URL of the `Any` is invalid or unknown, or if
+    /// the packed message cannot be decoded. File descriptors that fail to parse are skipped.
+    pub fn unpack_any(&self, any: &prost_types::Any) -> Result<prost_reflect::DynamicMessage> {
+        unpack_any(
+            any,
+            &proto::parse_file_descriptors(&self.file_descriptors()?),
+        )
+    }
+
+    /// This function returns a message of the given type as JSON, with every field set to its
+    /// default value, including nested messages. This is useful as a starting point to write
+    /// messages by hand, such as the ones sent through an [`UntypedPublisher`]. The type is looked
+    /// up in this node's file descriptors.
+    ///
+    /// # Errors
+    /// This function will return an error if the type URL is invalid or unknown, or if the message
+    /// cannot be serialized as JSON.
+    pub fn message_template(&self, type_url: &str) -> Result<serde_json::Value> {
+        let message_descriptor = proto::search_file_descriptors(
+            &proto::parse_file_descriptors(&self.file_descriptors()?),
+            type_url,
+        )?;
+        Ok(
+            proto::message_template(&message_descriptor).serialize_with_options(
+                serde_json::value::Serializer,
+                &prost_reflect::SerializeOptions::new().skip_default_fields(false),
+            )?,
+        )
+    }
+
+    /// Returns the file descriptor sets of this node, in the order types should be searched in,
+    /// according to its [`DescriptorMerge`] strategy.
+    fn file_descriptors(&self) -> Result<Vec<Vec<u8>>> {
+        proto::resolve_file_descriptors(
+            self.file_descriptor
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone(),
+            self.descriptor_merge,
+        )
+    }
+
+    /// This function replaces the clock used to timestamp messages sent by publishers created from
+    /// this node. By default, nodes use the [`SystemClock`]. Note that publishers that already
+    /// exist will keep using the clock that was set when they were created.
+    pub fn set_clock<C: Clock + 'static>(&mut self, clock: C) {
+        self.clock = Arc::new(clock);
+    }
+
+    /// This function makes the node follow simulated time, as published by a simulator on the
+    /// given topic as [`ClockMessage`]s. From then on, this node's clock reports the last time
+    /// received on that topic, or the UNIX epoch until the first message arrives. Calling this
+    /// again replaces the previous clock topic. As with [`Node::set_clock`], only publishers
+    /// created after this call will be stamped with simulated time.
+    ///
+    /// # Errors
+    /// This function will return an error if the clock subscriber cannot be created. This usually
+    /// means an error from zenoh, or that the topic is invalid or not permitted for this node (see
+    /// [`NodeBuilder::allow_topics`]).
+    pub async fn use_sim_time<S: AsRef<str>>(&mut self, topic: S) -> Result<()> {
+        let topic = topic.as_ref();
+        self.topic_acl.check(topic)?;
+        let sim_clock = SimClock::new();
+        let callback_clock = sim_clock.clone();
+        let subscriber = self
+            .zenoh_session
+            .declare_subscriber(topic)
+            .callback(move |sample| {
+                match subscriber::decode_sample::<ClockMessage, ProstCodec>(&sample) {
+                    Ok(msg) => {
+                        if let Some(Ok(time)) = msg.message.time.map(SystemTime::try_from) {
+                            callback_clock.set(time);
+                        } else {
+                            warn!(msg = "invalid_sim_time", error = "missing or invalid time");
+                        }
+                    }
+                    Err(e) => warn!(msg = "invalid_sim_time", error = %e),
+                }
+            })
+            .res()
+            .await?;
+        self.sim_time_subscriber = Some(subscriber);
+        self.clock = Arc::new(sim_clock);
+        info!(
+            msg = "sim_time_enabled",
+            name = self.node_name,
+            topic = topic
+        );
+        Ok(())
+    }
+
+    /// This function aliases one topic to another, republishing every message published on `from`
+    /// onto `to`. This eases migrations between topic names, when producers and consumers can't
+    /// all be updated at once. If `bidirectional` is set, messages published on `to` are also
+    /// republished on `from`. In that case, only messages published by other nodes are aliased, to
+    /// avoid republishing messages back and forth. Aliasing stops when the returned
+    /// [`TopicAlias`] is dropped.
+    ///
+    /// # Errors
+    /// This function will return an error if the alias cannot be created. This usually means an
+    /// error from zenoh, or that either topic is invalid or not permitted for this node (see
+    /// [`NodeBuilder::allow_topics`]).
+    pub async fn alias_topic<S: AsRef<str>, S2: AsRef<str>>(
+        &self,
+        from: S,
+        to: S2,
+        bidirectional: bool,
+    ) -> Result<TopicAlias> {
+        let from = from.as_ref();
+        let to = to.as_ref();
+        self.topic_acl.check(from)?;
+        self.topic_acl.check(to)?;
+        let alias = TopicAlias::new(&self.zenoh_session, from, to, bidirectional).await?;
+        info!(
+            msg = "topic_aliased",
+            name = self.node_name,
+            from = from,
+            to = to,
+            bidirectional = bidirectional,
+        );
+        Ok(alias)
+    }
+
+    /// This function relays a topic of this node to a topic of `target`, republishing every message
+    /// received on `from_topic` onto `to_topic` as-is, header included. Messages aren't decoded and
+    /// re-encoded, so relaying works for any message type and has little overhead. With `target`
+    /// being a node on a different zenoh network (see [`NodeBuilder::connect`]), this bridges
+    /// topics between otherwise isolated networks. Relaying to a topic of this same node is also
+    /// possible, though [`Node::alias_topic`] is usually more convenient for that. Relaying stops
+    /// when the returned [`TopicRelay`] is dropped.
+    ///
+    /// # Errors
+    /// This function will return an error if the relay cannot be created. This usually means an
+    /// error from zenoh, or that `from_topic` is invalid or not permitted for this node, or
+    /// `to_topic` for the target node (see [`NodeBuilder::allow_topics`]).
+    pub async fn relay<S: AsRef<str>, S2: AsRef<str>>(
+        &self,
+        from_topic: S,
+        target: &Node,
+        to_topic: S2,
+    ) -> Result<TopicRelay> {
+        let from_topic = from_topic.as_ref();
+        let to_topic = to_topic.as_ref();
+        self.topic_acl.check(from_topic)?;
+        target.topic_acl.check(to_topic)?;
+        let relay = TopicRelay::new(
+            &self.zenoh_session,
+            &target.zenoh_session,
+            from_topic,
+            to_topic,
+        )
+        .await?;
+        info!(
+            msg = "topic_relayed",
+            name = self.node_name,
+            from = from_topic,
+            target = target.node_name,
+            to = to_topic,
+        );
+        Ok(relay)
+    }
+
+    /// This function starts publishing a [`Heartbeat`](robotica_types::Heartbeat) on the given
+    /// topic every `period`, with the name of this node, how long the heartbeat has been running,
+    /// and the status returned by `status_fn` at that time. This gives every node in a fleet the
+    /// same way of reporting it's alive. Heartbeats are stamped with this node's clock, and are
+    /// published from a task spawned on the current tokio runtime until the returned
+    /// [`HeartbeatTask`] is dropped or stopped. Heartbeats are sent through a regular publisher, so
+    /// the topic shows up in [`Node::graph`] like any other.
+    ///
+    /// # Errors
+    /// This function will return an error if `period` is zero, or if the publisher cannot be
+    /// created. This usually means an error from zenoh, or that the topic is invalid or not
+    /// permitted for this node (see [`NodeBuilder::allow_topics`]). Failures to publish individual
+    /// heartbeats are logged instead.
+    ///
+    /// # Panics
+    /// This function will panic if it's not called from within a tokio runtime.
+    #[must_use = "heartbeats stop as soon as the returned task is dropped"]
+    pub async fn start_heartbeat<S: AsRef<str>, F: Fn() -> String + Send + 'static>(
+        &self,
+        topic: S,
+        period: Duration,
+        status_fn: F,
+    ) -> Result<HeartbeatTask> {
+        let topic = topic.as_ref();
+        self.topic_acl.check(topic)?;
+        let task = HeartbeatTask::start(
+            self.zenoh_session.clone(),
+            topic.into(),
+            self.node_name.clone(),
+            self.clock.clone(),
+            self.log_traffic,
+            period,
+            status_fn,
+        )
+        .await?;
+        info!(
+            msg = "heartbeat_started",
+            name = self.node_name,
+            topic = topic
+        );
+        Ok(task)
+    }
+
+    /// This function runs a task and restarts it whenever it returns an error or panics, waiting
+    /// longer between consecutive restarts, from 100 ms up to 30 s. Each restart is logged along
+    /// with the error. The task is created anew by calling `task_factory` on every start, which is
+    /// useful for the many "subscribe and loop forever" consumers of long-running applications:
+    /// a failure then costs a resubscription instead of the whole process. Supervision ends when
+    /// the task returns successfully, or when the returned [`SupervisedTask`] is dropped or
+    /// stopped. The task runs on the current tokio runtime.
+    ///
+    /// # Panics
+    /// This function will panic if it's not called from within a tokio runtime.
+    #[must_use = "supervision stops as soon as the returned task is dropped"]
+    pub fn supervise<S, F, Fut>(&self, task_name: S, task_factory: F) -> SupervisedTask
+    where
+        S: AsRef<str>,
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send,
+    {
+        info!(
+            msg = "supervised_task_started",
+            name = self.node_name,
+            task = task_name.as_ref(),
+        );
+        SupervisedTask::start(
+            self.node_name.clone(),
+            task_name.as_ref().into(),
+            task_factory,
+        )
+    }
+
+    /// This function starts watching the nodes that set a last will with
+    /// [`NodeBuilder::last_will`], to publish it when they die. Wills are stamped with this node's
+    /// clock. See [`LastWillMonitor`] for details.
+    ///
+    /// # Errors
+    /// This function will return an error if the liveliness subscriber cannot be created, or if
+    /// the nodes that are already alive cannot be queried. This usually means an error from zenoh.
+    pub async fn monitor_last_wills(&self) -> Result<LastWillMonitor> {
+        LastWillMonitor::new(&self.zenoh_session, self.clock.clone()).await
+    }
+
+    /// This function returns the ID of this node, which is the zenoh ID of its session. Other
+    /// nodes can use it to send messages to this node only, with [`Publisher::send_to`]. This is
+    /// the same ID reported as [`NodeInfo::replier_id`] by [`Node::list_nodes_detailed`].
+    #[must_use]
+    pub fn id(&self) -> String {
+        self.zenoh_session.zid().to_string()
+    }
+
+    /// This function returns the zenoh routers and peers this node's session is currently
+    /// connected to. This is mostly useful to debug discovery issues between nodes. Note that
+    /// zenoh only reports the ID of each connection, not the locators it was reached through.
+    pub async fn peers(&self) -> Vec<PeerInfo> {
+        let info = self.zenoh_session.info();
+        let routers = info.routers_zid().res().await.map(|zid| PeerInfo {
+            zid: zid.to_string(),
+            kind: PeerKind::Router,
+        });
+        let peers = info.peers_zid().res().await.map(|zid| PeerInfo {
+            zid: zid.to_string(),
+            kind: PeerKind::Peer,
+        });
+        routers.chain(peers).collect()
+    }
+
+    /// This function returns the names of all nodes currently running across the system,
+    /// including this one. Nodes with the same name are only listed once, see
+    /// [`Node::list_nodes_detailed`] to tell them apart.
+    ///
+    /// # Errors
+    /// This function will return an error if the query fails in zenoh.
+    pub async fn list_nodes(&self) -> Result<HashSet<String>> {
+        let mut names = HashSet::new();
+        self.query_nodes(|node| {
+            names.insert(node.name);
+            true
+        })
+        .await?;
+        Ok(names)
+    }
+
+    /// This function returns the names of all nodes currently running across the system, same as
+    /// [`Node::list_nodes`], but stops waiting for replies once the given token is cancelled. The
+    /// underlying zenoh query is dropped along with any replies still to come.
+    ///
+    /// # Errors
+    /// This function will return [`Error::Cancelled`] if the token is cancelled before every node
+    /// replies, or an error if the query fails in zenoh.
+    pub async fn list_nodes_with_cancel(
+        &self,
+        token: &CancellationToken,
+    ) -> Result<HashSet<String>> {
+        cancel::cancellable(token, self.list_nodes()).await
+    }
+
+    /// This function returns the names of up to `max_nodes` nodes currently running across the
+    /// system, same as [`Node::list_nodes`], but stops waiting for replies as soon as that many
+    /// distinct names were received. This bounds the memory and time taken by discovery on large
+    /// deployments, at the cost of an arbitrary subset of nodes being listed.
+    ///
+    /// # Errors
+    /// This function will return an error if the query fails in zenoh.
+    pub async fn list_nodes_up_to(&self, max_nodes: usize) -> Result<HashSet<String>> {
+        let mut names = HashSet::new();
+        if max_nodes == 0 {
+            return Ok(names);
+        }
+        self.query_nodes(|node| {
+            names.insert(node.name);
+            names.len() < max_nodes
+        })
+        .await?;
+        Ok(names)
+    }
+
+    /// This function returns every reply to a query for the names of running nodes, along with
+    /// the zenoh ID of the session that sent it. Since each node answers on its own, two entries
+    /// with the same name but different replier IDs mean two nodes on different sessions share a
+    /// name, which is usually a misconfiguration. Replies that cannot be decoded are skipped.
+    ///
+    /// # Errors
+    /// This function will return an error if the query fails in zenoh.
+    pub async fn list_nodes_detailed(&self) -> Result<Vec<NodeInfo>> {
+        let mut nodes = Vec::new();
+        self.query_nodes(|node| {
+            nodes.push(node);
+            true
+        })
+        .await?;
+        Ok(nodes)
+    }
+
+    /// This function asks a running node for its metadata: how long it's been running, the
+    /// version of robotica it was built with, and the ID of its process. This is mostly useful to
+    /// diagnose a live system, such as finding nodes that restarted or run an outdated build. If
+    /// several nodes share the name, the first reply is returned, and a warning is logged with the
+    /// number of nodes that answered.
+    ///
+    /// # Errors
+    /// This function will return an error if no node with this name answers, if the query fails in
+    /// zenoh, or if the first reply cannot be decoded.
+    pub async fn node_info<S: AsRef<str>>(&self, node_name: S) -> Result<NodeMetadata> {
+        let node_name = node_name.as_ref();
+        let replies = self
+            .zenoh_session
+            .get(info::node_metadata_key(node_name))
+            .consolidation(ConsolidationMode::None)
+            .res()
+            .await?;
+        let reply = replies
+            .recv_async()
+            .await
+            .map_err(|_| Error::NodeNotFound(node_name.into()))?;
+        let mut others = 0;
+        while replies.recv_async().await.is_ok() {
+            others += 1;
+        }
+        if others > 0 {
+            warn!(
+                msg = "duplicate_node_names",
+                node = node_name,
+                replies = others + 1,
+            );
+        }
+        let sample = reply
+            .sample
+            .map_err(|value| Error::QueryReply(value.to_string()))?;
+        Ok(NodeMetadata::decode(&*sample.value.payload.contiguous())?)
+    }
+
+    /// Queries the names of running nodes, passing each valid reply to `on_node` until it returns
+    /// false or every node has replied.
+    async fn query_nodes<F: FnMut(NodeInfo) -> bool>(&self, mut on_node: F) -> Result<()> {
+        let replies = self
+            .zenoh_session
+            .get(info::NODE_NAMES_SELECTOR)
+            .consolidation(ConsolidationMode::None)
+            .res()
+            .await?;
+        while let Ok(reply) = replies.recv_async().await {
+            let name = reply
+                .sample
+                .map_err(|value| value.to_string())
+                .and_then(|sample| {
+                    String::from_utf8(sample.value.payload.contiguous().to_vec())
+                        .map_err(|e| e.to_string())
+                });
+            match name {
+                Ok(name) => {
+                    let node = NodeInfo {
+                        name,
+                        replier_id: reply.replier_id.to_string(),
+                    };
+                    if !on_node(node) {
+                        break;
+                    }
+                }
+                Err(e) => warn!(msg = "invalid_node_name_reply", error = e),
+            }
+        }
+        Ok(())
+    }
+
+    /// This function returns the pub/sub graph of the whole system in one call: every running
+    /// node, every topic each of them publishes or subscribes to, and an edge from each publisher
+    /// to each subscriber of the same topic. Nodes are identified by their ID, see [`Node::id`].
+    /// This aggregates the node and topic introspection queries into a single traversal, and is
+    /// meant for tools such as graph viewers. Only publishers created with [`Node::publish`] or
+    /// [`Node::publish_untyped`] (and their variants) are listed, and subscribers of older versions
+    /// of robotica aren't. Replies that cannot be decoded are skipped.
+    ///
+    /// # Errors
+    /// This function will return an error if any of the queries fails in zenoh.
+    pub async fn graph(&self) -> Result<SystemGraph> {
+        let (nodes, publishers, subscribers) = futures::try_join!(
+            self.list_nodes_detailed(),
+            self.graph_publishers(),
+            self.graph_subscribers(),
+        )?;
+        let mut edges = Vec::new();
+        for publisher in &publishers {
+            for subscriber in subscribers.iter().filter(|s| s.topic == publisher.topic) {
+                edges.push(GraphEdge {
+                    publisher_id: publisher.node_id.clone(),
+                    subscriber_id: subscriber.node_id.clone(),
+                    topic: publisher.topic.clone(),
+                    type_url: publisher.type_url.clone(),
+                });
+            }
+        }
+        Ok(SystemGraph {
+            nodes,
+            publishers,
+            subscribers,
+            edges,
+        })
+    }
+
+    /// Queries the publishers of every topic, as listed in [`SystemGraph::publishers`]. Replies
+    /// that cannot be decoded are skipped.
+    async fn graph_publishers(&self) -> Result<Vec<GraphPublisher>> {
+        let replies = self
+            .zenoh_session
+            .get(info::TOPIC_INFO_SELECTOR)
+            .consolidation(ConsolidationMode::None)
+            .res()
+            .await?;
+        let mut publishers = Vec::new();
+        while let Ok(reply) = replies.recv_async().await {
+            let publisher = reply
+                .sample
+                .map_err(|value| Error::QueryReply(value.to_string()))
+                .and_then(|sample| {
+                    let info = TopicInfo::decode(&*sample.value.payload.contiguous())?;
+                    Ok((sample.key_expr, info))
+                });
+            match publisher {
+                Ok((key_expr, info)) => {
+                    if let Some(topic) = info::topic_from_info_key(key_expr.as_str()) {
+                        publishers.push(GraphPublisher {
+                            node_id: reply.replier_id.to_string(),
+                            topic: topic.into(),
+                            type_url: info.type_url,
+                        });
+                    }
+                }
+                Err(e) => warn!(msg = "invalid_topic_info_reply", error = %e),
+            }
+        }
+        Ok(publishers)
+    }
+
+    /// Queries the subscribers of every topic, as listed in [`SystemGraph::subscribers`].
+    async fn graph_subscribers(&self) -> Result<Vec<GraphSubscriber>> {
+        let replies = self
+            .zenoh_session
+            .get(info::TOPIC_SUBSCRIBERS_SELECTOR)
+            .consolidation(ConsolidationMode::None)
+            .res()
+            .await?;
+        let mut subscribers = Vec::new();
+        while let Ok(reply) = replies.recv_async().await {
+            match reply.sample {
+                Ok(sample) => {
+                    if let Some(topic) = info::topic_from_subscribers_key(sample.key_expr.as_str())
+                    {
+                        subscribers.push(GraphSubscriber {
+                            node_id: reply.replier_id.to_string(),
+                            topic: topic.into(),
+                        });
+                    }
+                }
+                Err(value) => warn!(msg = "invalid_topic_subscribers_reply", error = %value),
+            }
+        }
+        Ok(subscribers)
+    }
+
+    /// This function asks every node built with [`NodeBuilder::publish_descriptors`] for its file
+    /// descriptors, and returns the type URL of every message they define, along with the names of
+    /// the nodes that provide each. This gives tools a catalog of every type that can be decoded
+    /// on the system. Replies that cannot be decoded are skipped.
+    ///
+    /// # Errors
+    /// This function will return an error if the query fails in zenoh.
+    pub async fn discover_types(&self) -> Result<HashMap<String, HashSet<String>>> {
+        let replies = self
+            .zenoh_session
+            .get(info::NODE_DESCRIPTORS_SELECTOR)
+            .consolidation(ConsolidationMode::None)
+            .res()
+            .await?;
+        let mut types: HashMap<String, HashSet<String>> = HashMap::new();
+        while let Ok(reply) = replies.recv_async().await {
+            let sample = match reply.sample {
+                Ok(sample) => sample,
+                Err(value) => {
+                    warn!(msg = "invalid_descriptors_reply", error = %value);
+                    continue;
+                }
+            };
+            let Some(node_name) = info::node_name_from_descriptors_key(sample.key_expr.as_str())
+            else {
+                continue;
+            };
+            let bytes = sample.value.payload.contiguous();
+            match prost_reflect::DescriptorPool::decode(bytes.as_ref()) {
+                Ok(pool) => {
+                    for message in pool.all_messages() {
+                        types
+                            .entry(proto::type_url(message.full_name()))
+                            .or_default()
+                            .insert(node_name.into());
+                    }
+                }
+                Err(e) => warn!(msg = "invalid_descriptors_reply", node = node_name, error = %e),
+            }
+        }
+        Ok(types)
+    }
+
+    /// This function asks the publishers of a topic for its type URL and the latest message they
+    /// sent, if any. This is a cheap way to learn the type of a topic without waiting for new
+    /// messages to be published. If several publishers answer, the first reply is returned, and a
+    /// warning is logged for every other publisher that reports a different type, since that
+    /// usually means two unrelated publishers share the topic by mistake.
+    ///
+    /// # Errors
+    /// This function will return an error if no publisher answers for this topic, if the query
+    /// fails in zenoh, or if the first reply cannot be decoded.
+    pub async fn describe_topic<S: AsRef<str>>(&self, topic: S) -> Result<TopicInfo> {
+        let topic = topic.as_ref();
+        let replies = self
+            .zenoh_session
+            .get(info::topic_info_key(topic))
+            .consolidation(ConsolidationMode::None)
+            .res()
+            .await?;
+        let reply = replies
+            .recv_async()
+            .await
+            .map_err(|_| Error::TopicNotFound(topic.into()))?;
+        let info = info::decode_topic_info(reply)?;
+        while let Ok(reply) = replies.recv_async().await {
+            let replier_id = reply.replier_id;
+            match info::decode_topic_info(reply) {
+                Ok(other) if other.type_url != info.type_url => warn!(
+                    msg = "conflicting_topic_types",
+                    topic = topic,
+                    type_url = info.type_url,
+                    other_type_url = other.type_url,
+                    replier_id = %replier_id,
+                ),
+                Ok(_) => {}
+                Err(e) => warn!(msg = "invalid_topic_info_reply", topic = topic, error = %e),
+            }
+        }
+        Ok(info)
+    }
+
+    /// This function creates a dynamically-typed publisher for the topic that carries messages of
+    /// the given type, as reported by the publishers already running on it. This is a convenience
+    /// for tools and scripts where the type identifies the data better than the topic name does.
+    /// Only topics with a running publisher can be found, and replies that cannot be decoded are
+    /// skipped.
+    ///
+    /// # Errors
+    /// This function will return an error if no topic or more than one topic carries the type, if
+    /// the query fails in zenoh, or if the publisher cannot be created. See
+    /// [`Node::publish_untyped`] for details on the latter.
+    pub async fn publish_by_type<S: AsRef<str>>(
+        &self,
+        type_url: S,
+    ) -> Result<UntypedPublisher<'_>> {
+        let type_url = type_url.as_ref();
+        let topics: HashSet<String> = self
+            .graph_publishers()
+            .await?
+            .into_iter()
+            .filter(|publisher| publisher.type_url == type_url)
+            .map(|publisher| publisher.topic)
+            .collect();
+        let mut topics: Vec<String> = topics.into_iter().collect();
+        match topics.len() {
+            0 => Err(Error::TypeNotPublished(type_url.into())),
+            1 => self.publish_untyped(topics.remove(0), type_url).await,
+            _ => {
+                topics.sort();
+                Err(Error::AmbiguousTopicType {
+                    type_url: type_url.into(),
+                    topics,
+                })
+            }
+        }
     }
 
     /// This function creates a subscriber for a given topic. The topic is a string that uniquely
     /// identifies the data channel across an entire system. Note that we expect the type to be a
-    /// protobuf message that can be decoded.
+    /// protobuf message that can be decoded. Call [`Node::register_type`] to make the type
+    /// available to reflection and introspection.
     ///
     /// # Errors
     /// This function will return an error if the subscriber cannot be created. This usually means
-    /// an error from zenoh.
+    /// an error from zenoh, or that the topic is invalid or not permitted for this node (see
+    /// [`NodeBuilder::allow_topics`]).
     pub async fn subscribe<M: prost::Message + prost::Name + Default, S: AsRef<str>>(
         &self,
         topic: S,
-    ) -> Result<Subscriber<'_, M>> {
+    ) -> Result<Subscriber<M>> {
+        self.subscribe_with_options(topic, SubscriberOptions::default())
+            .await
+    }
+
+    /// This function creates a subscriber for a given topic, same as [`Node::subscribe`], but
+    /// configured with the provided [`SubscriberOptions`].
+    ///
+    /// # Errors
+    /// This function will return an error if the subscriber cannot be created. This usually means
+    /// an error from zenoh, or that the topic is invalid or not permitted for this node (see
+    /// [`NodeBuilder::allow_topics`]).
+    pub async fn subscribe_with_options<
+        M: prost::Message + prost::Name + Default,
+        S: AsRef<str>,
+    >(
+        &self,
+        topic: S,
+        options: SubscriberOptions,
+    ) -> Result<Subscriber<M>> {
+        self.subscribe_with_codec::<M, ProstCodec, S>(topic, options)
+            .await
+    }
+
+    /// This function creates a subscriber for a given topic, same as
+    /// [`Node::subscribe_with_options`], but decoding messages with the given [`Decoder`] instead
+    /// of as protobuf. This lets robotica carry messages of other serialization formats.
+    ///
+    /// # Errors
+    /// This function will return an error if the subscriber cannot be created. This usually means
+    /// an error from zenoh, or that the topic is invalid or not permitted for this node (see
+    /// [`NodeBuilder::allow_topics`]).
+    pub async fn subscribe_with_codec<M, C: Decoder<M>, S: AsRef<str>>(
+        &self,
+        topic: S,
+        options: SubscriberOptions,
+    ) -> Result<Subscriber<M, C>> {
         let topic = topic.as_ref();
-        let sub = Subscriber::new_from_session(&self.zenoh_session, topic).await?;
+        self.topic_acl.check(topic)?;
+        let sub = Subscriber::new_from_session(
+            &self.zenoh_session,
+            &self.subscriptions,
+            topic,
+            self.clock.clone(),
+            &options,
+            self.log_traffic,
+        )
+        .await?;
         info!(
             msg = "subscriber_created",
             name = self.node_name,
             topic = topic,
-            type_url = M::type_url(),
+            type_url = C::type_url(),
         );
         Ok(sub)
     }
@@ -79,19 +872,42 @@ impl Node {
     /// This function creates an untyped subscriber for a given topic. The topic is a string that
     /// uniquely identifies the data channel across an entire system. The subscriber will attempt
     /// to dynamically decode the messages it receives by searching for a protobuf that matches the
-    /// type URL of the message in the provided file descriptors.
+    /// type URL of the message in the provided file descriptors. File descriptors that fail to
+    /// parse are logged and skipped, so only their types are unavailable.
     ///
     /// # Errors
     /// This function will return an error if the subscriber cannot be created. This usually means
-    /// an error from zenoh.
-    pub async fn subscribe_untyped<S: AsRef<str>>(
+    /// an error from zenoh, or that the topic is invalid or not permitted for this node (see
+    /// [`NodeBuilder::allow_topics`]).
+    pub async fn subscribe_untyped<S: AsRef<str>>(&self, topic: S) -> Result<UntypedSubscriber> {
+        self.subscribe_untyped_with_options(topic, SubscriberOptions::default())
+            .await
+    }
+
+    /// This function creates an untyped subscriber for a given topic, same as
+    /// [`Node::subscribe_untyped`], but configured with the provided [`SubscriberOptions`].
+    ///
+    /// # Errors
+    /// This function will return an error if the subscriber cannot be created. This usually means
+    /// an error from zenoh, or that the topic is invalid or not permitted for this node (see
+    /// [`NodeBuilder::allow_topics`]).
+    pub async fn subscribe_untyped_with_options<S: AsRef<str>>(
         &self,
         topic: S,
-    ) -> Result<UntypedSubscriber<'_>> {
+        options: SubscriberOptions,
+    ) -> Result<UntypedSubscriber> {
         let topic = topic.as_ref();
-        let sub =
-            UntypedSubscriber::new_from_session(&self.zenoh_session, topic, &self.file_descriptor)
-                .await?;
+        self.topic_acl.check(topic)?;
+        let sub = UntypedSubscriber::new_from_session(
+            &self.zenoh_session,
+            &self.subscriptions,
+            topic,
+            &self.file_descriptors()?,
+            self.clock.clone(),
+            &options,
+            self.log_traffic,
+        )
+        .await?;
         info!(
             msg = "subscriber_created",
             name = self.node_name,
@@ -103,26 +919,87 @@ impl Node {
 
     /// This function creates a publisher for a given topic. The topic is a string that uniquely
     /// identifies the data channel across an entire system. Note that we expect the type to be a
-    /// protobuf message that can be encoded.
+    /// protobuf message that can be encoded. Call [`Node::register_type`] to make the type
+    /// available to reflection and introspection.
     ///
     /// # Errors
     /// This function will return an error if the publisher cannot be created. This usually means
-    /// an error from zenoh.
+    /// an error from zenoh, or that the topic is invalid or not permitted for this node (see
+    /// [`NodeBuilder::allow_topics`]).
     pub async fn publish<M: prost::Message + prost::Name, S: AsRef<str>>(
         &self,
         topic: S,
     ) -> Result<Publisher<'_, M>> {
+        self.publish_with_options(topic, PublisherOptions::default())
+            .await
+    }
+
+    /// This function creates a publisher for a given topic, same as [`Node::publish`], but
+    /// configured with the provided [`PublisherOptions`].
+    ///
+    /// # Errors
+    /// This function will return an error if the publisher cannot be created. This usually means
+    /// an error from zenoh, or that the topic is invalid or not permitted for this node (see
+    /// [`NodeBuilder::allow_topics`]).
+    pub async fn publish_with_options<M: prost::Message + prost::Name, S: AsRef<str>>(
+        &self,
+        topic: S,
+        options: PublisherOptions,
+    ) -> Result<Publisher<'_, M>> {
+        self.publish_with_codec::<M, ProstCodec, S>(topic, options)
+            .await
+    }
+
+    /// This function creates a publisher for a given topic, same as
+    /// [`Node::publish_with_options`], but encoding messages with the given [`Codec`] instead of
+    /// as protobuf. This lets robotica carry messages of other serialization formats.
+    ///
+    /// # Errors
+    /// This function will return an error if the publisher cannot be created. This usually means
+    /// an error from zenoh, or that the topic is invalid or not permitted for this node (see
+    /// [`NodeBuilder::allow_topics`]).
+    pub async fn publish_with_codec<M, C: Codec<M>, S: AsRef<str>>(
+        &self,
+        topic: S,
+        options: PublisherOptions,
+    ) -> Result<Publisher<'_, M, C>> {
         let topic = topic.as_ref();
-        let publisher = Publisher::new_from_session(&self.zenoh_session, topic).await?;
+        self.topic_acl.check(topic)?;
+        let publisher = Publisher::new_from_session(
+            &self.zenoh_session,
+            topic,
+            self.clock.clone(),
+            &options,
+            self.log_traffic,
+        )
+        .await?;
         info!(
             msg = "publisher_created",
             name = self.node_name,
             topic = topic,
-            type_url = M::type_url(),
+            type_url = C::type_url(),
         );
         Ok(publisher)
     }
 
+    /// This function creates a group of publishers for the given topics, all of the same message
+    /// type. Messages sent together through the group share the same header timestamp and group
+    /// ID. See [`PublisherGroup`] for details.
+    ///
+    /// # Errors
+    /// This function will return an error if any of the publishers cannot be created. This usually
+    /// means an error from zenoh.
+    pub async fn publish_group<M: prost::Message + prost::Name, S: AsRef<str>>(
+        &self,
+        topics: &[S],
+    ) -> Result<PublisherGroup<'_, M>> {
+        let mut publishers = Vec::with_capacity(topics.len());
+        for topic in topics {
+            publishers.push(self.publish(topic).await?);
+        }
+        Ok(PublisherGroup::new(publishers, self.clock.clone()))
+    }
+
     /// This function creates a dynamically-typed publisher for a given topic. The topic is a
     /// string that uniquely identifies the data channel across an entire system. Note that we
     /// expect the type to be specified ahead of time in the `type_url` parameter, and any
@@ -131,19 +1008,43 @@ impl Node {
     ///
     /// # Errors
     /// This function will return an error if the publisher cannot be created. This usually means
-    /// an error from zenoh, or that the type URL doesn't exist in the provided file descriptors.
+    /// an error from zenoh, that the type URL doesn't exist in the provided file descriptors, or
+    /// that the topic is invalid or not permitted for this node (see
+    /// [`NodeBuilder::allow_topics`]).
     pub async fn publish_untyped<S: AsRef<str>, S2: AsRef<str>>(
         &self,
         topic: S,
         type_url: S2,
+    ) -> Result<UntypedPublisher<'_>> {
+        self.publish_untyped_with_options(topic, type_url, PublisherOptions::default())
+            .await
+    }
+
+    /// This function creates a dynamically-typed publisher for a given topic, same as
+    /// [`Node::publish_untyped`], but configured with the provided [`PublisherOptions`].
+    ///
+    /// # Errors
+    /// This function will return an error if the publisher cannot be created. This usually means
+    /// an error from zenoh, that the type URL doesn't exist in the provided file descriptors, or
+    /// that the topic is invalid or not permitted for this node (see
+    /// [`NodeBuilder::allow_topics`]).
+    pub async fn publish_untyped_with_options<S: AsRef<str>, S2: AsRef<str>>(
+        &self,
+        topic: S,
+        type_url: S2,
+        options: PublisherOptions,
     ) -> Result<UntypedPublisher<'_>> {
         let topic = topic.as_ref();
+        self.topic_acl.check(topic)?;
         let type_url = type_url.as_ref();
         let publisher = UntypedPublisher::new_from_session(
             &self.zenoh_session,
             topic,
             type_url,
-            &self.file_descriptor,
+            &self.file_descriptors()?,
+            self.clock.clone(),
+            &options,
+            self.log_traffic,
         )
         .await?;
         info!(
@@ -154,6 +1055,185 @@ impl Node {
         );
         Ok(publisher)
     }
+
+    /// This function creates a publisher of raw binary data for a given topic, such as JPEG frames
+    /// or other payloads that don't fit the protobuf model. The data is sent along with the given
+    /// content type, usually a MIME type, so subscribers know how to interpret it.
+    ///
+    /// # Errors
+    /// This function will return an error if the publisher cannot be created. This usually means
+    /// an error from zenoh, or that the topic is invalid or not permitted for this node (see
+    /// [`NodeBuilder::allow_topics`]).
+    pub async fn publish_bytes<S: AsRef<str>, S2: AsRef<str>>(
+        &self,
+        topic: S,
+        content_type: S2,
+    ) -> Result<BytesPublisher<'_>> {
+        let topic = topic.as_ref();
+        self.topic_acl.check(topic)?;
+        let content_type = content_type.as_ref();
+        let publisher = BytesPublisher::new_from_session(
+            &self.zenoh_session,
+            topic,
+            content_type,
+            self.clock.clone(),
+            self.log_traffic,
+        )
+        .await?;
+        info!(
+            msg = "publisher_created",
+            name = self.node_name,
+            topic = topic,
+            content_type = content_type,
+        );
+        Ok(publisher)
+    }
+
+    /// This function creates a subscriber for a topic of raw binary data, sent by publishers
+    /// created with [`Node::publish_bytes`]. The data is received as-is, along with its content
+    /// type.
+    ///
+    /// # Errors
+    /// This function will return an error if the subscriber cannot be created. This usually means
+    /// an error from zenoh, or that the topic is invalid or not permitted for this node (see
+    /// [`NodeBuilder::allow_topics`]).
+    pub async fn subscribe_bytes<S: AsRef<str>>(&self, topic: S) -> Result<BytesSubscriber> {
+        let topic = topic.as_ref();
+        self.topic_acl.check(topic)?;
+        let sub = BytesSubscriber::new_from_session(
+            &self.zenoh_session,
+            &self.subscriptions,
+            topic,
+            self.log_traffic,
+        )
+        .await?;
+        info!(
+            msg = "subscriber_created",
+            name = self.node_name,
+            topic = topic,
+            content_type = "unknown",
+        );
+        Ok(sub)
+    }
+
+    /// This function records every message received on a topic to a compact log file at the given
+    /// path, appending to it if it exists. See [`FileSink`] for the format, and [`FileSource`] to
+    /// read it back.
+    ///
+    /// # Errors
+    /// This function will return an error if the file cannot be opened, if the subscriber cannot be
+    /// created, or if the topic is invalid or not permitted for this node (see
+    /// [`NodeBuilder::allow_topics`]).
+    pub async fn record_to_file<S: AsRef<str>, P: AsRef<Path>>(
+        &self,
+        topic: S,
+        path: P,
+    ) -> Result<FileSink> {
+        let topic = topic.as_ref();
+        self.topic_acl.check(topic)?;
+        let sink = FileSink::new(&self.zenoh_session, topic, path.as_ref()).await?;
+        info!(
+            msg = "file_sink_created",
+            name = self.node_name,
+            topic = topic,
+            path = %path.as_ref().display(),
+        );
+        Ok(sink)
+    }
+
+    /// This function sends a single JSON message on a topic, without keeping a publisher around.
+    /// This is the same as creating an [`UntypedPublisher`] with [`Node::publish_untyped`],
+    /// sending the value, and dropping it, which is handy for scripts and other fire-and-forget
+    /// publishes. Prefer keeping a publisher if you're sending more than one message.
+    ///
+    /// # Errors
+    /// This function will return an error if the publisher cannot be created, or if the message
+    /// cannot be sent. See [`Node::publish_untyped`] and [`UntypedPublisher::send`] for details.
+    pub async fn publish_json_once<S: AsRef<str>, S2: AsRef<str>>(
+        &self,
+        topic: S,
+        type_url: S2,
+        json_value: serde_json::Value,
+    ) -> Result<()> {
+        self.publish_untyped(topic, type_url)
+            .await?
+            .send(json_value)
+            .await
+    }
+}
+
+/// A node found by [`Node::list_nodes_detailed`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeInfo {
+    /// The name of the node
+    pub name: String,
+    /// The zenoh ID of the session the node replied from
+    pub replier_id: String,
+}
+
+/// The pub/sub graph of the whole system, as returned by [`Node::graph`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SystemGraph {
+    /// Every running node. The `replier_id` of each is the ID other entries refer to it by
+    pub nodes: Vec<NodeInfo>,
+    /// Every publisher of every topic
+    pub publishers: Vec<GraphPublisher>,
+    /// Every subscriber of every topic. Nodes with several subscribers on a topic are only listed
+    /// once for it
+    pub subscribers: Vec<GraphSubscriber>,
+    /// An edge from every publisher to every subscriber of the same topic
+    pub edges: Vec<GraphEdge>,
+}
+
+/// A publisher found by [`Node::graph`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GraphPublisher {
+    /// The ID of the node publishing
+    pub node_id: String,
+    /// The topic published to
+    pub topic: String,
+    /// The type URL of the messages published
+    pub type_url: String,
+}
+
+/// A subscriber found by [`Node::graph`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GraphSubscriber {
+    /// The ID of the node subscribing
+    pub node_id: String,
+    /// The topic subscribed to
+    pub topic: String,
+}
+
+/// A flow of messages between two nodes found by [`Node::graph`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GraphEdge {
+    /// The ID of the node publishing the messages
+    pub publisher_id: String,
+    /// The ID of the node subscribed to them
+    pub subscriber_id: String,
+    /// The topic the messages are sent on
+    pub topic: String,
+    /// The type URL of the messages
+    pub type_url: String,
+}
+
+/// Information about a zenoh instance a [`Node`] is connected to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerInfo {
+    /// The zenoh ID of the connected instance
+    pub zid: String,
+    /// Whether the connected instance is a router or a peer
+    pub kind: PeerKind,
+}
+
+/// The role a connected zenoh instance plays in the network.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PeerKind {
+    /// A zenoh router, which routes data between other peers and clients
+    Router,
+    /// A zenoh peer, usually another node connected directly to this one
+    Peer,
 }
 
 /// Configuration for the logging setup
@@ -161,6 +1241,8 @@ pub struct LogConfig {
     default_level: LevelFilter,
     zenoh_level: LevelFilter,
     robotica_level: LevelFilter,
+    external: bool,
+    log_traffic: bool,
 }
 
 impl LogConfig {
@@ -170,6 +1252,17 @@ impl LogConfig {
         Self::default()
     }
 
+    /// Create a log config that leaves logging entirely to the host application. No global logger
+    /// gets installed, so this is safe to use when embedding robotica in an application that
+    /// already set up its own logger. Levels set on this config are ignored.
+    #[must_use]
+    pub fn external() -> LogConfig {
+        LogConfig {
+            external: true,
+            ..Self::default()
+        }
+    }
+
     /// Sets the level to log at as default.
     #[must_use]
     pub fn default_level(mut self, l: LevelFilter) -> LogConfig {
@@ -190,6 +1283,16 @@ impl LogConfig {
         self.robotica_level = l;
         self
     }
+
+    /// Sets whether to log every message sent and received by the node's publishers and
+    /// subscribers, at info level, along with its topic, type URL and size in bytes. This is
+    /// useful to trace data flow on low-rate topics, but gets noisy quickly, so it's disabled by
+    /// default. Unlike levels, this also applies to external logging.
+    #[must_use]
+    pub fn log_traffic(mut self, log_traffic: bool) -> LogConfig {
+        self.log_traffic = log_traffic;
+        self
+    }
 }
 
 impl Default for LogConfig {
@@ -198,17 +1301,32 @@ impl Default for LogConfig {
             default_level: LevelFilter::Info,
             zenoh_level: LevelFilter::Warn,
             robotica_level: LevelFilter::Info,
+            external: false,
+            log_traffic: false,
         }
     }
 }
 
-fn configure_logging(log_config: &LogConfig) -> Result<()> {
-    SimpleLogger::new()
-        .with_level(log_config.default_level)
-        .with_module_level("zenoh", log_config.zenoh_level)
-        .with_module_level("robotica", log_config.robotica_level)
-        .init()?;
-    Ok(())
+/// Guards the installation of the logger, which is the only state robotica shares between the
+/// nodes of a process.
+static LOGGER_INIT: Once = Once::new();
+
+fn configure_logging(log_config: &LogConfig) {
+    if log_config.external {
+        return;
+    }
+    LOGGER_INIT.call_once(|| {
+        // Setting the logger only fails if the host application already set one, in which case we
+        // keep that one
+        if let Err(e) = SimpleLogger::new()
+            .with_level(log_config.default_level)
+            .with_module_level("zenoh", log_config.zenoh_level)
+            .with_module_level("robotica", log_config.robotica_level)
+            .init()
+        {
+            warn!(msg = "logger_already_initialized", error = %e);
+        }
+    });
 }
 
 /// The full set of errors returned by this library. Please refer to the specific enum values for
@@ -230,20 +1348,185 @@ pub enum Error {
     /// any other reason.
     #[error("error decoding protobuf: {0}")]
     ProtobufDecode(#[from] prost::DecodeError),
+    /// A message is shorter than the length written in front of it, usually because it was cut off
+    /// while being sent or written to a log file.
+    #[error("truncated message: expected {expected} bytes, but only {actual} are left")]
+    TruncatedMessage { expected: usize, actual: usize },
+    /// A custom [`Decoder`] failed to decode a message, usually because the data is not a valid
+    /// message of its format.
+    #[error("error in message codec: {0}")]
+    Codec(String),
     /// Failure when reading a protobuf descriptor. This error is thrown during untyped subscriber
     /// creation.
     #[error("error reading protobuf descriptor: {0}")]
     ProtobufDescriptorRead(#[from] prost_reflect::DescriptorError),
-    /// A message was received with an invalid type URL, either because the type does not exist or
-    /// because we cannot parse it.
+    /// A message was received with a type URL we cannot parse.
     #[error("invalid type URL: {0}")]
     InvalidTypeUrl(String),
+    /// The type of a type URL is not in any of the file descriptors available, usually because the
+    /// descriptors of its package were never registered. The names of the files searched are
+    /// listed, and the closest type name found, if any, is suggested.
+    #[error(
+        "type URL \"{type_url}\" not found in descriptor files [{}]{}",
+        files.join(", "),
+        suggestion.as_ref().map(|name| format!(", did you mean \"{name}\"?")).unwrap_or_default()
+    )]
+    TypeNotFound {
+        type_url: String,
+        files: Vec<String>,
+        suggestion: Option<String>,
+    },
     /// Error when parsing the JSON provided in the dynamic publisher.
     #[error("invalid type URL: {0}")]
     SerdeJsonError(#[from] serde_json::Error),
-    /// Error when parsing the JSON provided in the dynamic publisher.
+    /// Error when parsing the YAML provided in the dynamic publisher.
+    #[cfg(feature = "yaml")]
+    #[error("invalid YAML: {0}")]
+    SerdeYamlError(#[from] serde_yaml::Error),
+    /// Error when reading or writing files, such as recordings.
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    /// Error when writing an MCAP recording.
+    #[error("MCAP error: {0}")]
+    Mcap(#[from] mcap::McapError),
+    /// No publisher answered a query about a topic, usually because nothing is publishing on it.
+    #[error("no publishers found for topic \"{0}\"")]
+    TopicNotFound(String),
+    /// No running publisher sends messages of the given type URL. See [`Node::publish_by_type`].
+    #[error("no topic found carrying messages of type \"{0}\"")]
+    TypeNotPublished(String),
+    /// More than one topic carries messages of the given type URL, so the topic to use is
+    /// ambiguous. See [`Node::publish_by_type`].
+    #[error("several topics carry messages of type \"{type_url}\": {}", topics.join(", "))]
+    AmbiguousTopicType {
+        type_url: String,
+        topics: Vec<String>,
+    },
+    /// The number of messages sent through a publisher group doesn't match its number of topics.
+    #[error("publisher group has {expected} topics, but {actual} messages were sent")]
+    GroupSizeMismatch { expected: usize, actual: usize },
+    /// No descriptor registry answered on the given key expression. See
+    /// [`NodeBuilder::descriptor_registry`].
+    #[error("no descriptor registry found at \"{0}\"")]
+    DescriptorRegistryNotFound(String),
+    /// The node tried to publish or subscribe to a topic it's not permitted to. See
+    /// [`NodeBuilder::allow_topics`] and [`NodeBuilder::deny_topics`].
+    #[error("topic \"{0}\" is not permitted for this node")]
+    TopicNotPermitted(String),
+    /// No node with the given name answered a query. See [`Node::node_info`].
+    #[error("no node named \"{0}\" found")]
+    NodeNotFound(String),
+    /// Another node with the same name is already running. See
+    /// [`NodeBuilder::name_collision`].
+    #[error("a node named \"{0}\" is already running")]
+    NodeNameTaken(String),
+    /// A message was dropped because sending it would exceed the maximum rate of its publisher.
+    /// See [`PublisherOptions::max_rate_hz`].
+    #[error("message on topic \"{0}\" dropped by the rate limit")]
+    RateLimited(String),
+    /// No node acknowledged a message sent with [`Publisher::send_acked`] before the timeout.
+    #[error("no acknowledgement received for message on topic \"{0}\"")]
+    AckTimeout(String),
+    /// A message was received compressed with an algorithm this version of robotica doesn't
+    /// support, usually because it was sent by a newer version.
+    #[error("unsupported message compression: {0}")]
+    UnsupportedCompression(i32),
+    /// No message satisfying the predicate given to [`Subscriber::wait_for`] was received before
+    /// the timeout.
+    #[error("timed out waiting for a matching message on topic \"{0}\"")]
+    WaitTimeout(String),
+    /// The node name is empty or contains characters that can't be used in a zenoh key expression
+    /// chunk, such as `/` or wildcards.
+    #[error("invalid node name \"{0}\": node names must be non-empty and cannot contain '/', '*', '$', '?' or '#'")]
+    InvalidNodeName(String),
+    /// The topic name is not a valid zenoh key expression, or contains wildcards, which would
+    /// match more than one topic.
+    #[error("invalid topic \"{topic}\": {reason}")]
+    InvalidTopic { topic: String, reason: String },
+    /// An argument is outside the values a function accepts, such as a zero period or capacity.
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+    /// A message sent or received by an untyped publisher or subscriber violates the
+    /// protovalidate constraints on its fields. This is only returned with the `validate` feature.
+    #[error("message failed validation: {0}")]
+    ValidationFailed(String),
+    /// A file appears in two of the node's file descriptor sets with different contents. See
+    /// [`DescriptorMerge::ErrorOnConflict`].
+    #[error("conflicting definitions of file descriptor \"{0}\"")]
+    ConflictingDescriptors(String),
+    /// A query to another robotica entity was answered with an error.
+    #[error("query replied with an error: {0}")]
+    QueryReply(String),
+    /// Error setting up logging. Nodes no longer fail when a logger is already installed, and keep
+    /// that logger instead, so this is never returned. It's kept so matches on it still compile.
+    #[deprecated(note = "logging setup no longer fails, so this error is never returned")]
     #[error("error with logging: {0}")]
     LogSetupError(#[from] log::SetLoggerError),
+    /// A message older than the maximum age set with [`SubscriberOptions::max_age`] was received
+    /// by a subscriber set to error on stale messages.
+    #[error("stale message on topic \"{topic}\": sent {age:?} ago")]
+    StaleMessage { topic: String, age: Duration },
+    /// A subscriber set to verify the encoding of samples received one with a different
+    /// encoding, usually because something other than robotica published on the topic. See
+    /// [`SubscriberOptions::verify_encoding`].
+    #[error("unexpected encoding \"{encoding}\" on topic \"{topic}\"")]
+    UnexpectedEncoding { topic: String, encoding: String },
+    /// The operation was cancelled through its [`CancellationToken`] before completing.
+    #[error("operation cancelled")]
+    Cancelled,
+    /// An operation on a topic failed. This wraps the underlying error with the topic and the
+    /// operation that triggered it, so failures can be told apart in nodes with many topics. Every
+    /// error sending or receiving messages is wrapped, except those already naming the topic, such
+    /// as [`Error::StaleMessage`]. See [`Error::root`] to get the underlying error.
+    #[error("{operation} on topic \"{topic}\" failed: {source}")]
+    Context {
+        topic: String,
+        operation: &'static str,
+        source: Box<Error>,
+    },
+}
+
+impl Error {
+    /// Returns the underlying error, without the context added by [`Error::Context`]. Match on
+    /// this to handle specific errors regardless of where they happened.
+    #[must_use]
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::Context { source, .. } => source.root(),
+            error => error,
+        }
+    }
+
+    /// Wraps this error with the topic and operation that triggered it, unless it already has
+    /// some context or names the topic itself.
+    pub(crate) fn context(self, topic: &str, operation: &'static str) -> Error {
+        match self {
+            Error::Context { .. }
+            | Error::StaleMessage { .. }
+            | Error::UnexpectedEncoding { .. }
+            | Error::RateLimited(_)
+            | Error::AckTimeout(_)
+            | Error::WaitTimeout(_) => self,
+            source => Error::Context {
+                topic: topic.into(),
+                operation,
+                source: Box::new(source),
+            },
+        }
+    }
+}
+
+/// Adds the topic and operation that failed to the errors of operations on a topic. Every send and
+/// receive path of publishers and subscribers goes through this, see [`Error::Context`].
+pub(crate) trait ResultExt<T> {
+    /// Wraps the error, if any, with the topic and operation that triggered it.
+    fn context(self, topic: &str, operation: &'static str) -> Result<T>;
+}
+
+impl<T> ResultExt<T> for Result<T> {
+    fn context(self, topic: &str, operation: &'static str) -> Result<T> {
+        self.map_err(|e| e.context(topic, operation))
+    }
 }
 
 /// A type alias for results returned by functions in this library.
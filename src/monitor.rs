@@ -0,0 +1,114 @@
+use crate::{subscriber::ReceivedMessage, Result, UntypedSubscriber};
+use prost_reflect::{DynamicMessage, Value};
+use tracing::{instrument, warn};
+
+/// This struct keeps running statistics of a numeric field of the messages received on a topic,
+/// such as the voltage reported by a battery. The field is read through reflection, so this works
+/// on any message type, which is useful to feed monitoring dashboards without writing a consumer
+/// for each type.
+pub struct FieldMonitor {
+    subscriber: UntypedSubscriber,
+    field_path: Vec<String>,
+    stats: FieldStats,
+    /// Whether a message without a numeric value for the field was already reported, so a topic
+    /// of the wrong type doesn't flood the logs.
+    warned_not_numeric: bool,
+}
+
+/// Summary statistics of the values of a field seen by a [`FieldMonitor`]. All values are `NaN`
+/// until the first value is seen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FieldStats {
+    pub count: u64,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+impl Default for FieldStats {
+    fn default() -> Self {
+        FieldStats {
+            count: 0,
+            min: f64::NAN,
+            max: f64::NAN,
+            mean: f64::NAN,
+        }
+    }
+}
+
+impl FieldMonitor {
+    /// Creates a monitor for a field of the messages received by the given subscriber. The field
+    /// path is a list of field names separated by dots, going through nested messages, like
+    /// `pose.position.x`.
+    #[must_use]
+    pub fn new<S: AsRef<str>>(subscriber: UntypedSubscriber, field_path: S) -> FieldMonitor {
+        FieldMonitor {
+            subscriber,
+            field_path: field_path.as_ref().split('.').map(String::from).collect(),
+            stats: FieldStats::default(),
+            warned_not_numeric: false,
+        }
+    }
+
+    /// Returns the statistics of all the values seen so far.
+    #[must_use]
+    pub fn stats(&self) -> FieldStats {
+        self.stats
+    }
+
+    /// This function blocks until a message is received on the subscriber, and updates the
+    /// statistics with the value of the field in it. Returns that value, or `None` if the message
+    /// doesn't have the field or it isn't numeric, in which case the statistics are left as-is. A
+    /// warning is logged the first time this happens. Call this in a loop to keep the statistics
+    /// up to date.
+    ///
+    /// # Errors
+    /// This function will return an error if the message cannot be received or decoded. See
+    /// [`UntypedSubscriber::recv`] for details.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn recv(&mut self) -> Result<Option<f64>> {
+        let ReceivedMessage { message, .. } = self.subscriber.recv().await?;
+        let Some(value) = numeric_field(&message, &self.field_path) else {
+            if !self.warned_not_numeric {
+                self.warned_not_numeric = true;
+                warn!(
+                    msg = "field_not_numeric",
+                    topic = self.subscriber.topic(),
+                    field = self.field_path.join("."),
+                );
+            }
+            return Ok(None);
+        };
+        let stats = &mut self.stats;
+        if stats.count == 0 {
+            stats.min = value;
+            stats.max = value;
+            stats.mean = value;
+        } else {
+            stats.min = stats.min.min(value);
+            stats.max = stats.max.max(value);
+        }
+        stats.count += 1;
+        #[allow(clippy::cast_precision_loss)]
+        let count = stats.count as f64;
+        stats.mean += (value - stats.mean) / count;
+        Ok(Some(value))
+    }
+}
+
+/// Returns the value of the field at the given path as a number, if it exists and is numeric.
+#[allow(clippy::cast_precision_loss)]
+fn numeric_field(message: &DynamicMessage, field_path: &[String]) -> Option<f64> {
+    let (name, rest) = field_path.split_first()?;
+    let value = message.get_field_by_name(name)?;
+    match (value.as_ref(), rest.is_empty()) {
+        (Value::Message(nested), false) => numeric_field(nested, rest),
+        (Value::I32(v), true) => Some(f64::from(*v)),
+        (Value::U32(v), true) => Some(f64::from(*v)),
+        (Value::I64(v), true) => Some(*v as f64),
+        (Value::U64(v), true) => Some(*v as f64),
+        (Value::F32(v), true) => Some(f64::from(*v)),
+        (Value::F64(v), true) => Some(*v),
+        _ => None,
+    }
+}
@@ -1,12 +1,17 @@
-use crate::{Error, Result};
-use prost_reflect::{DescriptorPool, MessageDescriptor};
+use crate::{DescriptorMerge, Error, Result};
+use prost::Message;
+use prost_reflect::{DescriptorPool, DynamicMessage, Kind, MessageDescriptor, Value};
+use prost_types::{Any, FileDescriptorSet};
+use std::collections::{HashMap, HashSet};
+use tracing::warn;
 
 /// This function searches the provided file descriptors for a message descriptor that matches the
 /// provided type URL.
 ///
 /// # Errors
 /// This function will return an error if the type URL is invalid or if no matching message
-/// descriptor can be found.
+/// descriptor can be found. In the latter case, the error lists the files searched and suggests
+/// the closest type name found in the pools, if any is close enough.
 pub(crate) fn search_file_descriptors(
     file_descriptor_pools: &[DescriptorPool],
     type_url: &str,
@@ -16,17 +21,194 @@ pub(crate) fn search_file_descriptors(
     file_descriptor_pools
         .iter()
         .find_map(|pool| pool.get_message_by_name(message_name))
-        .ok_or_else(|| Error::InvalidTypeUrl(message_name.into()))
+        .ok_or_else(|| Error::TypeNotFound {
+            type_url: type_url.into(),
+            files: searched_files(file_descriptor_pools),
+            suggestion: suggest_message_name(file_descriptor_pools, message_name),
+        })
 }
 
-/// This function parses the provided file descriptor bytes into a set of descriptor pools.
-pub(crate) fn parse_file_descriptors(
-    file_descriptors_bytes: &[Vec<u8>],
-) -> Result<Vec<DescriptorPool>> {
-    Ok(file_descriptors_bytes
+/// Returns the names of the files in the pools, without duplicates, such as well-known types
+/// shared by several pools.
+fn searched_files(file_descriptor_pools: &[DescriptorPool]) -> Vec<String> {
+    let mut seen = HashSet::new();
+    file_descriptor_pools
+        .iter()
+        .flat_map(DescriptorPool::files)
+        .map(|file| file.name().to_string())
+        .filter(|name| seen.insert(name.clone()))
+        .collect()
+}
+
+/// Returns the name of the message in the pools that's most likely meant by a name that wasn't
+/// found, such as one missing its package or with a typo. Names further than a third of their
+/// length in edit distance are not suggested.
+fn suggest_message_name(
+    file_descriptor_pools: &[DescriptorPool],
+    message_name: &str,
+) -> Option<String> {
+    let short_name = message_name.rsplit('.').next().unwrap_or(message_name);
+    let max_distance = (message_name.chars().count() / 3).max(1);
+    file_descriptor_pools
+        .iter()
+        .flat_map(DescriptorPool::all_messages)
+        .filter_map(|message| {
+            // A matching name in another package is the most likely mistake
+            let distance = if message.name() == short_name {
+                0
+            } else {
+                edit_distance(message.full_name(), message_name)
+            };
+            (distance <= max_distance).then(|| (distance, message.full_name().to_string()))
+        })
+        .min()
+        .map(|(_, name)| name)
+}
+
+/// Returns the Levenshtein distance between two strings, counted in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, a_char) in a.chars().enumerate() {
+        current[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+/// This function decodes the message packed in a `google.protobuf.Any`, looking up its type in the
+/// provided descriptor pools. This is useful to inspect or serialize `Any` fields of messages
+/// received on typed subscribers, which are otherwise left undecoded. See
+/// [`Node::unpack_any`](crate::Node::unpack_any) to use the descriptors of a node instead.
+///
+/// # Errors
+/// This function will return an error if the type URL of the `Any` is invalid or not found in the
+/// descriptor pools, or if the packed message cannot be decoded.
+pub fn unpack_any(any: &Any, file_descriptor_pools: &[DescriptorPool]) -> Result<DynamicMessage> {
+    let message_descriptor = search_file_descriptors(file_descriptor_pools, &any.type_url)?;
+    Ok(DynamicMessage::decode(
+        message_descriptor,
+        any.value.as_slice(),
+    )?)
+}
+
+/// This function builds a message of the given type with every field set to its default value,
+/// including nested messages, so that it serializes with all of its fields. Messages that contain
+/// themselves are only expanded once, oneofs are left unset, and so are well-known types other
+/// than timestamps and durations, since most of them can't be serialized without a value.
+pub(crate) fn message_template(message_descriptor: &MessageDescriptor) -> DynamicMessage {
+    expand_message(message_descriptor, &mut Vec::new())
+}
+
+fn expand_message(
+    message_descriptor: &MessageDescriptor,
+    path: &mut Vec<String>,
+) -> DynamicMessage {
+    let mut message = DynamicMessage::new(message_descriptor.clone());
+    path.push(message_descriptor.full_name().into());
+    for field in message_descriptor.fields() {
+        if field.is_list() || field.is_map() || field.containing_oneof().is_some() {
+            continue;
+        }
+        let Kind::Message(nested) = field.kind() else {
+            continue;
+        };
+        let expandable = if nested.package_name() == "google.protobuf" {
+            matches!(
+                nested.full_name(),
+                "google.protobuf.Timestamp" | "google.protobuf.Duration"
+            )
+        } else {
+            !path.iter().any(|name| name == nested.full_name())
+        };
+        if expandable {
+            message.set_field(&field, Value::Message(expand_message(&nested, path)));
+        }
+    }
+    path.pop();
+    message
+}
+
+/// This function parses the provided file descriptor bytes into a set of descriptor pools. Each
+/// file descriptor set is parsed on its own, and the ones that fail to parse are logged and
+/// skipped, so a single invalid set doesn't make the types of all the others unavailable.
+pub(crate) fn parse_file_descriptors(file_descriptors_bytes: &[Vec<u8>]) -> Vec<DescriptorPool> {
+    file_descriptors_bytes
         .iter()
-        .map(|b| DescriptorPool::decode(&b[..]))
-        .collect::<Result<Vec<_>, _>>()?)
+        .enumerate()
+        .filter_map(|(index, b)| match DescriptorPool::decode(&b[..]) {
+            Ok(pool) => Some(pool),
+            Err(e) => {
+                warn!(msg = "invalid_file_descriptors", index = index, error = %e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// This function merges the provided file descriptor sets into a single one. Files that appear in
+/// more than one set (usually well-known types) are only included once, taking the first one.
+pub(crate) fn merge_file_descriptors(file_descriptors_bytes: &[Vec<u8>]) -> Result<Vec<u8>> {
+    let mut seen_files = HashSet::new();
+    let mut merged = FileDescriptorSet::default();
+    for bytes in file_descriptors_bytes {
+        for file in FileDescriptorSet::decode(&bytes[..])?.file {
+            if seen_files.insert(file.name().to_string()) {
+                merged.file.push(file);
+            }
+        }
+    }
+    Ok(merged.encode_to_vec())
+}
+
+/// This function applies a merge strategy to the provided file descriptor sets, returning the sets
+/// to search for types in order. With [`DescriptorMerge::ErrorOnConflict`], all sets are merged
+/// into a single one, which is checked to be consistent.
+///
+/// # Errors
+/// This function will return an error if the strategy is [`DescriptorMerge::ErrorOnConflict`] and
+/// any of the sets cannot be decoded, a file appears in two sets with different contents, or two
+/// files define the same type.
+pub(crate) fn resolve_file_descriptors(
+    mut file_descriptors_bytes: Vec<Vec<u8>>,
+    merge: DescriptorMerge,
+) -> Result<Vec<Vec<u8>>> {
+    match merge {
+        DescriptorMerge::FirstWins => Ok(file_descriptors_bytes),
+        DescriptorMerge::LastWins => {
+            file_descriptors_bytes.reverse();
+            Ok(file_descriptors_bytes)
+        }
+        DescriptorMerge::ErrorOnConflict => {
+            let mut files = HashMap::new();
+            let mut merged = FileDescriptorSet::default();
+            for bytes in &file_descriptors_bytes {
+                for file in FileDescriptorSet::decode(&bytes[..])?.file {
+                    match files.get(file.name()) {
+                        Some(existing) if *existing == file => {}
+                        Some(_) => return Err(Error::ConflictingDescriptors(file.name().into())),
+                        None => {
+                            files.insert(file.name().to_string(), file.clone());
+                            merged.file.push(file);
+                        }
+                    }
+                }
+            }
+            // Building a pool checks that no type is defined by two different files
+            DescriptorPool::from_file_descriptor_set(merged.clone())?;
+            Ok(vec![merged.encode_to_vec()])
+        }
+    }
+}
+
+/// Returns the type URL of the message with the given full name, as used in message headers.
+pub(crate) fn type_url(message_name: &str) -> String {
+    format!("type.googleapis.com/{message_name}")
 }
 
 fn message_name_from_type_url(type_url: &str) -> Result<&str> {
@@ -35,3 +217,203 @@ fn message_name_from_type_url(type_url: &str) -> Result<&str> {
         .nth(1)
         .ok_or_else(|| Error::InvalidTypeUrl(type_url.into()))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        edit_distance, parse_file_descriptors, resolve_file_descriptors, search_file_descriptors,
+        suggest_message_name,
+    };
+    use crate::{DescriptorMerge, Error};
+    use prost::Message;
+    use prost_reflect::DescriptorPool;
+    use prost_types::{
+        field_descriptor_proto::{Label, Type},
+        DescriptorProto, FieldDescriptorProto, FileDescriptorProto, FileDescriptorSet,
+    };
+
+    /// Builds a file defining messages with the given string fields.
+    fn file(name: &str, package: &str, messages: &[(&str, &[&str])]) -> FileDescriptorProto {
+        FileDescriptorProto {
+            name: Some(name.into()),
+            package: Some(package.into()),
+            syntax: Some("proto3".into()),
+            message_type: messages
+                .iter()
+                .map(|(message, fields)| DescriptorProto {
+                    name: Some((*message).into()),
+                    field: fields
+                        .iter()
+                        .zip(1..)
+                        .map(|(field, number)| FieldDescriptorProto {
+                            name: Some((*field).into()),
+                            number: Some(number),
+                            label: Some(Label::Optional.into()),
+                            r#type: Some(Type::String.into()),
+                            ..FieldDescriptorProto::default()
+                        })
+                        .collect(),
+                    ..DescriptorProto::default()
+                })
+                .collect(),
+            ..FileDescriptorProto::default()
+        }
+    }
+
+    fn pool(files: Vec<FileDescriptorProto>) -> DescriptorPool {
+        DescriptorPool::from_file_descriptor_set(FileDescriptorSet { file: files }).unwrap()
+    }
+
+    #[test]
+    fn edit_distance_counts_characters() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("abc", "abc"), 0);
+        assert_eq!(edit_distance("café", "cafe"), 1);
+        assert_eq!(edit_distance("日本語", "日本"), 1);
+        assert_eq!(edit_distance("ロボット", "ロボツト"), 1);
+    }
+
+    #[test]
+    fn suggests_names_from_other_packages_and_typos() {
+        let pools = [pool(vec![file(
+            "geometry.proto",
+            "geometry",
+            &[("Pose", &[]), ("Twist", &[])],
+        )])];
+        assert_eq!(
+            suggest_message_name(&pools, "robotica.Pose").as_deref(),
+            Some("geometry.Pose")
+        );
+        assert_eq!(
+            suggest_message_name(&pools, "geometry.Poze").as_deref(),
+            Some("geometry.Pose")
+        );
+        assert_eq!(suggest_message_name(&pools, "sensors.Imu"), None);
+    }
+
+    #[test]
+    fn suggestion_threshold_counts_characters() {
+        let pools = [pool(vec![file("a.proto", "a", &[("Bc", &[])])])];
+        assert_eq!(
+            suggest_message_name(&pools, "a.Bcé").as_deref(),
+            Some("a.Bc")
+        );
+        // Three edits away, which is within a third of the name in bytes but not in characters
+        assert_eq!(suggest_message_name(&pools, "a.Bcééé"), None);
+    }
+
+    #[test]
+    fn missing_types_list_the_files_searched() {
+        let common = file("common.proto", "common", &[("Empty", &[])]);
+        let pools = [
+            pool(vec![common.clone(), file("a.proto", "a", &[("Pose", &[])])]),
+            pool(vec![common, file("b.proto", "b", &[("Twist", &[])])]),
+        ];
+        let Err(Error::TypeNotFound {
+            type_url,
+            mut files,
+            suggestion,
+        }) = search_file_descriptors(&pools, "type.googleapis.com/c.Pose")
+        else {
+            panic!("expected the type not to be found");
+        };
+        files.sort();
+        assert_eq!(type_url, "type.googleapis.com/c.Pose");
+        assert_eq!(files, ["a.proto", "b.proto", "common.proto"]);
+        assert_eq!(suggestion.as_deref(), Some("a.Pose"));
+
+        assert!(matches!(
+            search_file_descriptors(&pools, "b.Twist"),
+            Err(Error::InvalidTypeUrl(_))
+        ));
+        let found = search_file_descriptors(&pools, "type.googleapis.com/b.Twist").unwrap();
+        assert_eq!(found.full_name(), "b.Twist");
+    }
+
+    /// Returns the fields of the given type in the first set that defines it, once merged.
+    fn resolved_fields(sets: Vec<Vec<u8>>, merge: DescriptorMerge) -> Vec<String> {
+        let resolved = resolve_file_descriptors(sets, merge).unwrap();
+        let pools = parse_file_descriptors(&resolved);
+        search_file_descriptors(&pools, "type.googleapis.com/a.Pose")
+            .unwrap()
+            .fields()
+            .map(|field| field.name().to_string())
+            .collect()
+    }
+
+    /// Two sets with different versions of the same file.
+    fn conflicting_sets() -> Vec<Vec<u8>> {
+        [&["x"][..], &["x", "y"][..]]
+            .into_iter()
+            .map(|fields| {
+                FileDescriptorSet {
+                    file: vec![file("a.proto", "a", &[("Pose", fields)])],
+                }
+                .encode_to_vec()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn first_wins_uses_the_first_definition() {
+        assert_eq!(
+            resolved_fields(conflicting_sets(), DescriptorMerge::FirstWins),
+            ["x"]
+        );
+    }
+
+    #[test]
+    fn last_wins_uses_the_last_definition() {
+        assert_eq!(
+            resolved_fields(conflicting_sets(), DescriptorMerge::LastWins),
+            ["x", "y"]
+        );
+    }
+
+    #[test]
+    fn error_on_conflict_rejects_conflicting_files() {
+        assert!(matches!(
+            resolve_file_descriptors(conflicting_sets(), DescriptorMerge::ErrorOnConflict),
+            Err(Error::ConflictingDescriptors(name)) if name == "a.proto"
+        ));
+    }
+
+    #[test]
+    fn error_on_conflict_merges_identical_files() {
+        let set = FileDescriptorSet {
+            file: vec![file("a.proto", "a", &[("Pose", &["x"])])],
+        }
+        .encode_to_vec();
+        let other = FileDescriptorSet {
+            file: vec![file("b.proto", "b", &[("Twist", &[])])],
+        }
+        .encode_to_vec();
+        let resolved = resolve_file_descriptors(
+            vec![set.clone(), set, other],
+            DescriptorMerge::ErrorOnConflict,
+        )
+        .unwrap();
+        assert_eq!(resolved.len(), 1);
+        let merged = FileDescriptorSet::decode(&resolved[0][..]).unwrap();
+        let names: Vec<&str> = merged.file.iter().map(|file| file.name()).collect();
+        assert_eq!(names, ["a.proto", "b.proto"]);
+    }
+
+    #[test]
+    fn error_on_conflict_rejects_types_defined_twice() {
+        let sets = [("a.proto", "x"), ("other.proto", "y")]
+            .into_iter()
+            .map(|(name, field)| {
+                FileDescriptorSet {
+                    file: vec![file(name, "a", &[("Pose", &[field])])],
+                }
+                .encode_to_vec()
+            })
+            .collect();
+        assert!(matches!(
+            resolve_file_descriptors(sets, DescriptorMerge::ErrorOnConflict),
+            Err(Error::ProtobufDescriptorRead(_))
+        ));
+    }
+}
@@ -1,55 +1,586 @@
 use crate::{
+    clock::Clock,
+    codec::{Codec, ProstCodec},
+    info::TopicInfoQueryable,
     proto::{parse_file_descriptors, search_file_descriptors},
-    Result,
+    subscriber::HEADER_ATTACHMENT_KEY,
+    subscription::{acked_key, unicast_key},
+    Error, Result, ResultExt,
 };
 use prost::Message;
 use prost_reflect::{DynamicMessage, MessageDescriptor};
 use prost_types::Timestamp;
 use robotica_types::Header;
 use serde_json::Value;
-use std::{marker::PhantomData, time::SystemTime};
-use tracing::instrument;
-use zenoh::prelude::r#async::*;
+use std::{
+    collections::{HashMap, HashSet},
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tracing::{info, instrument, warn};
+use zenoh::{
+    buffers::ZBuf,
+    prelude::r#async::*,
+    publication::{CongestionControl, Priority},
+    sample::{Attachment, AttachmentBuilder},
+};
+
+/// The zenoh encoding set on every sample sent by robotica publishers, so generic zenoh tools can
+/// tell their payload is framed by robotica, with a header in front of the message. Subscribers can
+/// require it with
+/// [`SubscriberOptions::verify_encoding`](crate::SubscriberOptions::verify_encoding).
+pub const SAMPLE_ENCODING: &str = "application/robotica+protobuf";
 
 /// This struct represents a publisher to a topic. This will require you send messages of type M.
 /// Note that you cannot create this struct directly, but must instead fetch one from a
 /// [`Node`](crate::Node).
-pub struct Publisher<'a, M: prost::Message + prost::Name> {
+pub struct Publisher<'a, M, C: Codec<M> = ProstCodec> {
+    session: &'a Session,
+    topic: String,
     publisher: zenoh::publication::Publisher<'a>,
-    _phantom: PhantomData<M>,
+    clock: Arc<dyn Clock>,
+    info_queryable: TopicInfoQueryable<'a>,
+    rate_limiter: Option<Mutex<RateLimiter>>,
+    in_flight: InFlight,
+    congestion_control: Option<CongestionControl>,
+    header_in_attachment: bool,
+    message_ttl: Option<Duration>,
+    log_traffic: bool,
+    _phantom: PhantomData<(M, C)>,
 }
 
-impl<'a, M: prost::Message + prost::Name> Publisher<'a, M> {
+impl<'a, M, C: Codec<M>> Publisher<'a, M, C> {
     pub(crate) async fn new_from_session<S: AsRef<str>>(
         session: &'a Session,
         topic: S,
+        clock: Arc<dyn Clock>,
+        options: &PublisherOptions,
+        log_traffic: bool,
     ) -> Result<Self> {
-        let publisher = session
-            .declare_publisher(topic.as_ref().to_string())
-            .res()
-            .await?;
+        let publisher = options.declare(session, topic.as_ref()).await?;
+        let info_queryable =
+            TopicInfoQueryable::new(session, topic.as_ref(), C::type_url(), clock.clone()).await?;
         Ok(Publisher {
+            session,
+            topic: topic.as_ref().into(),
             publisher,
+            clock,
+            info_queryable,
+            rate_limiter: options.rate_limiter(),
+            in_flight: InFlight::new(options.max_in_flight),
+            congestion_control: options.resolved_congestion_control(),
+            header_in_attachment: options.header_in_attachment,
+            message_ttl: options.message_ttl,
+            log_traffic,
             _phantom: PhantomData,
         })
     }
 
+    /// Returns the zenoh key expression this publisher sends messages on. This is useful to
+    /// correlate robotica channels with raw zenoh tools.
+    #[must_use]
+    pub fn key_expr(&self) -> &str {
+        self.publisher.key_expr().as_str()
+    }
+
+    /// This function waits until every message being sent by this publisher, such as from other
+    /// tasks sharing it, has been queued for transmission by zenoh. With
+    /// [`CongestionControl::Block`], which [`PublisherOptions::max_in_flight`] implies, this waits
+    /// for room in zenoh's queue when the network is congested. Call this before a controlled
+    /// shutdown to make sure the last messages aren't lost. Zenoh sends the messages it queued on
+    /// its own shortly after, but doesn't let us wait for them to leave the process, so keep the
+    /// node alive for a moment after flushing if the last messages matter.
+    pub async fn flush(&self) {
+        self.in_flight.wait_idle().await;
+    }
+
+    /// This function returns whether any subscriber currently matches the topic we're publishing
+    /// to, across the whole system. Use this to skip producing messages that are expensive to
+    /// compute when nobody is listening. Note zenoh only reports whether there are matching
+    /// subscribers, not how many.
+    ///
+    /// # Errors
+    /// This function will return an error if zenoh fails to report the matching status.
+    pub async fn has_subscribers(&self) -> Result<bool> {
+        Ok(self
+            .publisher
+            .matching_status()
+            .res()
+            .await?
+            .matching_subscribers())
+    }
+
+    /// This function returns how many bytes the message would take on the wire if sent now with
+    /// [`Publisher::send`], header included, without sending it. This is useful to budget
+    /// bandwidth on constrained links, such as deciding whether to downsample data before sending
+    /// it. Note zenoh adds some framing of its own, which isn't accounted for.
+    #[must_use]
+    pub fn encoded_len(&self, message: &M) -> usize {
+        encoded_len(
+            C::encoded_len(message),
+            &self.header(self.clock.now()),
+            self.header_in_attachment,
+        )
+    }
+
     /// This function sends a message to the topic we're publishing to. Messages will be received
-    /// by all subscribers to this topic.
+    /// by all subscribers to this topic. If the publisher has a maximum rate, messages sent faster
+    /// than it are silently dropped. Use [`Publisher::try_send`] to know whether they were.
     ///
     /// # Errors
     /// This function will return an error if the message cannot be sent for any reason. In
     /// practice, this means there was an error returned by zenoh when sending down the channel.
+    /// The error is wrapped in [`Error::Context`], naming the topic.
     #[instrument(level = "trace", skip_all)]
     pub async fn send(&self, message: &M) -> Result<()> {
+        self.try_send(message).await?;
+        Ok(())
+    }
+
+    /// This function sends a message to the topic we're publishing to, same as
+    /// [`Publisher::send`], and returns whether it was sent. It's only dropped if sending it would
+    /// exceed the maximum rate of the publisher, see [`PublisherOptions::max_rate_hz`].
+    ///
+    /// # Errors
+    /// This function will return an error if the message cannot be sent for any reason. In
+    /// practice, this means there was an error returned by zenoh when sending down the channel.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn try_send(&self, message: &M) -> Result<bool> {
+        self.send_sample(message, self.header(self.clock.now()), Route::Topic(None))
+            .await
+    }
+
+    /// This function sends a message to the topic we're publishing to, same as
+    /// [`Publisher::send`], but with the given header fields instead of the ones the publisher
+    /// fills in. This is useful to stamp a message with the time its data was captured rather than
+    /// the time it was sent, for instance. Returns whether the message was sent, see
+    /// [`Publisher::try_send`].
+    ///
+    /// # Errors
+    /// This function will return an error if the message cannot be sent for any reason. In
+    /// practice, this means there was an error returned by zenoh when sending down the channel.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn send_with_header(&self, message: &M, overrides: &HeaderOverrides) -> Result<bool> {
+        let timestamp = overrides.timestamp.unwrap_or_else(|| self.clock.now());
         let header = Header {
-            message_timestamp: Some(Timestamp::from(SystemTime::now())),
-            type_url: M::type_url(),
+            group_id: overrides.group_id.unwrap_or(0),
+            attributes: overrides.attributes.clone(),
+            ..self.header(timestamp)
         };
-        let mut buf = header.encode_length_delimited_to_vec();
-        buf.extend_from_slice(&message.encode_length_delimited_to_vec());
-        self.publisher.put(buf).res().await?;
-        Ok(())
+        self.send_sample(message, header, Route::Topic(None)).await
+    }
+
+    /// This function sends a message to the topic we're publishing to, same as
+    /// [`Publisher::send`], but only if any subscriber currently matches the topic. Returns
+    /// whether the message was sent, which it also isn't if it exceeds the maximum rate of the
+    /// publisher. Combine it with [`Publisher::has_subscribers`] to also skip producing the message
+    /// when nobody is listening.
+    ///
+    /// # Errors
+    /// This function will return an error if zenoh fails to report the matching status, or if the
+    /// message cannot be sent. See [`Publisher::send`] for details.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn send_if_subscribed(&self, message: &M) -> Result<bool> {
+        if !self
+            .has_subscribers()
+            .await
+            .context(self.key_expr(), "send")?
+        {
+            return Ok(false);
+        }
+        self.try_send(message).await
+    }
+
+    /// This function sends a message to the topic we're publishing to, same as
+    /// [`Publisher::send`], but with the given priority instead of the publisher's. This lets
+    /// urgent messages, like an emergency stop, get ahead of other data in zenoh's queues. Returns
+    /// whether the message was sent, see [`Publisher::try_send`].
+    ///
+    /// # Errors
+    /// This function will return an error if the message cannot be sent for any reason. In
+    /// practice, this means there was an error returned by zenoh when sending down the channel.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn send_with_priority(&self, message: &M, priority: Priority) -> Result<bool> {
+        let header = self.header(self.clock.now());
+        self.send_sample(message, header, Route::Topic(Some(priority)))
+            .await
+    }
+
+    /// Returns the header of a message sent by this publisher at the given time.
+    fn header(&self, timestamp: SystemTime) -> Header {
+        Header {
+            ttl: self.message_ttl.and_then(|ttl| ttl.try_into().ok()),
+            ..new_header(C::type_url(), timestamp)
+        }
+    }
+
+    /// Sends a message with the given header, unless sending it would exceed the maximum rate of
+    /// the publisher. Returns whether it was sent. Every put of the publisher goes through here.
+    async fn send_sample(&self, message: &M, header: Header, route: Route<'_>) -> Result<bool> {
+        self.put_sample(message, header, route)
+            .await
+            .context(self.key_expr(), "send")
+    }
+
+    /// Puts a message with the given header, see [`Publisher::send_sample`].
+    async fn put_sample(&self, message: &M, header: Header, route: Route<'_>) -> Result<bool> {
+        if !within_rate(self.rate_limiter.as_ref()) {
+            return Ok(false);
+        }
+        let (payload, attachment) =
+            encode_sample(C::encode(message), &header, self.header_in_attachment);
+        self.log_sent(payload.len());
+        let _permit = self.in_flight.acquire().await;
+        match route {
+            Route::Topic(priority) => {
+                self.info_queryable.set_latest_payload(
+                    payload.clone(),
+                    &header,
+                    attachment.is_none(),
+                );
+                let mut put = self.publisher.put(sample_value(payload));
+                if let Some(priority) = priority {
+                    put = put.priority(priority);
+                }
+                if let Some(attachment) = attachment {
+                    put = put.with_attachment(attachment);
+                }
+                put.res().await?;
+            }
+            Route::Node(target_id) => {
+                let mut put = self
+                    .session
+                    .put(unicast_key(target_id, &self.topic), sample_value(payload));
+                if let Some(congestion_control) = self.congestion_control {
+                    put = put.congestion_control(congestion_control);
+                }
+                if let Some(attachment) = attachment {
+                    put = put.with_attachment(attachment);
+                }
+                put.res().await?;
+            }
+        }
+        Ok(true)
+    }
+
+    /// This function sends a message to the subscribers of a single node on the topic we're
+    /// publishing to. The target is identified by its node ID, as returned by
+    /// [`Node::id`](crate::Node::id). Other nodes subscribed to the topic don't receive the
+    /// message, and it is not reported as the latest message on the topic. Returns whether the
+    /// message was sent, see [`Publisher::try_send`].
+    ///
+    /// # Errors
+    /// This function will return an error if the message cannot be sent for any reason. In
+    /// practice, this means there was an error returned by zenoh when sending down the channel.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn send_to<S: AsRef<str>>(&self, target_id: S, message: &M) -> Result<bool> {
+        let header = self.header(self.clock.now());
+        self.send_sample(message, header, Route::Node(target_id.as_ref()))
+            .await
+    }
+
+    /// This function sends a message to the topic we're publishing to and waits for the nodes
+    /// subscribed to it to acknowledge receiving it, for up to `timeout`. Returns the IDs of the
+    /// nodes that acknowledged the message, as returned by [`Node::id`](crate::Node::id). Use this
+    /// for commands that must not be lost, retrying if needed, which gives at-least-once delivery.
+    /// Messages are acknowledged once queued by the receiving node, so a paused subscriber still
+    /// acknowledges messages it discards. Subscribers from older versions of robotica don't receive
+    /// these messages at all. The header is always sent in front of the message, even if the
+    /// publisher was configured with [`PublisherOptions::header_in_attachment`].
+    ///
+    /// # Errors
+    /// This function will return [`Error::RateLimited`] if sending the message would exceed the
+    /// maximum rate of the publisher, and an error if no node acknowledges the message before the
+    /// timeout, or if the message cannot be sent. In practice, the latter means there was an error
+    /// returned by zenoh when sending the query.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn send_acked(&self, message: &M, timeout: Duration) -> Result<HashSet<String>> {
+        self.query_acks(message, timeout)
+            .await
+            .context(self.key_expr(), "send")
+    }
+
+    /// Sends a message as a query and collects the IDs of the nodes replying to it, see
+    /// [`Publisher::send_acked`].
+    async fn query_acks(&self, message: &M, timeout: Duration) -> Result<HashSet<String>> {
+        if !within_rate(self.rate_limiter.as_ref()) {
+            return Err(Error::RateLimited(self.topic.clone()));
+        }
+        let header = self.header(self.clock.now());
+        let payload = frame(&header, &C::encode(message));
+        self.log_sent(payload.len());
+        self.info_queryable
+            .set_latest_payload(Arc::new(payload.clone()), &header, true);
+        let replies = self
+            .session
+            .get(acked_key(&self.topic))
+            .with_value(sample_value(payload))
+            .consolidation(ConsolidationMode::None)
+            .timeout(timeout)
+            .res()
+            .await?;
+        let mut acks = HashSet::new();
+        while let Ok(reply) = replies.recv_async().await {
+            match reply.sample {
+                Ok(_) => {
+                    acks.insert(reply.replier_id.to_string());
+                }
+                Err(value) => warn!(msg = "invalid_ack", topic = self.topic, error = %value),
+            }
+        }
+        if acks.is_empty() {
+            return Err(Error::AckTimeout(self.topic.clone()));
+        }
+        Ok(acks)
+    }
+
+    /// Turns this publisher into a [`ChangePublisher`], which only sends messages that differ
+    /// significantly from the last one it sent. `is_significant` is called with the last message
+    /// sent and the new one, and returns whether the new one should be sent. This cuts bandwidth
+    /// on slowly-changing topics, such as battery levels or temperatures.
+    #[must_use]
+    pub fn on_change<F: Fn(&M, &M) -> bool>(self, is_significant: F) -> ChangePublisher<'a, M, F, C>
+    where
+        M: Clone,
+    {
+        ChangePublisher {
+            publisher: self,
+            is_significant,
+            last_sent: Mutex::new(None),
+        }
+    }
+
+    fn log_sent(&self, bytes: usize) {
+        if self.log_traffic {
+            info!(
+                msg = "message_sent",
+                topic = self.topic,
+                type_url = C::type_url(),
+                bytes = bytes,
+            );
+        }
+    }
+}
+
+/// Header fields to set on a message sent with [`Publisher::send_with_header`], instead of the
+/// ones the publisher fills in. Fields left unset keep the values [`Publisher::send`] would use.
+/// The type URL can't be overridden, since subscribers rely on it to decode the message.
+#[derive(Clone, Debug, Default)]
+pub struct HeaderOverrides {
+    timestamp: Option<SystemTime>,
+    group_id: Option<u64>,
+    attributes: HashMap<String, String>,
+}
+
+impl HeaderOverrides {
+    /// Create header overrides that don't override any field
+    #[must_use]
+    pub fn new() -> HeaderOverrides {
+        Self::default()
+    }
+
+    /// Sets the timestamp of the message. Defaults to the current time of the node's clock.
+    #[must_use]
+    pub fn timestamp(mut self, timestamp: SystemTime) -> HeaderOverrides {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Sets the group ID of the message, used to correlate messages sent together on different
+    /// topics. Defaults to zero, meaning the message isn't part of a group. See
+    /// [`PublisherGroup`].
+    #[must_use]
+    pub fn group_id(mut self, group_id: u64) -> HeaderOverrides {
+        self.group_id = Some(group_id);
+        self
+    }
+
+    /// Adds an attribute to the header of the message, such as the frame or sensor the data comes
+    /// from. Subscribers can filter messages by attribute without decoding them, see
+    /// [`SubscriberOptions::attribute_filter`](crate::SubscriberOptions::attribute_filter).
+    #[must_use]
+    pub fn attribute<S: AsRef<str>, S2: AsRef<str>>(
+        mut self,
+        key: S,
+        value: S2,
+    ) -> HeaderOverrides {
+        self.attributes
+            .insert(key.as_ref().into(), value.as_ref().into());
+        self
+    }
+}
+
+/// Returns the header of a message of the given type sent at the given time, with no other fields
+/// set.
+pub(crate) fn new_header(type_url: String, timestamp: SystemTime) -> Header {
+    Header {
+        message_timestamp: Some(Timestamp::from(timestamp)),
+        type_url,
+        ..Header::default()
+    }
+}
+
+/// Returns the length of an encoded message of `message_len` bytes sent along with its header, as
+/// done by [`encode_sample`].
+fn encoded_len(message_len: usize, header: &Header, header_in_attachment: bool) -> usize {
+    if header_in_attachment {
+        return HEADER_ATTACHMENT_KEY.len() + header.encoded_len() + message_len;
+    }
+    header.encoded_len()
+        + prost::length_delimiter_len(header.encoded_len())
+        + message_len
+        + prost::length_delimiter_len(message_len)
+}
+
+/// Puts an encoded message and its header into the payload and attachment of a zenoh sample. The
+/// header is sent as an attachment if `header_in_attachment` is set, leaving the payload to the
+/// message alone, and in front of the message otherwise.
+fn encode_sample(
+    message: Vec<u8>,
+    header: &Header,
+    header_in_attachment: bool,
+) -> (Arc<Vec<u8>>, Option<Attachment>) {
+    if !header_in_attachment {
+        return (Arc::new(frame(header, &message)), None);
+    }
+    let mut attachment = AttachmentBuilder::new();
+    attachment.insert(HEADER_ATTACHMENT_KEY, &header.encode_to_vec());
+    (Arc::new(message), Some(attachment.build()))
+}
+
+/// Wraps the payload of a robotica message into a zenoh value, tagged with [`SAMPLE_ENCODING`].
+pub(crate) fn sample_value<P: Into<ZBuf>>(payload: P) -> Value {
+    Value::new(payload.into()).encoding(Encoding::from(SAMPLE_ENCODING))
+}
+
+/// Puts an encoded message after its header, both length-delimited, as sent on the wire.
+pub(crate) fn frame(header: &Header, message: &[u8]) -> Vec<u8> {
+    let mut buf = header.encode_length_delimited_to_vec();
+    let len = u64::try_from(message.len()).expect("usize should always fit in u64");
+    prost::encoding::encode_varint(len, &mut buf);
+    buf.extend_from_slice(message);
+    buf
+}
+
+/// Where a typed publisher sends a message.
+enum Route<'r> {
+    /// To every subscriber of the topic, with the given priority instead of the publisher's
+    Topic(Option<Priority>),
+    /// To the subscribers of the node with the given ID only, see [`Publisher::send_to`]
+    Node(&'r str),
+}
+
+/// This struct represents a set of publishers whose messages are sent together, such as the left
+/// and right images of a stereo camera. All messages sent in one call share the same timestamp and
+/// group ID in their headers, so subscribers can correlate them. Note that you cannot create this
+/// struct directly, but must instead fetch one from a [`Node`](crate::Node).
+#[allow(clippy::module_name_repetitions)]
+pub struct PublisherGroup<'a, M, C: Codec<M> = ProstCodec> {
+    publishers: Vec<Publisher<'a, M, C>>,
+    clock: Arc<dyn Clock>,
+    next_group_id: AtomicU64,
+}
+
+impl<'a, M, C: Codec<M>> PublisherGroup<'a, M, C> {
+    pub(crate) fn new(publishers: Vec<Publisher<'a, M, C>>, clock: Arc<dyn Clock>) -> Self {
+        PublisherGroup {
+            publishers,
+            clock,
+            next_group_id: AtomicU64::new(1),
+        }
+    }
+
+    /// This function sends one message to each topic of the group, in the order the topics were
+    /// given when creating the group. Messages are stamped with the same timestamp and a group ID
+    /// unique to this call. Returns whether every message was sent. Messages exceeding the maximum
+    /// rate of their publisher are dropped, see [`Publisher::try_send`], so with rate limits the
+    /// group may be sent partially.
+    ///
+    /// # Errors
+    /// This function will return an error if the number of messages doesn't match the number of
+    /// topics in the group, or if any of the messages cannot be sent. In the latter case, messages
+    /// to topics earlier in the group may have been sent already.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn send_all(&self, messages: &[&M]) -> Result<bool> {
+        if messages.len() != self.publishers.len() {
+            return Err(Error::GroupSizeMismatch {
+                expected: self.publishers.len(),
+                actual: messages.len(),
+            });
+        }
+        let timestamp = self.clock.now();
+        let group_id = self.next_group_id.fetch_add(1, Ordering::Relaxed);
+        let mut all_sent = true;
+        for (publisher, message) in self.publishers.iter().zip(messages) {
+            let header = Header {
+                group_id,
+                ..publisher.header(timestamp)
+            };
+            all_sent &= publisher
+                .send_sample(message, header, Route::Topic(None))
+                .await?;
+        }
+        Ok(all_sent)
+    }
+}
+
+/// This struct represents a publisher that only sends messages that changed significantly since the
+/// last message it sent, as decided by a user-provided comparator. The first message is always
+/// sent. Note that you cannot create this struct directly, but must instead get one from
+/// [`Publisher::on_change`].
+#[allow(clippy::module_name_repetitions)]
+pub struct ChangePublisher<'a, M: Clone, F: Fn(&M, &M) -> bool, C: Codec<M> = ProstCodec> {
+    publisher: Publisher<'a, M, C>,
+    is_significant: F,
+    last_sent: Mutex<Option<M>>,
+}
+
+impl<'a, M: Clone, F: Fn(&M, &M) -> bool, C: Codec<M>> ChangePublisher<'a, M, F, C> {
+    /// This function sends a message to the topic we're publishing to, same as
+    /// [`Publisher::try_send`], unless the change since the last message sent isn't significant.
+    /// Returns whether the message was sent. Messages dropped for exceeding the maximum rate of
+    /// the publisher don't count as sent, so later messages are still compared against the last
+    /// one that was.
+    ///
+    /// # Errors
+    /// This function will return an error if the message cannot be sent for any reason. In
+    /// practice, this means there was an error returned by zenoh when sending down the channel.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn send(&self, message: &M) -> Result<bool> {
+        let significant = self
+            .last_sent
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_ref()
+            .map_or(true, |last_sent| (self.is_significant)(last_sent, message));
+        if !significant || !self.publisher.try_send(message).await? {
+            return Ok(false);
+        }
+        *self
+            .last_sent
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(message.clone());
+        Ok(true)
+    }
+
+    /// Forgets the last message sent, so the next message is sent regardless of how much it
+    /// changed. Use this to force an update after subscribers may have missed messages.
+    pub fn reset(&self) {
+        *self
+            .last_sent
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = None;
+    }
+
+    /// Returns the underlying publisher, to send messages without comparing them.
+    #[must_use]
+    pub fn publisher(&self) -> &Publisher<'a, M, C> {
+        &self.publisher
     }
 }
 
@@ -61,6 +592,13 @@ pub struct UntypedPublisher<'a> {
     publisher: zenoh::publication::Publisher<'a>,
     message_descriptor: MessageDescriptor,
     type_url: String,
+    clock: Arc<dyn Clock>,
+    info_queryable: TopicInfoQueryable<'a>,
+    rate_limiter: Option<Mutex<RateLimiter>>,
+    in_flight: InFlight,
+    header_in_attachment: bool,
+    message_ttl: Option<Duration>,
+    log_traffic: bool,
 }
 
 impl<'a> UntypedPublisher<'a> {
@@ -69,43 +607,481 @@ impl<'a> UntypedPublisher<'a> {
         topic: S,
         type_url: S2,
         file_descriptors_bytes: &[Vec<u8>],
+        clock: Arc<dyn Clock>,
+        options: &PublisherOptions,
+        log_traffic: bool,
     ) -> Result<UntypedPublisher<'a>> {
         let type_url = type_url.as_ref();
-        let file_descriptor_pools = parse_file_descriptors(file_descriptors_bytes)?;
+        let file_descriptor_pools = parse_file_descriptors(file_descriptors_bytes);
         let message_descriptor = search_file_descriptors(&file_descriptor_pools, type_url)?;
-        let publisher = session
-            .declare_publisher(topic.as_ref().to_string())
-            .res()
-            .await?;
+        let publisher = options.declare(session, topic.as_ref()).await?;
+        let info_queryable =
+            TopicInfoQueryable::new(session, topic.as_ref(), type_url.into(), clock.clone())
+                .await?;
         Ok(UntypedPublisher {
             publisher,
             message_descriptor,
             type_url: type_url.into(),
+            clock,
+            info_queryable,
+            rate_limiter: options.rate_limiter(),
+            in_flight: InFlight::new(options.max_in_flight),
+            header_in_attachment: options.header_in_attachment,
+            message_ttl: options.message_ttl,
+            log_traffic,
         })
     }
 
+    /// Returns the zenoh key expression this publisher sends messages on. See
+    /// [`Publisher::key_expr`].
+    #[must_use]
+    pub fn key_expr(&self) -> &str {
+        self.publisher.key_expr().as_str()
+    }
+
+    /// This function waits until every message being sent by this publisher has been queued for
+    /// transmission by zenoh. See [`Publisher::flush`].
+    pub async fn flush(&self) {
+        self.in_flight.wait_idle().await;
+    }
+
+    /// This function returns whether any subscriber currently matches the topic we're publishing
+    /// to. See [`Publisher::has_subscribers`].
+    ///
+    /// # Errors
+    /// This function will return an error if zenoh fails to report the matching status.
+    pub async fn has_subscribers(&self) -> Result<bool> {
+        Ok(self
+            .publisher
+            .matching_status()
+            .res()
+            .await?
+            .matching_subscribers())
+    }
+
     /// This function sends a message to the topic we're publishing to. Messages will be received
     /// by all subscribers to this topic. Note we expect a dynamic message as input that will be
-    /// parsed and encoded based on the type URL provided at creation time.
+    /// parsed and encoded based on the type URL provided at creation time. If the publisher has a
+    /// maximum rate, messages sent faster than it are silently dropped. Use
+    /// [`UntypedPublisher::try_send`] to know whether they were.
     ///
     /// # Errors
     /// This function will return an error if the message cannot be sent for any reason. In
     /// practice, this means there was an error returned by zenoh when sending down the channel, or
-    /// an error while attempting to encode the message dynamically.
+    /// an error while attempting to encode the message dynamically. With the `validate` feature,
+    /// this also returns an error if the message violates the protovalidate constraints of its
+    /// type. The error is wrapped in [`Error::Context`], naming the topic.
     #[instrument(level = "trace", skip_all)]
     pub async fn send(&self, json_value: Value) -> Result<()> {
+        self.try_send(json_value).await?;
+        Ok(())
+    }
+
+    /// This function sends a message to the topic we're publishing to, same as
+    /// [`UntypedPublisher::send`], and returns whether it was sent. It's only dropped if sending
+    /// it would exceed the maximum rate of the publisher, see [`PublisherOptions::max_rate_hz`].
+    ///
+    /// # Errors
+    /// This function will return an error if the message cannot be sent for any reason. See
+    /// [`UntypedPublisher::send`] for details.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn try_send(&self, json_value: Value) -> Result<bool> {
+        let dyn_message = self.parse(&json_value).context(self.key_expr(), "send")?;
+        #[cfg(feature = "validate")]
+        crate::validate::validate(&dyn_message).context(self.key_expr(), "send")?;
+        self.send_unchecked(&dyn_message).await
+    }
+
+    /// This function sends an already-built message to the topic we're publishing to, same as
+    /// [`UntypedPublisher::send`], but without going through JSON. This takes either a
+    /// [`DynamicMessage`] or a concrete prost message whose type is only known at runtime, such as
+    /// one loaded by a plugin. The message is sent with the type URL provided at creation time
+    /// without checking it, so it must be of that type for subscribers to decode it.
+    ///
+    /// # Errors
+    /// This function will return an error if the message cannot be sent for any reason. In
+    /// practice, this means there was an error returned by zenoh when sending down the channel.
+    /// With the `validate` feature, this also returns an error if the message violates the
+    /// protovalidate constraints of the publisher's type, checked by decoding it as that type.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn send_message<M: prost::Message>(&self, message: &M) -> Result<()> {
+        self.try_send_message(message).await?;
+        Ok(())
+    }
+
+    /// This function sends an already-built message to the topic we're publishing to, same as
+    /// [`UntypedPublisher::send_message`], and returns whether it was sent. See
+    /// [`UntypedPublisher::try_send`].
+    ///
+    /// # Errors
+    /// This function will return an error if the message cannot be sent for any reason. See
+    /// [`UntypedPublisher::send_message`] for details.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn try_send_message<M: prost::Message>(&self, message: &M) -> Result<bool> {
+        #[cfg(feature = "validate")]
+        {
+            let dyn_message = DynamicMessage::decode(
+                self.message_descriptor.clone(),
+                message.encode_to_vec().as_slice(),
+            )
+            .map_err(Error::from)
+            .context(self.key_expr(), "send")?;
+            crate::validate::validate(&dyn_message).context(self.key_expr(), "send")?;
+        }
+        self.send_unchecked(message).await
+    }
+
+    /// Sends a message without validating it, returning whether it was sent or dropped by the rate
+    /// limit.
+    async fn send_unchecked<M: prost::Message>(&self, message: &M) -> Result<bool> {
+        if !within_rate(self.rate_limiter.as_ref()) {
+            return Ok(false);
+        }
+
+        let header = self.new_header();
+        let (payload, attachment) =
+            encode_sample(message.encode_to_vec(), &header, self.header_in_attachment);
+        if self.log_traffic {
+            info!(
+                msg = "message_sent",
+                topic = self.key_expr(),
+                type_url = self.type_url,
+                bytes = payload.len(),
+            );
+        }
+        self.info_queryable
+            .set_latest_payload(payload.clone(), &header, attachment.is_none());
+        let _permit = self.in_flight.acquire().await;
+        let mut put = self.publisher.put(sample_value(payload));
+        if let Some(attachment) = attachment {
+            put = put.with_attachment(attachment);
+        }
+        put.res()
+            .await
+            .map_err(Error::from)
+            .context(self.key_expr(), "send")?;
+        Ok(true)
+    }
+
+    /// This function returns how many bytes the message would take on the wire if sent now with
+    /// [`UntypedPublisher::send`], header included, without sending it. See
+    /// [`Publisher::encoded_len`].
+    ///
+    /// # Errors
+    /// This function will return an error if the JSON value cannot be encoded as the type of this
+    /// publisher.
+    pub fn encoded_len(&self, json_value: &Value) -> Result<usize> {
+        Ok(encoded_len(
+            self.parse(json_value)?.encoded_len(),
+            &self.new_header(),
+            self.header_in_attachment,
+        ))
+    }
+
+    /// Parses a JSON value into a message of the type of this publisher.
+    fn parse(&self, json_value: &Value) -> Result<DynamicMessage> {
         let json_string = json_value.to_string();
         let mut deserializer = serde_json::Deserializer::from_str(&json_string);
-        let dyn_message =
-            DynamicMessage::deserialize(self.message_descriptor.clone(), &mut deserializer)?;
+        Ok(DynamicMessage::deserialize(
+            self.message_descriptor.clone(),
+            &mut deserializer,
+        )?)
+    }
 
-        let header = Header {
-            message_timestamp: Some(Timestamp::from(SystemTime::now())),
+    /// Returns the header of a message sent now by this publisher.
+    fn new_header(&self) -> Header {
+        Header {
+            message_timestamp: Some(Timestamp::from(self.clock.now())),
             type_url: self.type_url.clone(),
-        };
-        let mut buf = header.encode_length_delimited_to_vec();
-        buf.extend_from_slice(&dyn_message.encode_length_delimited_to_vec());
-        self.publisher.put(buf).res().await?;
-        Ok(())
+            ttl: self.message_ttl.and_then(|ttl| ttl.try_into().ok()),
+            ..Header::default()
+        }
+    }
+
+    /// This function sends a message given as YAML to the topic we're publishing to. The YAML is
+    /// converted to JSON and sent as with [`UntypedPublisher::send`], so it must follow the same
+    /// JSON mapping.
+    ///
+    /// # Errors
+    /// This function will return an error if the YAML cannot be parsed, or if the message cannot
+    /// be sent. See [`UntypedPublisher::send`] for details.
+    #[cfg(feature = "yaml")]
+    pub async fn send_yaml(&self, yaml: &str) -> Result<()> {
+        let json_value: Value = serde_yaml::from_str(yaml)
+            .map_err(Error::from)
+            .context(self.key_expr(), "send")?;
+        self.send(json_value).await
+    }
+}
+
+/// Options used to configure a publisher at creation time. Pass these to
+/// [`Node::publish_with_options`](crate::Node::publish_with_options) or
+/// [`Node::publish_untyped_with_options`](crate::Node::publish_untyped_with_options).
+#[derive(Clone, Debug, Default)]
+#[allow(clippy::module_name_repetitions)]
+pub struct PublisherOptions {
+    congestion_control: Option<CongestionControl>,
+    priority: Option<Priority>,
+    max_rate_hz: Option<f32>,
+    max_in_flight: Option<usize>,
+    header_in_attachment: bool,
+    message_ttl: Option<Duration>,
+}
+
+impl PublisherOptions {
+    /// Create publisher options with default values
+    #[must_use]
+    pub fn new() -> PublisherOptions {
+        Self::default()
+    }
+
+    /// Sets what happens when messages can't be sent as fast as they are published, usually
+    /// because of a slow subscriber or a saturated link. With [`CongestionControl::Drop`] (the
+    /// zenoh default), `send` never waits and messages get dropped instead, which suits periodic
+    /// data such as sensor readings or control loops where only the latest value matters. With
+    /// [`CongestionControl::Block`], `send` waits until the message can be sent, so no message is
+    /// lost, but a slow subscriber will slow down the publishing loop. Use it for commands and
+    /// other messages that must not be missed.
+    #[must_use]
+    pub fn congestion_control(mut self, congestion_control: CongestionControl) -> PublisherOptions {
+        self.congestion_control = Some(congestion_control);
+        self
+    }
+
+    /// Sets the priority zenoh gives to the messages of this publisher. Higher priority messages
+    /// get ahead of lower priority ones when links are congested. Individual messages can still
+    /// override it with [`Publisher::send_with_priority`].
+    #[must_use]
+    pub fn priority(mut self, priority: Priority) -> PublisherOptions {
+        self.priority = Some(priority);
+        self
+    }
+
+    /// Sets the maximum rate at which the publisher sends messages, in messages per second.
+    /// Messages sent faster than this are dropped, which moves throttling of fast producers out of
+    /// user code. The rate is enforced with a token bucket that holds a single message, so
+    /// messages are never sent in bursts. Every message sent by the publisher counts towards the
+    /// limit. Methods that return a bool report whether the message was dropped, such as
+    /// [`Publisher::try_send`], while `send` drops it silently. A rate that isn't a positive
+    /// finite number, such as zero or NaN, disables the limit.
+    #[must_use]
+    pub fn max_rate_hz(mut self, max_rate_hz: f32) -> PublisherOptions {
+        self.max_rate_hz = Some(max_rate_hz);
+        self
+    }
+
+    /// Sets how many messages of the publisher can be in the process of being sent at once. Once
+    /// the limit is reached, further sends wait for one of them to complete instead of piling up
+    /// in memory, which keeps producers that share a publisher across tasks from outpacing a
+    /// congested network. A send completes once zenoh has queued the message for transmission,
+    /// which only waits on the network with [`CongestionControl::Block`], so setting a limit also
+    /// makes the publisher use it, overriding [`PublisherOptions::congestion_control`]. A limit of
+    /// zero is treated as one.
+    #[must_use]
+    pub fn max_in_flight(mut self, max_in_flight: usize) -> PublisherOptions {
+        self.max_in_flight = Some(max_in_flight);
+        self
+    }
+
+    /// Sets whether the header of each message is sent as a zenoh attachment instead of in front of
+    /// the message. The payload then holds only the encoded protobuf message, so tools that don't
+    /// know about robotica (such as zenoh bridges or plain protobuf consumers) can decode it, and
+    /// middleware can read the header without touching the payload. Subscribers of this version of
+    /// robotica accept both forms, but older ones can't decode messages sent this way. Messages
+    /// sent with [`Publisher::send_acked`] always carry the header in front. Defaults to false.
+    #[must_use]
+    pub fn header_in_attachment(mut self, header_in_attachment: bool) -> PublisherOptions {
+        self.header_in_attachment = header_in_attachment;
+        self
+    }
+
+    /// Sets how long each message stays valid after it's sent. The TTL is recorded in the header
+    /// of every message, and once it has passed, the message is no longer reported as the latest
+    /// one on the topic (see [`Node::describe_topic`](crate::Node::describe_topic)). Storages and
+    /// other consumers can read it from the header to stop serving expired data. By default,
+    /// messages don't expire.
+    #[must_use]
+    pub fn message_ttl(mut self, ttl: Duration) -> PublisherOptions {
+        self.message_ttl = Some(ttl);
+        self
+    }
+
+    /// Returns the congestion control of a publisher with these options, if not zenoh's default.
+    /// Publishers with a maximum of messages in flight always block, see
+    /// [`PublisherOptions::max_in_flight`].
+    fn resolved_congestion_control(&self) -> Option<CongestionControl> {
+        match self.max_in_flight {
+            Some(_) => Some(CongestionControl::Block),
+            None => self.congestion_control,
+        }
+    }
+
+    /// Creates the rate limiter for a publisher with these options, if it has a maximum rate.
+    fn rate_limiter(&self) -> Option<Mutex<RateLimiter>> {
+        self.max_rate_hz
+            .filter(|max_rate_hz| max_rate_hz.is_finite() && *max_rate_hz > 0.)
+            .map(|max_rate_hz| Mutex::new(RateLimiter::new(max_rate_hz, Instant::now())))
+    }
+
+    /// Declares a zenoh publisher on a topic with these options.
+    pub(crate) async fn declare<'a>(
+        &self,
+        session: &'a Session,
+        topic: &str,
+    ) -> Result<zenoh::publication::Publisher<'a>> {
+        let mut publisher = session.declare_publisher(topic.to_string());
+        if let Some(congestion_control) = self.resolved_congestion_control() {
+            publisher = publisher.congestion_control(congestion_control);
+        }
+        if let Some(priority) = self.priority {
+            publisher = publisher.priority(priority);
+        }
+        Ok(publisher.res().await?)
+    }
+}
+
+/// Returns whether a message can be sent without exceeding the maximum rate of a publisher,
+/// consuming the allowance for it if so. Publishers without a rate limiter can always send.
+fn within_rate(rate_limiter: Option<&Mutex<RateLimiter>>) -> bool {
+    rate_limiter.map_or(true, |rate_limiter| {
+        rate_limiter
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .try_acquire()
+    })
+}
+
+/// Keeps track of the sends of a publisher that are in progress, limiting how many there can be at
+/// once if the publisher has a limit.
+struct InFlight {
+    semaphore: Semaphore,
+    capacity: u32,
+}
+
+impl InFlight {
+    fn new(max_in_flight: Option<usize>) -> InFlight {
+        let max_permits = u32::try_from(Semaphore::MAX_PERMITS).unwrap_or(u32::MAX);
+        let capacity = max_in_flight.map_or(max_permits, |max_in_flight| {
+            u32::try_from(max_in_flight.max(1)).map_or(max_permits, |max| max.min(max_permits))
+        });
+        InFlight {
+            semaphore: Semaphore::new(capacity as usize),
+            capacity,
+        }
+    }
+
+    /// Waits until a message can be sent without exceeding the limit. The returned permit must be
+    /// held until the message is sent.
+    async fn acquire(&self) -> SemaphorePermit<'_> {
+        self.semaphore
+            .acquire()
+            .await
+            .expect("in-flight semaphore is never closed")
+    }
+
+    /// Waits until no message is being sent.
+    async fn wait_idle(&self) {
+        let _permits = self
+            .semaphore
+            .acquire_many(self.capacity)
+            .await
+            .expect("in-flight semaphore is never closed");
+    }
+}
+
+/// A token bucket holding up to one token, refilled at a fixed rate.
+struct RateLimiter {
+    rate_hz: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    fn new(rate_hz: f32, now: Instant) -> RateLimiter {
+        RateLimiter {
+            rate_hz: rate_hz.into(),
+            tokens: 1.,
+            last_refill: now,
+        }
+    }
+
+    /// Takes a token from the bucket, returning false if it's empty.
+    fn try_acquire(&mut self) -> bool {
+        self.try_acquire_at(Instant::now())
+    }
+
+    /// Takes a token from the bucket at the given time, refilling it for the time elapsed since
+    /// the last call.
+    fn try_acquire_at(&mut self, now: Instant) -> bool {
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate_hz).min(1.);
+        self.last_refill = now;
+        if self.tokens >= 1. {
+            self.tokens -= 1.;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PublisherOptions, RateLimiter};
+    use std::time::{Duration, Instant};
+    use zenoh::publication::CongestionControl;
+
+    #[test]
+    fn rate_limiter_never_sends_bursts() {
+        let start = Instant::now();
+        let mut rate_limiter = RateLimiter::new(10., start);
+        assert!(rate_limiter.try_acquire_at(start));
+        assert!(!rate_limiter.try_acquire_at(start));
+        assert!(!rate_limiter.try_acquire_at(start + Duration::from_millis(50)));
+
+        // Idling doesn't let tokens pile up past a single message
+        let later = start + Duration::from_secs(10);
+        assert!(rate_limiter.try_acquire_at(later));
+        assert!(!rate_limiter.try_acquire_at(later));
+    }
+
+    #[test]
+    fn rate_limiter_refills_at_the_rate() {
+        let start = Instant::now();
+        let mut rate_limiter = RateLimiter::new(10., start);
+        assert!(rate_limiter.try_acquire_at(start));
+        assert!(!rate_limiter.try_acquire_at(start + Duration::from_millis(60)));
+        // Time elapsed before a rejected send still counts towards the next token
+        assert!(rate_limiter.try_acquire_at(start + Duration::from_millis(110)));
+        assert!(!rate_limiter.try_acquire_at(start + Duration::from_millis(150)));
+        assert!(rate_limiter.try_acquire_at(start + Duration::from_millis(250)));
+    }
+
+    #[test]
+    fn invalid_rates_disable_the_limit() {
+        for max_rate_hz in [0., -1., f32::NAN, f32::INFINITY] {
+            let options = PublisherOptions::new().max_rate_hz(max_rate_hz);
+            assert!(options.rate_limiter().is_none(), "{max_rate_hz}");
+        }
+        assert!(PublisherOptions::new().rate_limiter().is_none());
+        assert!(PublisherOptions::new()
+            .max_rate_hz(1.)
+            .rate_limiter()
+            .is_some());
+    }
+
+    #[test]
+    fn max_in_flight_implies_blocking() {
+        assert_eq!(PublisherOptions::new().resolved_congestion_control(), None);
+        let options = PublisherOptions::new().congestion_control(CongestionControl::Drop);
+        assert_eq!(
+            options.resolved_congestion_control(),
+            Some(CongestionControl::Drop)
+        );
+        let options = options.max_in_flight(4);
+        assert_eq!(
+            options.resolved_congestion_control(),
+            Some(CongestionControl::Block)
+        );
     }
 }
@@ -0,0 +1,126 @@
+use crate::{subscriber::ReceivedMessage, Result, UntypedSubscriber};
+use mcap::{records::MessageHeader, Channel, Schema};
+use prost::Message;
+use prost_reflect::{DynamicMessage, ReflectMessage};
+use std::{
+    borrow::Cow,
+    collections::{hash_map::Entry, BTreeMap, HashMap, VecDeque},
+    fs::File,
+    io::BufWriter,
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+/// This struct keeps a rolling window of the latest messages received on a set of untyped
+/// subscribers, which can be dumped to an MCAP file at any point. This is useful as a "black box"
+/// to inspect what happened right before a failure.
+pub struct Recorder {
+    subscribers: Vec<UntypedSubscriber>,
+    window: Duration,
+    buffer: VecDeque<RecordedMessage>,
+}
+
+struct RecordedMessage {
+    topic: String,
+    received_at: SystemTime,
+    message: ReceivedMessage<DynamicMessage>,
+}
+
+impl Recorder {
+    /// Creates a new recorder that keeps the messages received in the last `window` on any of the
+    /// given subscribers.
+    #[must_use]
+    pub fn new(subscribers: Vec<UntypedSubscriber>, window: Duration) -> Recorder {
+        Recorder {
+            subscribers,
+            window,
+            buffer: VecDeque::new(),
+        }
+    }
+
+    /// This function blocks until a message is received on any of the recorded subscribers, and
+    /// adds it to the buffer. Messages older than the window are dropped from the buffer. Call
+    /// this in a loop to keep the recorder up to date. If the recorder has no subscribers, this
+    /// function never returns.
+    ///
+    /// # Errors
+    /// This function will return an error if receiving from any of the subscribers fails. See
+    /// [`UntypedSubscriber::recv`] for details.
+    pub async fn record_next(&mut self) -> Result<()> {
+        if self.subscribers.is_empty() {
+            return futures::future::pending().await;
+        }
+        let (message, idx, _) = futures::future::select_all(
+            self.subscribers
+                .iter_mut()
+                .map(|subscriber| Box::pin(subscriber.recv())),
+        )
+        .await;
+        let received_at = SystemTime::now();
+        self.buffer.push_back(RecordedMessage {
+            topic: self.subscribers[idx].topic().into(),
+            received_at,
+            message: message?,
+        });
+        while self.buffer.front().is_some_and(|recorded| {
+            received_at
+                .duration_since(recorded.received_at)
+                .is_ok_and(|age| age > self.window)
+        }) {
+            self.buffer.pop_front();
+        }
+        Ok(())
+    }
+
+    /// This function writes all the messages currently in the buffer to an MCAP file at the given
+    /// path, overwriting it if it exists. Each topic is written as a protobuf channel, with the
+    /// schema taken from the file descriptors used to decode its messages.
+    ///
+    /// # Errors
+    /// This function will return an error if the file cannot be created or written to.
+    pub fn dump_to_mcap<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut writer = mcap::Writer::new(BufWriter::new(File::create(path)?))?;
+        let mut channel_ids = HashMap::new();
+        for (sequence, recorded) in self.buffer.iter().enumerate() {
+            let descriptor = recorded.message.message.descriptor();
+            let channel_id = match channel_ids
+                .entry((recorded.topic.clone(), descriptor.full_name().to_string()))
+            {
+                Entry::Occupied(entry) => *entry.get(),
+                Entry::Vacant(entry) => *entry.insert(writer.add_channel(&Channel {
+                    topic: recorded.topic.clone(),
+                    schema: Some(Arc::new(Schema {
+                        name: descriptor.full_name().into(),
+                        encoding: "protobuf".into(),
+                        data: Cow::Owned(descriptor.parent_pool().encode_to_vec()),
+                    })),
+                    message_encoding: "protobuf".into(),
+                    metadata: BTreeMap::new(),
+                })?),
+            };
+            let publish_time = recorded
+                .message
+                .header
+                .message_timestamp
+                .and_then(|timestamp| SystemTime::try_from(timestamp).ok())
+                .unwrap_or(recorded.received_at);
+            writer.write_to_known_channel(
+                &MessageHeader {
+                    channel_id,
+                    sequence: u32::try_from(sequence).unwrap_or(u32::MAX),
+                    log_time: nanos_since_epoch(recorded.received_at),
+                    publish_time: nanos_since_epoch(publish_time),
+                },
+                &recorded.message.message.encode_to_vec(),
+            )?;
+        }
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+fn nanos_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| u64::try_from(d.as_nanos()).unwrap_or(u64::MAX))
+}
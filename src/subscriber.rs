@@ -1,83 +1,653 @@
 use crate::{
+    cancel::cancellable,
+    clock::Clock,
+    codec::{Decoder, ProstCodec},
+    field_mask::FieldMask,
     proto::{parse_file_descriptors, search_file_descriptors},
-    Error, Result,
+    publisher::SAMPLE_ENCODING,
+    subscription::{SharedSubscription, Subscriptions},
+    Error, Result, ResultExt,
 };
+use bytes::Buf;
 use prost::Message;
 use prost_reflect::{DescriptorPool, DynamicMessage, MessageDescriptor};
-use robotica_types::Header;
-use std::marker::PhantomData;
-use tracing::instrument;
-use zenoh::{prelude::r#async::*, subscriber::FlumeSubscriber};
+use robotica_types::{Compression, Header};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet, VecDeque},
+    hash::Hasher,
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, instrument};
+use zenoh::{
+    buffers::{ZBuf, ZSlice},
+    prelude::r#async::*,
+};
+
+/// The key of the zenoh attachment carrying the header of messages sent by publishers configured
+/// with [`PublisherOptions::header_in_attachment`](crate::PublisherOptions::header_in_attachment).
+pub(crate) const HEADER_ATTACHMENT_KEY: &str = "robotica.header";
 
 /// This struct represents a subscriber to a topic. This guarantees to return messages of type M.
 /// Note that you cannot create this struct directly, but must instead fetch one from a
-/// [`Node`](crate::Node).
-pub struct Subscriber<'a, M: prost::Message + prost::Name + Default> {
-    subscriber: FlumeSubscriber<'a>,
-    _phantom: PhantomData<M>,
+/// [`Node`](crate::Node). All subscribers of a node on the same topic share a single zenoh
+/// subscriber, and each of them receives every message.
+pub struct Subscriber<M, C: Decoder<M> = ProstCodec> {
+    subscription: Arc<SharedSubscription>,
+    receiver: flume::Receiver<Sample>,
+    paused: Arc<AtomicBool>,
+    deduplicator: Option<Mutex<Deduplicator>>,
+    attribute_filter: HashMap<String, String>,
+    stale_filter: Option<StaleFilter>,
+    verify_encoding: bool,
+    log_traffic: bool,
+    _phantom: PhantomData<(M, C)>,
 }
 
-impl<'a, M: prost::Message + prost::Name + Default> Subscriber<'a, M> {
+impl<M, C: Decoder<M>> Subscriber<M, C> {
     pub(crate) async fn new_from_session<S: AsRef<str>>(
-        session: &'a Session,
+        session: &Arc<Session>,
+        subscriptions: &Subscriptions,
         topic: S,
+        clock: Arc<dyn Clock>,
+        options: &SubscriberOptions,
+        log_traffic: bool,
     ) -> Result<Self> {
-        let subscriber = session.declare_subscriber(topic.as_ref()).res().await?;
+        let paused = Arc::new(AtomicBool::new(false));
+        let (subscription, receiver) = subscriptions
+            .subscribe(session, topic.as_ref(), options.history, paused.clone())
+            .await?;
         Ok(Subscriber {
-            subscriber,
+            subscription,
+            receiver,
+            paused,
+            deduplicator: options
+                .dedup_window
+                .map(|w| Mutex::new(Deduplicator::new(w))),
+            attribute_filter: options.attribute_filter.clone(),
+            stale_filter: StaleFilter::new(options, clock),
+            verify_encoding: options.verify_encoding,
+            log_traffic,
             _phantom: PhantomData,
         })
     }
 
+    /// Returns the zenoh key expression this subscriber receives messages on. This is useful to
+    /// correlate robotica channels with raw zenoh tools. Messages sent only to this node with
+    /// [`Publisher::send_to`](crate::Publisher::send_to) are received on a separate key.
+    #[must_use]
+    pub fn key_expr(&self) -> &str {
+        self.subscription.key_expr()
+    }
+
+    /// Pauses the subscriber. While paused, messages received on the topic are discarded instead
+    /// of queued, so a subscriber that can't keep up doesn't hold back delivery to the other
+    /// subscribers of the topic. Messages queued before pausing can still be received. The
+    /// subscription itself stays declared, so resuming is immediate.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    /// Resumes a subscriber paused with [`Subscriber::pause`]. Messages received from now on are
+    /// queued again.
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+
+    /// Returns whether the subscriber is currently paused.
+    #[must_use]
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Closes the subscriber, passing every message still queued to the given sink before it's
+    /// dropped. New messages stop being queued first, so this returns once the queue is empty.
+    /// Use this to make sure the last messages received before a controlled shutdown are handled,
+    /// such as when recording. Messages that fail to decode are passed to the sink as errors.
+    pub fn close<F: FnMut(Result<ReceivedMessage<M>>)>(self, mut sink: F) {
+        self.pause();
+        loop {
+            match self.try_next_sample() {
+                Ok(Some(sample)) => {
+                    sink(decode_sample::<M, C>(&sample).context(self.key_expr(), "recv"));
+                }
+                Ok(None) | Err(Error::Flume(_)) => break,
+                Err(e) => sink(Err(e.context(self.key_expr(), "recv"))),
+            }
+        }
+    }
+
     /// This function blocks until a message is received on the topic we're subscribed to, per the
     /// `QoS` requirements of this subscriber.
     ///
     /// # Errors
     /// This function will return an error if the message cannot be received for any reason. In
     /// practice, this means either an error was returned by zenoh, or we failed to decode the
-    /// protobuf data.
+    /// protobuf data. The error is wrapped in [`Error::Context`], naming the topic.
     #[instrument(level = "trace", skip_all)]
     pub async fn recv(&self) -> Result<ReceivedMessage<M>> {
-        let sample = self.subscriber.recv_async().await?;
-        let bytes = sample.value.payload.contiguous();
-        let mut byte_ref = bytes.as_ref();
-        let header = Header::decode_length_delimited(&mut byte_ref)?;
-        if header.type_url == M::type_url() {
-            Ok(ReceivedMessage {
-                header,
-                message: M::decode_length_delimited(&mut byte_ref)?,
-            })
-        } else {
-            Err(Error::MismatchedSubscriberType {
-                expected: M::type_url(),
-                actual: header.type_url,
-            })
+        self.next_sample()
+            .await
+            .and_then(|sample| decode_sample::<M, C>(&sample))
+            .context(self.key_expr(), "recv")
+    }
+
+    /// This function blocks until a message is received on the topic we're subscribed to, same as
+    /// [`Subscriber::recv`], or until the given token is cancelled. This lets orchestration code
+    /// stop every task waiting on messages at once when shutting down a subsystem. No message is
+    /// lost on cancellation, it stays queued for the next call.
+    ///
+    /// # Errors
+    /// This function will return [`Error::Cancelled`] if the token is cancelled before a message
+    /// is received, or an error if the message cannot be received. See [`Subscriber::recv`] for
+    /// details.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn recv_with_cancel(&self, token: &CancellationToken) -> Result<ReceivedMessage<M>> {
+        cancellable(token, self.recv()).await
+    }
+
+    /// This function receives messages on the topic we're subscribed to until one satisfies the
+    /// predicate, and returns it. Messages that don't are dropped. This is useful in tests and
+    /// orchestration code that needs to wait for a given state, such as a goal being reached.
+    ///
+    /// # Errors
+    /// This function will return an error if no matching message is received within `timeout`, or
+    /// if any of the messages cannot be received. See [`Subscriber::recv`] for details.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn wait_for<F: FnMut(&ReceivedMessage<M>) -> bool>(
+        &self,
+        mut predicate: F,
+        timeout: Duration,
+    ) -> Result<ReceivedMessage<M>> {
+        let wait = async {
+            loop {
+                let message = self.recv().await?;
+                if predicate(&message) {
+                    return Ok(message);
+                }
+            }
+        };
+        let result = tokio::time::timeout(timeout, wait).await;
+        result.unwrap_or_else(|_| Err(Error::WaitTimeout(self.key_expr().into())))
+    }
+
+    /// This function blocks until a message is received on the topic we're subscribed to, same as
+    /// [`Subscriber::recv`], but without checking that the type URL in the header matches M. The
+    /// message is decoded as M regardless, which is useful during rolling upgrades where a type
+    /// gets renamed or moved to another package without changing its fields. The header still
+    /// carries the type URL the message was sent with.
+    ///
+    /// # Errors
+    /// This function will return an error if the message cannot be received for any reason. In
+    /// practice, this means either an error was returned by zenoh, or the protobuf data cannot be
+    /// decoded as M.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn recv_lenient(&self) -> Result<ReceivedMessage<M>> {
+        self.next_sample()
+            .await
+            .and_then(|sample| decode_lenient::<M, C>(&sample))
+            .context(self.key_expr(), "recv")
+    }
+
+    /// This function blocks until a message is received on the topic we're subscribed to, same as
+    /// [`Subscriber::recv`], but only decodes the header. The message itself is decoded when
+    /// calling [`LazyMessage::message`], which lets you skip decoding messages you'll discard
+    /// based on their header.
+    ///
+    /// # Errors
+    /// This function will return an error if the message cannot be received for any reason. In
+    /// practice, this means either an error was returned by zenoh, or we failed to decode the
+    /// header.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn recv_lazy(&self) -> Result<LazyMessage<M, C>> {
+        self.next_sample()
+            .await
+            .and_then(LazyMessage::new)
+            .context(self.key_expr(), "recv")
+    }
+
+    /// This function returns a batch of up to `max` messages received on the topic we're
+    /// subscribed to. It waits at most `timeout` for the first message, and then returns it along
+    /// with any other messages that are already queued, without waiting for more. If no message is
+    /// received before the timeout, an empty batch is returned.
+    ///
+    /// # Errors
+    /// This function will return an error if any of the messages cannot be received. See
+    /// [`Subscriber::recv`] for details. Note that on error, any messages already received as part
+    /// of this batch are dropped.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn recv_many(
+        &self,
+        max: usize,
+        timeout: Duration,
+    ) -> Result<Vec<ReceivedMessage<M>>> {
+        self.next_batch(max, timeout)
+            .await
+            .context(self.key_expr(), "recv")
+    }
+
+    /// Receives a batch of messages, see [`Subscriber::recv_many`].
+    async fn next_batch(&self, max: usize, timeout: Duration) -> Result<Vec<ReceivedMessage<M>>> {
+        let mut messages = Vec::new();
+        if max == 0 {
+            return Ok(messages);
+        }
+        let Ok(sample) = tokio::time::timeout(timeout, self.next_sample()).await else {
+            return Ok(messages);
+        };
+        // Everything already queued is likely to be part of the batch, so allocate for it at once
+        messages.reserve(max.min(self.receiver.len() + 1));
+        messages.push(decode_sample::<M, C>(&sample?)?);
+        while messages.len() < max {
+            let Some(sample) = self.try_next_sample()? else {
+                break;
+            };
+            messages.push(decode_sample::<M, C>(&sample)?);
         }
+        Ok(messages)
+    }
+
+    /// Waits for the next sample on the topic, skipping any duplicates and messages filtered out
+    /// by their attributes.
+    async fn next_sample(&self) -> Result<Sample> {
+        loop {
+            let sample = self.receiver.recv_async().await?;
+            check_encoding(self.verify_encoding, &sample, self.key_expr())?;
+            if matches_attributes(&self.attribute_filter, &sample)?
+                && is_fresh(self.stale_filter.as_ref(), &sample, self.key_expr())?
+                && !self.is_duplicate(&sample)?
+            {
+                self.log_received(&sample);
+                return Ok(sample);
+            }
+        }
+    }
+
+    /// Returns the next sample already queued on the topic, skipping any duplicates and messages
+    /// filtered out by their attributes, or `None` if there are no samples queued.
+    fn try_next_sample(&self) -> Result<Option<Sample>> {
+        loop {
+            let Some(sample) = try_recv(&self.receiver)? else {
+                return Ok(None);
+            };
+            check_encoding(self.verify_encoding, &sample, self.key_expr())?;
+            if matches_attributes(&self.attribute_filter, &sample)?
+                && is_fresh(self.stale_filter.as_ref(), &sample, self.key_expr())?
+                && !self.is_duplicate(&sample)?
+            {
+                self.log_received(&sample);
+                return Ok(Some(sample));
+            }
+        }
+    }
+
+    fn log_received(&self, sample: &Sample) {
+        if self.log_traffic {
+            info!(
+                msg = "message_received",
+                topic = self.subscription.key_expr(),
+                type_url = C::type_url(),
+                bytes = sample.value.payload.len(),
+            );
+        }
+    }
+
+    fn is_duplicate(&self, sample: &Sample) -> Result<bool> {
+        match &self.deduplicator {
+            Some(deduplicator) => deduplicator
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .is_duplicate(sample),
+            None => Ok(false),
+        }
+    }
+}
+
+impl<M: prost::Message + prost::Name + Default> Subscriber<M, ProstCodec> {
+    /// This function blocks until a message is received on the topic we're subscribed to, same as
+    /// [`Subscriber::recv`], but decodes it into the given message instead of allocating a new
+    /// one, and returns its header. The message is cleared first, so none of its previous contents
+    /// remain, but buffers it already allocated get reused. Reusing the same message in a loop
+    /// avoids per-message allocations on high-rate topics.
+    ///
+    /// # Errors
+    /// This function will return an error if the message cannot be received for any reason. In
+    /// practice, this means either an error was returned by zenoh, or we failed to decode the
+    /// protobuf data. On a decoding error, the message may be partially filled in.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn recv_into(&self, message: &mut M) -> Result<Header> {
+        self.next_sample()
+            .await
+            .and_then(|sample| merge_sample(&sample, message))
+            .context(self.key_expr(), "recv")
+    }
+}
+
+/// Decodes a sample into the given message, clearing it first, and returns its header. See
+/// [`Subscriber::recv_into`].
+fn merge_sample<M: prost::Message + prost::Name>(
+    sample: &Sample,
+    message: &mut M,
+) -> Result<Header> {
+    let (header, buf) = split_sample(sample)?;
+    let header = check_typed_header(header, M::type_url())?;
+    message.clear();
+    message.merge(buf)?;
+    Ok(header)
+}
+
+/// Returns whether the header of a sample has every attribute in the filter, with the same value.
+/// The message itself is not decoded.
+fn matches_attributes(filter: &HashMap<String, String>, sample: &Sample) -> Result<bool> {
+    if filter.is_empty() {
+        return Ok(true);
+    }
+    let header = decode_header(sample)?;
+    Ok(filter
+        .iter()
+        .all(|(key, value)| header.attributes.get(key) == Some(value)))
+}
+
+/// Checks that a sample is tagged with the encoding of robotica messages, if the subscriber was set
+/// to verify it with [`SubscriberOptions::verify_encoding`].
+fn check_encoding(verify_encoding: bool, sample: &Sample, topic: &str) -> Result<()> {
+    if verify_encoding && sample.value.encoding != Encoding::from(SAMPLE_ENCODING) {
+        return Err(Error::UnexpectedEncoding {
+            topic: topic.into(),
+            encoding: sample.value.encoding.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Returns whether a sample is recent enough to be received, per the maximum age set with
+/// [`SubscriberOptions::max_age`]. Stale samples are skipped, unless the subscriber errors on them.
+fn is_fresh(stale_filter: Option<&StaleFilter>, sample: &Sample, topic: &str) -> Result<bool> {
+    let Some(stale_filter) = stale_filter else {
+        return Ok(true);
+    };
+    let Some(timestamp) = decode_header(sample)?.message_timestamp else {
+        return Ok(true);
+    };
+    let Ok(sent_at) = SystemTime::try_from(timestamp) else {
+        return Ok(true);
+    };
+    // Messages timestamped in the future, such as when clocks are skewed, are not stale
+    let age = stale_filter
+        .clock
+        .now()
+        .duration_since(sent_at)
+        .unwrap_or_default();
+    if age <= stale_filter.max_age {
+        return Ok(true);
+    }
+    if stale_filter.error_on_stale {
+        return Err(Error::StaleMessage {
+            topic: topic.into(),
+            age,
+        });
+    }
+    Ok(false)
+}
+
+/// Returns the next sample already queued in a receiver, or `None` if the queue is empty.
+fn try_recv(receiver: &flume::Receiver<Sample>) -> Result<Option<Sample>> {
+    match receiver.try_recv() {
+        Ok(sample) => Ok(Some(sample)),
+        Err(flume::TryRecvError::Empty) => Ok(None),
+        Err(flume::TryRecvError::Disconnected) => Err(flume::RecvError::Disconnected.into()),
+    }
+}
+
+/// Decodes the header and body of a sample into a message of type M, checking that the type URL
+/// in the header matches.
+pub(crate) fn decode_sample<M, C: Decoder<M>>(sample: &Sample) -> Result<ReceivedMessage<M>> {
+    let (header, buf) = split_sample(sample)?;
+    Ok(ReceivedMessage {
+        header: check_typed_header(header, C::type_url())?,
+        message: C::decode(buf)?,
+    })
+}
+
+/// Decodes the header and body of a sample into a message of type M, without checking the type URL
+/// in the header. See [`Subscriber::recv_lenient`].
+fn decode_lenient<M, C: Decoder<M>>(sample: &Sample) -> Result<ReceivedMessage<M>> {
+    let (header, buf) = split_sample(sample)?;
+    check_compression(&header)?;
+    Ok(ReceivedMessage {
+        header,
+        message: C::decode(buf)?,
+    })
+}
+
+/// Decodes the header of a sample, without checking its type URL.
+pub(crate) fn decode_header(sample: &Sample) -> Result<Header> {
+    read_header(sample, &mut PayloadBuf::new(&sample.value.payload))
+}
+
+/// Decodes the header of a sample, from its attachment if it has one, or from the start of the
+/// payload otherwise, in which case the buffer is left at the end of the header. See
+/// [`PublisherOptions::header_in_attachment`](crate::PublisherOptions::header_in_attachment).
+pub(crate) fn read_header(sample: &Sample, buf: &mut PayloadBuf<'_>) -> Result<Header> {
+    match header_attachment(sample) {
+        Some(header_bytes) => Ok(Header::decode(&header_bytes[..])?),
+        None => Ok(Header::decode_length_delimited(buf)?),
+    }
+}
+
+/// Returns the header of a sample if it was sent as an attachment, still encoded.
+pub(crate) fn header_attachment(sample: &Sample) -> Option<ZSlice> {
+    sample.attachment()?.get(HEADER_ATTACHMENT_KEY)
+}
+
+/// Decodes the header of a sample, and returns it along with a buffer over the message that
+/// follows it.
+fn split_sample(sample: &Sample) -> Result<(Header, PayloadBuf<'_>)> {
+    let mut buf = PayloadBuf::new(&sample.value.payload);
+    let header = read_header(sample, &mut buf)?;
+    if header_attachment(sample).is_none() {
+        // Messages following a header in the payload are length-delimited
+        let len = prost::encoding::decode_length_delimiter(&mut buf)?;
+        check_len(len, buf.remaining())?;
+        buf.limit(len);
+    }
+    Ok((header, buf))
+}
+
+/// Checks that the length written in front of a message fits in what's left of its buffer, so a
+/// truncated message is reported instead of being decoded from the bytes that remain.
+pub(crate) fn check_len(expected: usize, actual: usize) -> Result<()> {
+    if actual < expected {
+        return Err(Error::TruncatedMessage { expected, actual });
+    }
+    Ok(())
+}
+
+/// Checks that a header is for a message of the expected type, which we know how to decode.
+fn check_typed_header(header: Header, expected_type_url: String) -> Result<Header> {
+    check_compression(&header)?;
+    if header.type_url == expected_type_url {
+        Ok(header)
+    } else {
+        Err(Error::MismatchedSubscriberType {
+            expected: expected_type_url,
+            actual: header.type_url,
+        })
+    }
+}
+
+/// Checks that the message following a header is stored in a way we know how to decode. Payloads
+/// compressed with an algorithm this version doesn't support would otherwise be decoded as
+/// garbage, or fail with a confusing protobuf error.
+pub(crate) fn check_compression(header: &Header) -> Result<()> {
+    match Compression::try_from(header.compression) {
+        Ok(Compression::None) => Ok(()),
+        Err(_) => Err(Error::UnsupportedCompression(header.compression)),
+    }
+}
+
+/// A message received by [`Subscriber::recv_lazy`]. The header is decoded on reception, but the
+/// message itself is only decoded when calling [`LazyMessage::message`].
+pub struct LazyMessage<M, C: Decoder<M> = ProstCodec> {
+    pub header: Header,
+    payload: ZBuf,
+    body_offset: usize,
+    body_len: usize,
+    _phantom: PhantomData<(M, C)>,
+}
+
+impl<M, C: Decoder<M>> LazyMessage<M, C> {
+    /// Decodes the header of a sample, checking that its type URL matches, and keeps the rest of
+    /// the payload to decode the message later.
+    fn new(sample: Sample) -> Result<LazyMessage<M, C>> {
+        let (header, body_offset, body_len) = {
+            let (header, buf) = split_sample(&sample)?;
+            (
+                check_typed_header(header, C::type_url())?,
+                buf.position(),
+                buf.remaining(),
+            )
+        };
+        Ok(LazyMessage {
+            header,
+            payload: sample.value.payload,
+            body_offset,
+            body_len,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Decodes the message. Note the message is decoded again on every call.
+    ///
+    /// # Errors
+    /// This function will return an error if the message data cannot be decoded.
+    pub fn message(&self) -> Result<M> {
+        let mut buf = PayloadBuf::new(&self.payload);
+        buf.advance(self.body_offset);
+        buf.limit(self.body_len);
+        C::decode(buf)
     }
 }
 
 #[allow(clippy::module_name_repetitions)]
-pub struct UntypedSubscriber<'a> {
-    subscriber: FlumeSubscriber<'a>,
+pub struct UntypedSubscriber {
+    subscription: Arc<SharedSubscription>,
+    receiver: flume::Receiver<Sample>,
+    paused: Arc<AtomicBool>,
+    topic: String,
     file_descriptor_pools: Vec<DescriptorPool>,
     active_message_descriptor: Option<(String, MessageDescriptor)>,
+    deduplicator: Option<Deduplicator>,
+    attribute_filter: HashMap<String, String>,
+    error_sender: Option<flume::Sender<Error>>,
+    stale_filter: Option<StaleFilter>,
+    verify_encoding: bool,
+    field_mask: Option<FieldMask>,
+    log_traffic: bool,
 }
 
-impl<'a> UntypedSubscriber<'a> {
+impl UntypedSubscriber {
     pub(crate) async fn new_from_session<S: AsRef<str>>(
-        session: &'a Session,
+        session: &Arc<Session>,
+        subscriptions: &Subscriptions,
         topic: S,
         file_descriptors_bytes: &[Vec<u8>],
+        clock: Arc<dyn Clock>,
+        options: &SubscriberOptions,
+        log_traffic: bool,
     ) -> Result<Self> {
-        let subscriber = session.declare_subscriber(topic.as_ref()).res().await?;
-        let file_descriptor_pools = parse_file_descriptors(file_descriptors_bytes)?;
+        let file_descriptor_pools = parse_file_descriptors(file_descriptors_bytes);
+        let paused = Arc::new(AtomicBool::new(false));
+        let (subscription, receiver) = subscriptions
+            .subscribe(session, topic.as_ref(), options.history, paused.clone())
+            .await?;
         Ok(UntypedSubscriber {
-            subscriber,
+            subscription,
+            receiver,
+            paused,
+            topic: topic.as_ref().into(),
             file_descriptor_pools,
             active_message_descriptor: None,
+            deduplicator: options.dedup_window.map(Deduplicator::new),
+            attribute_filter: options.attribute_filter.clone(),
+            error_sender: None,
+            stale_filter: StaleFilter::new(options, clock),
+            verify_encoding: options.verify_encoding,
+            field_mask: None,
+            log_traffic,
         })
     }
 
+    /// Returns the name of the topic this subscriber is subscribed to.
+    #[must_use]
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Returns the zenoh key expression this subscriber receives messages on. See
+    /// [`Subscriber::key_expr`].
+    #[must_use]
+    pub fn key_expr(&self) -> &str {
+        self.subscription.key_expr()
+    }
+
+    /// Pauses the subscriber, discarding messages until it's resumed. See [`Subscriber::pause`].
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    /// Resumes a subscriber paused with [`UntypedSubscriber::pause`].
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+
+    /// Returns whether the subscriber is currently paused.
+    #[must_use]
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Closes the subscriber, passing every message still queued to the given sink before it's
+    /// dropped. See [`Subscriber::close`].
+    pub fn close<F: FnMut(Result<ReceivedMessage<DynamicMessage>>)>(mut self, mut sink: F) {
+        self.pause();
+        loop {
+            match self.try_next_sample() {
+                Ok(Some(sample)) => sink(self.decode_dynamic(&sample).context(&self.topic, "recv")),
+                Ok(None) | Err(Error::Flume(_)) => break,
+                Err(e) => sink(Err(e.context(&self.topic, "recv"))),
+            }
+        }
+    }
+
+    /// Converts this subscriber into a typed [`Subscriber`] for messages of type M, once their
+    /// type is known. The same zenoh subscription is reused, so no messages are missed in between
+    /// and messages still queued are received by the typed subscriber. The pause state and message
+    /// filters carry over, with two exceptions: errors no longer get routed to the receiver
+    /// returned by [`UntypedSubscriber::route_errors`], and the field mask set with
+    /// [`UntypedSubscriber::with_field_mask`] is dropped, since typed messages are decoded whole.
+    /// As with [`Node::subscribe`](crate::Node::subscribe), the type isn't registered with the
+    /// node, see [`Node::register_type`](crate::Node::register_type).
+    #[must_use]
+    pub fn into_typed<M: prost::Message + prost::Name + Default>(self) -> Subscriber<M> {
+        Subscriber {
+            subscription: self.subscription,
+            receiver: self.receiver,
+            paused: self.paused,
+            deduplicator: self.deduplicator.map(Mutex::new),
+            attribute_filter: self.attribute_filter,
+            stale_filter: self.stale_filter,
+            verify_encoding: self.verify_encoding,
+            log_traffic: self.log_traffic,
+            _phantom: PhantomData,
+        }
+    }
+
     /// This function blocks until a message is received on the topic we're subscribed to, per the
     /// `QoS` requirements of this subscriber. Note the return type is a
     /// [`prost_reflect::DynamicMessage`], which can be queried for type information or serialized
@@ -88,29 +658,249 @@ impl<'a> UntypedSubscriber<'a> {
     /// practice, this means either an error was returned by zenoh, or we failed to decode the
     /// protobuf data. Note that because this is an untyped subscriber, we do a best-effort attempt
     /// at matching the type, but if two messages in the file descriptors have the same exact name,
-    /// we could end up decoding the wrong message silently.
-    ///
-    /// # Panics
-    /// This function will only panic if a u64 cannot be converted to a usize on your system.
+    /// we could end up decoding the wrong message silently. With the `validate` feature, this also
+    /// returns an error if the message violates the protovalidate constraints of its type. The
+    /// error is wrapped in [`Error::Context`], naming the topic.
     #[instrument(level = "trace", skip_all)]
     pub async fn recv(&mut self) -> Result<ReceivedMessage<DynamicMessage>> {
-        // Fetch message bytes and decode the header
-        let sample = self.subscriber.recv_async().await?;
-        let bytes = sample.value.payload.contiguous();
-        let mut byte_ref = bytes.as_ref();
-        let header = Header::decode_length_delimited(&mut byte_ref)?;
+        loop {
+            let result = match self.next_sample().await {
+                Ok(sample) => self.decode_dynamic(&sample),
+                Err(e) => Err(e),
+            };
+            match (result.context(&self.topic, "recv"), &self.error_sender) {
+                (Err(e), Some(error_sender)) if !matches!(e.root(), Error::Flume(_)) => {
+                    let _ = error_sender.send(e);
+                }
+                (result, _) => return result,
+            }
+        }
+    }
+
+    /// This function blocks until a message is received on the topic we're subscribed to, or until
+    /// the given token is cancelled. See [`Subscriber::recv_with_cancel`].
+    ///
+    /// # Errors
+    /// This function will return [`Error::Cancelled`] if the token is cancelled before a message
+    /// is received, or an error if the message cannot be received. See
+    /// [`UntypedSubscriber::recv`] for details.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn recv_with_cancel(
+        &mut self,
+        token: &CancellationToken,
+    ) -> Result<ReceivedMessage<DynamicMessage>> {
+        cancellable(token, self.recv()).await
+    }
+
+    /// This function receives messages on the topic we're subscribed to until one satisfies the
+    /// predicate, and returns it. See [`Subscriber::wait_for`].
+    ///
+    /// # Errors
+    /// This function will return an error if no matching message is received within `timeout`, or
+    /// if any of the messages cannot be received. See [`UntypedSubscriber::recv`] for details.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn wait_for<F: FnMut(&ReceivedMessage<DynamicMessage>) -> bool>(
+        &mut self,
+        mut predicate: F,
+        timeout: Duration,
+    ) -> Result<ReceivedMessage<DynamicMessage>> {
+        let wait = async {
+            loop {
+                let message = self.recv().await?;
+                if predicate(&message) {
+                    return Ok(message);
+                }
+            }
+        };
+        let result = tokio::time::timeout(timeout, wait).await;
+        result.unwrap_or_else(|_| Err(Error::WaitTimeout(self.topic.clone())))
+    }
+
+    /// Only keeps the given fields in the messages received, clearing all others once a message is
+    /// decoded. Fields are given as paths of field names separated by dots, going through nested
+    /// messages, like `pose.position`, and keeping a field keeps all of its subfields. Nested
+    /// messages in lists and maps are projected the same way. The whole message is still sent and
+    /// decoded, but consumers that only need a few fields of a large message hold and process less
+    /// data. Names that don't match any field are ignored. Calling this again replaces the
+    /// previous mask. The mask doesn't carry over to [`UntypedSubscriber::into_typed`].
+    #[must_use]
+    pub fn with_field_mask<I: IntoIterator<Item = S>, S: AsRef<str>>(
+        mut self,
+        paths: I,
+    ) -> UntypedSubscriber {
+        self.field_mask = Some(FieldMask::new(paths));
+        self
+    }
+
+    /// Routes the errors of individual messages to the returned receiver instead of returning them
+    /// from [`UntypedSubscriber::recv`], which then skips those messages and keeps delivering the
+    /// good ones. Errors from `recv` then only mean the subscription ended. This lets consumers log
+    /// or count bad messages without interrupting the flow of data. Errors are discarded once the
+    /// receiver is dropped. Calling this again replaces the previous receiver.
+    pub fn route_errors(&mut self) -> flume::Receiver<Error> {
+        let (error_sender, error_receiver) = flume::unbounded();
+        self.error_sender = Some(error_sender);
+        error_receiver
+    }
+
+    /// This function returns a batch of up to `max` messages received on the topic we're
+    /// subscribed to. It waits at most `timeout` for the first message, and then returns it along
+    /// with any other messages that are already queued, without waiting for more. If no message is
+    /// received before the timeout, an empty batch is returned.
+    ///
+    /// # Errors
+    /// This function will return an error if any of the messages cannot be received. See
+    /// [`UntypedSubscriber::recv`] for details. Note that on error, any messages already received
+    /// as part of this batch are dropped.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn recv_many(
+        &mut self,
+        max: usize,
+        timeout: Duration,
+    ) -> Result<Vec<ReceivedMessage<DynamicMessage>>> {
+        let topic = self.topic.clone();
+        self.next_batch(max, timeout).await.context(&topic, "recv")
+    }
+
+    /// Receives a batch of messages, see [`UntypedSubscriber::recv_many`].
+    async fn next_batch(
+        &mut self,
+        max: usize,
+        timeout: Duration,
+    ) -> Result<Vec<ReceivedMessage<DynamicMessage>>> {
+        let mut messages = Vec::new();
+        if max == 0 {
+            return Ok(messages);
+        }
+        let Ok(sample) = tokio::time::timeout(timeout, self.next_sample()).await else {
+            return Ok(messages);
+        };
+        messages.push(self.decode_dynamic(&sample?)?);
+        while messages.len() < max {
+            let Some(sample) = self.try_next_sample()? else {
+                break;
+            };
+            messages.push(self.decode_dynamic(&sample)?);
+        }
+        Ok(messages)
+    }
+
+    /// This function receives every message on the topic we're subscribed to and hands them to
+    /// the given sink in batches, until the given token is cancelled. Each batch holds all the
+    /// messages that were queued when it was taken, so a consumer that writes everything it gets,
+    /// such as a recorder, is woken up once per batch instead of once per message. Errors decoding
+    /// individual messages are passed to the sink in their batch, or to the receiver returned by
+    /// [`UntypedSubscriber::route_errors`] if it was called. Messages arriving after the token is
+    /// cancelled stay queued, use [`UntypedSubscriber::close`] to hand them to the sink too.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn drain_to<F: FnMut(Vec<Result<ReceivedMessage<DynamicMessage>>>)>(
+        &mut self,
+        token: &CancellationToken,
+        mut sink: F,
+    ) {
+        loop {
+            let mut batch = Vec::new();
+            let result = match cancellable(token, self.next_sample()).await {
+                Ok(sample) => self.decode_dynamic(&sample),
+                Err(Error::Cancelled | Error::Flume(_)) => return,
+                Err(e) => Err(e),
+            };
+            batch.extend(self.unrouted(result));
+            loop {
+                let result = match self.try_next_sample() {
+                    Ok(Some(sample)) => self.decode_dynamic(&sample),
+                    Ok(None) | Err(Error::Flume(_)) => break,
+                    Err(e) => Err(e),
+                };
+                batch.extend(self.unrouted(result));
+            }
+            if !batch.is_empty() {
+                sink(batch);
+            }
+        }
+    }
+
+    /// Sends the result of receiving a message to the receiver returned by
+    /// [`UntypedSubscriber::route_errors`] if it's an error and errors are routed, and returns it
+    /// otherwise.
+    fn unrouted(
+        &self,
+        result: Result<ReceivedMessage<DynamicMessage>>,
+    ) -> Option<Result<ReceivedMessage<DynamicMessage>>> {
+        match (result.context(&self.topic, "recv"), &self.error_sender) {
+            (Err(e), Some(error_sender)) => {
+                let _ = error_sender.send(e);
+                None
+            }
+            (result, _) => Some(result),
+        }
+    }
+
+    /// Waits for the next sample on the topic, skipping any duplicates and messages filtered out
+    /// by their attributes.
+    pub(crate) async fn next_sample(&mut self) -> Result<Sample> {
+        loop {
+            let sample = self.receiver.recv_async().await?;
+            check_encoding(self.verify_encoding, &sample, &self.topic)?;
+            if matches_attributes(&self.attribute_filter, &sample)?
+                && is_fresh(self.stale_filter.as_ref(), &sample, &self.topic)?
+                && !self.is_duplicate(&sample)?
+            {
+                return Ok(sample);
+            }
+        }
+    }
+
+    /// Returns the next sample already queued on the topic, skipping any duplicates and messages
+    /// filtered out by their attributes, or `None` if there are no samples queued.
+    fn try_next_sample(&mut self) -> Result<Option<Sample>> {
+        loop {
+            let Some(sample) = try_recv(&self.receiver)? else {
+                return Ok(None);
+            };
+            check_encoding(self.verify_encoding, &sample, &self.topic)?;
+            if matches_attributes(&self.attribute_filter, &sample)?
+                && is_fresh(self.stale_filter.as_ref(), &sample, &self.topic)?
+                && !self.is_duplicate(&sample)?
+            {
+                return Ok(Some(sample));
+            }
+        }
+    }
+
+    fn is_duplicate(&mut self, sample: &Sample) -> Result<bool> {
+        match &mut self.deduplicator {
+            Some(deduplicator) => deduplicator.is_duplicate(sample),
+            None => Ok(false),
+        }
+    }
+
+    pub(crate) fn decode_dynamic(
+        &mut self,
+        sample: &Sample,
+    ) -> Result<ReceivedMessage<DynamicMessage>> {
+        let (header, buf) = split_sample(sample)?;
+        check_compression(&header)?;
+        if self.log_traffic {
+            info!(
+                msg = "message_received",
+                topic = self.topic,
+                type_url = header.type_url,
+                bytes = sample.value.payload.len(),
+            );
+        }
 
         // Fetch the appropriate message descriptor
         let message_descriptor = self.get_message_descriptor(&header.type_url)?;
 
-        // Since messages are length-delimited, we need to read a single varint first
-        let len = usize::try_from(prost::encoding::decode_varint(&mut byte_ref)?)
-            .expect("u64 should always fit in usize");
+        let mut message = DynamicMessage::decode(message_descriptor.clone(), buf)?;
+        #[cfg(feature = "validate")]
+        crate::validate::validate(&message)?;
+        if let Some(field_mask) = &self.field_mask {
+            field_mask.apply(&mut message);
+        }
 
-        Ok(ReceivedMessage {
-            header,
-            message: DynamicMessage::decode(message_descriptor.clone(), &byte_ref[..len])?,
-        })
+        Ok(ReceivedMessage { header, message })
     }
 
     fn get_message_descriptor(&mut self, type_url: &str) -> Result<&MessageDescriptor> {
@@ -140,7 +930,405 @@ impl<'a> UntypedSubscriber<'a> {
     }
 }
 
+/// Options used to configure a subscriber at creation time. Pass these to
+/// [`Node::subscribe_with_options`](crate::Node::subscribe_with_options) or
+/// [`Node::subscribe_untyped_with_options`](crate::Node::subscribe_untyped_with_options).
+#[derive(Clone, Debug, Default)]
+#[allow(clippy::module_name_repetitions)]
+pub struct SubscriberOptions {
+    dedup_window: Option<Duration>,
+    history: Option<History>,
+    attribute_filter: HashMap<String, String>,
+    max_age: Option<Duration>,
+    error_on_stale: bool,
+    verify_encoding: bool,
+}
+
+impl SubscriberOptions {
+    /// Create subscriber options with default values
+    #[must_use]
+    pub fn new() -> SubscriberOptions {
+        Self::default()
+    }
+
+    /// Suppresses messages with the same contents as another message received within the given
+    /// window. This is useful on topics with redundant publishers. Only the message itself is
+    /// compared, so duplicates sent at different times by different publishers are still dropped.
+    #[must_use]
+    pub fn dedup_window(mut self, window: Duration) -> SubscriberOptions {
+        self.dedup_window = Some(window);
+        self
+    }
+
+    /// Sets how many received messages are kept until the subscriber reads them. See [`History`]
+    /// for the available options. By default, up to 256 messages are queued, and once the queue is
+    /// full, new messages are dropped and a warning is logged until the subscriber catches up.
+    #[must_use]
+    pub fn history(mut self, history: History) -> SubscriberOptions {
+        self.history = Some(history);
+        self
+    }
+
+    /// Only receive messages with the given attribute in their header, as set by
+    /// [`HeaderOverrides::attribute`](crate::HeaderOverrides::attribute). Calling this several
+    /// times requires all the attributes to match. Messages are filtered by their header only, so
+    /// the ones that don't match are never decoded.
+    #[must_use]
+    pub fn attribute_filter<S: AsRef<str>, S2: AsRef<str>>(
+        mut self,
+        key: S,
+        value: S2,
+    ) -> SubscriberOptions {
+        self.attribute_filter
+            .insert(key.as_ref().into(), value.as_ref().into());
+        self
+    }
+
+    /// Skips messages older than the given age by the time they're received, based on the
+    /// timestamp in their header and the clock of the node. This keeps control loops from acting
+    /// on stale data queued during a processing stall. Messages without a timestamp are never
+    /// considered stale.
+    #[must_use]
+    pub fn max_age(mut self, max_age: Duration) -> SubscriberOptions {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Sets whether receiving a message older than the age set with
+    /// [`SubscriberOptions::max_age`] returns [`Error::StaleMessage`] instead of skipping it.
+    /// Defaults to false.
+    #[must_use]
+    pub fn error_on_stale(mut self, error_on_stale: bool) -> SubscriberOptions {
+        self.error_on_stale = error_on_stale;
+        self
+    }
+
+    /// Sets whether received samples must be tagged with [`SAMPLE_ENCODING`], returning
+    /// [`Error::UnexpectedEncoding`] for any other sample. This catches other zenoh data
+    /// published on a shared key space. Messages sent by versions of robotica that didn't set the
+    /// encoding are rejected as well. Defaults to false.
+    #[must_use]
+    pub fn verify_encoding(mut self, verify_encoding: bool) -> SubscriberOptions {
+        self.verify_encoding = verify_encoding;
+        self
+    }
+}
+
+/// The maximum age of messages received by a subscriber. See [`SubscriberOptions::max_age`].
+struct StaleFilter {
+    max_age: Duration,
+    error_on_stale: bool,
+    clock: Arc<dyn Clock>,
+}
+
+impl StaleFilter {
+    fn new(options: &SubscriberOptions, clock: Arc<dyn Clock>) -> Option<StaleFilter> {
+        Some(StaleFilter {
+            max_age: options.max_age?,
+            error_on_stale: options.error_on_stale,
+            clock,
+        })
+    }
+}
+
+/// How received messages are queued until a subscriber reads them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum History {
+    /// Keep at most this many messages, dropping the oldest queued message when a new one arrives
+    /// and the queue is full. Delivery never blocks, but a slow subscriber silently misses
+    /// messages. Use this when only recent data matters, like sensor readings.
+    KeepLast(usize),
+    /// Keep every message until it's read. No message is ever dropped, but a subscriber that can't
+    /// keep up makes memory usage grow without bound.
+    KeepAll,
+}
+
+/// Keeps track of the hashes of recently received messages to detect duplicates.
+struct Deduplicator {
+    window: Duration,
+    /// The hashes seen within the window, oldest first, to expire them in order
+    seen: VecDeque<(Instant, u64)>,
+    /// The same hashes as `seen`, to look them up without going through the whole window
+    hashes: HashSet<u64>,
+}
+
+impl Deduplicator {
+    fn new(window: Duration) -> Deduplicator {
+        Deduplicator {
+            window,
+            seen: VecDeque::new(),
+            hashes: HashSet::new(),
+        }
+    }
+
+    /// Returns whether a message with the same contents as this sample was seen within the window.
+    /// Samples that are not duplicates are recorded for future checks.
+    fn is_duplicate(&mut self, sample: &Sample) -> Result<bool> {
+        self.is_duplicate_at(sample, Instant::now())
+    }
+
+    /// Checks whether a sample is a duplicate, as received at the given time.
+    fn is_duplicate_at(&mut self, sample: &Sample, now: Instant) -> Result<bool> {
+        let mut buf = PayloadBuf::new(&sample.value.payload);
+        // The header contains the send timestamp, so we only hash the message that follows it
+        read_header(sample, &mut buf)?;
+        let mut hasher = DefaultHasher::new();
+        while buf.has_remaining() {
+            let chunk = buf.chunk();
+            hasher.write(chunk);
+            let len = chunk.len();
+            buf.advance(len);
+        }
+        let hash = hasher.finish();
+
+        while let Some((seen_at, seen_hash)) = self.seen.front() {
+            if now.duration_since(*seen_at) <= self.window {
+                break;
+            }
+            self.hashes.remove(seen_hash);
+            self.seen.pop_front();
+        }
+        if self.hashes.insert(hash) {
+            self.seen.push_back((now, hash));
+            Ok(false)
+        } else {
+            Ok(true)
+        }
+    }
+}
+
+/// A [`Buf`] over the slices of a zenoh payload. Payloads split across several slices would
+/// otherwise have to be copied into a contiguous buffer before decoding them, which gets expensive
+/// for large messages like images or point clouds.
+pub(crate) struct PayloadBuf<'a> {
+    slices: VecDeque<&'a [u8]>,
+    remaining: usize,
+    position: usize,
+}
+
+impl<'a> PayloadBuf<'a> {
+    pub(crate) fn new(payload: &'a ZBuf) -> PayloadBuf<'a> {
+        let slices: VecDeque<&[u8]> = payload.slices().filter(|s| !s.is_empty()).collect();
+        let remaining = slices.iter().copied().map(<[u8]>::len).sum();
+        PayloadBuf {
+            slices,
+            remaining,
+            position: 0,
+        }
+    }
+
+    /// Returns how many bytes were read since the start of the payload.
+    fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Limits the buffer to the next `len` bytes of the payload, if there are more.
+    fn limit(&mut self, len: usize) {
+        self.remaining = self.remaining.min(len);
+    }
+}
+
+impl Buf for PayloadBuf<'_> {
+    fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    fn chunk(&self) -> &[u8] {
+        let front = self.slices.front().copied().unwrap_or_default();
+        &front[..front.len().min(self.remaining)]
+    }
+
+    fn advance(&mut self, mut cnt: usize) {
+        assert!(
+            cnt <= self.remaining,
+            "cannot advance past the end of the payload"
+        );
+        self.remaining -= cnt;
+        self.position += cnt;
+        while cnt > 0 {
+            let front = self
+                .slices
+                .front_mut()
+                .expect("remaining bytes must be in a slice");
+            if cnt < front.len() {
+                *front = &front[cnt..];
+                break;
+            }
+            cnt -= front.len();
+            self.slices.pop_front();
+        }
+    }
+}
+
 pub struct ReceivedMessage<M> {
     pub header: Header,
     pub message: M,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_sample, Deduplicator, PayloadBuf, HEADER_ATTACHMENT_KEY};
+    use crate::{publisher::frame, ProstCodec};
+    use bytes::Buf;
+    use prost::{Message, Name};
+    use prost_types::Timestamp;
+    use robotica_types::{Header, StringMessage};
+    use std::time::{Duration, Instant, SystemTime};
+    use zenoh::{
+        buffers::{ZBuf, ZSlice},
+        prelude::r#async::*,
+        sample::AttachmentBuilder,
+    };
+
+    /// Builds a payload made of the given slices, as zenoh hands us fragmented messages.
+    fn payload(slices: &[&[u8]]) -> ZBuf {
+        let mut payload = ZBuf::empty();
+        for slice in slices {
+            payload.push_zslice(ZSlice::from(slice.to_vec()));
+        }
+        payload
+    }
+
+    #[test]
+    fn reads_every_slice_in_order() {
+        let payload = payload(&[b"abc", b"", b"de", b"fghi"]);
+        let mut buf = PayloadBuf::new(&payload);
+        assert_eq!(buf.remaining(), 9);
+        assert_eq!(buf.chunk(), b"abc");
+
+        let mut data = [0; 9];
+        buf.copy_to_slice(&mut data);
+        assert_eq!(&data, b"abcdefghi");
+        assert_eq!(buf.remaining(), 0);
+        assert_eq!(buf.position(), 9);
+        assert!(buf.chunk().is_empty());
+    }
+
+    #[test]
+    fn advances_across_slice_boundaries() {
+        let payload = payload(&[b"abc", b"de", b"fghi"]);
+        let mut buf = PayloadBuf::new(&payload);
+
+        buf.advance(2);
+        assert_eq!(buf.chunk(), b"c");
+        buf.advance(1);
+        assert_eq!(buf.chunk(), b"de");
+        buf.advance(3);
+        assert_eq!(buf.chunk(), b"ghi");
+        assert_eq!(buf.remaining(), 3);
+        assert_eq!(buf.position(), 6);
+    }
+
+    #[test]
+    fn limit_stops_in_the_middle_of_a_slice() {
+        let payload = payload(&[b"abc", b"defg"]);
+        let mut buf = PayloadBuf::new(&payload);
+        buf.advance(1);
+        buf.limit(4);
+        assert_eq!(buf.remaining(), 4);
+        assert_eq!(buf.chunk(), b"bc");
+
+        buf.advance(2);
+        assert_eq!(buf.chunk(), b"de");
+        buf.advance(2);
+        assert!(buf.chunk().is_empty());
+        assert!(!buf.has_remaining());
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot advance past the end of the payload")]
+    fn advancing_past_the_end_panics() {
+        let payload = payload(&[b"ab", b"c"]);
+        let mut buf = PayloadBuf::new(&payload);
+        buf.advance(4);
+    }
+
+    #[test]
+    fn decodes_messages_split_at_any_point() {
+        let message = StringMessage {
+            data: "split across slices".into(),
+        };
+        let header = Header {
+            type_url: StringMessage::type_url(),
+            ..Header::default()
+        };
+        let bytes = frame(&header, &prost::Message::encode_to_vec(&message));
+        for split in 0..=bytes.len() {
+            let (front, back) = bytes.split_at(split);
+            let sample = Sample::new(
+                KeyExpr::try_from("test/topic").unwrap(),
+                Value::new(payload(&[front, back])),
+            );
+            let received = decode_sample::<StringMessage, ProstCodec>(&sample).unwrap();
+            assert_eq!(received.header, header);
+            assert_eq!(received.message, message);
+        }
+    }
+
+    /// Builds a sample carrying the given text, with a header stamped with the given time, in
+    /// front of the message or in an attachment.
+    fn text_sample(text: &str, sent_at: SystemTime, header_in_attachment: bool) -> Sample {
+        let header = Header {
+            message_timestamp: Some(Timestamp::from(sent_at)),
+            type_url: StringMessage::type_url(),
+            ..Header::default()
+        };
+        let message = StringMessage { data: text.into() }.encode_to_vec();
+        let key_expr = KeyExpr::try_from("test/topic").unwrap();
+        if !header_in_attachment {
+            return Sample::new(key_expr, frame(&header, &message));
+        }
+        let mut attachment = AttachmentBuilder::new();
+        attachment.insert(HEADER_ATTACHMENT_KEY, &header.encode_to_vec());
+        Sample::new(key_expr, message).with_attachment(attachment.build())
+    }
+
+    #[test]
+    fn duplicates_ignore_the_header() {
+        for header_in_attachment in [false, true] {
+            let start = Instant::now();
+            let sent_at = SystemTime::now();
+            let later = sent_at + Duration::from_secs(1);
+            let mut deduplicator = Deduplicator::new(Duration::from_secs(5));
+            let first = text_sample("a", sent_at, header_in_attachment);
+            assert!(!deduplicator.is_duplicate_at(&first, start).unwrap());
+            let resent = text_sample("a", later, header_in_attachment);
+            assert!(deduplicator.is_duplicate_at(&resent, start).unwrap());
+            let other = text_sample("b", sent_at, header_in_attachment);
+            assert!(!deduplicator.is_duplicate_at(&other, start).unwrap());
+        }
+    }
+
+    #[test]
+    fn duplicates_expire_after_the_window() {
+        let start = Instant::now();
+        let sent_at = SystemTime::now();
+        let mut deduplicator = Deduplicator::new(Duration::from_secs(5));
+        let sample = text_sample("a", sent_at, false);
+        assert!(!deduplicator.is_duplicate_at(&sample, start).unwrap());
+        let within = start + Duration::from_secs(5);
+        assert!(deduplicator.is_duplicate_at(&sample, within).unwrap());
+
+        // Duplicates don't extend the window, which starts from the first message
+        let after = start + Duration::from_secs(6);
+        assert!(!deduplicator.is_duplicate_at(&sample, after).unwrap());
+        assert!(deduplicator.is_duplicate_at(&sample, after).unwrap());
+    }
+
+    #[test]
+    fn duplicates_ignore_how_the_payload_is_split() {
+        let start = Instant::now();
+        let sample = text_sample("split", SystemTime::now(), false);
+        let bytes = sample.value.payload.contiguous().to_vec();
+        let mut deduplicator = Deduplicator::new(Duration::from_secs(5));
+        assert!(!deduplicator.is_duplicate_at(&sample, start).unwrap());
+        for split in 0..=bytes.len() {
+            let (front, back) = bytes.split_at(split);
+            let split_sample = Sample::new(
+                KeyExpr::try_from("test/topic").unwrap(),
+                Value::new(payload(&[front, back])),
+            );
+            assert!(deduplicator.is_duplicate_at(&split_sample, start).unwrap());
+        }
+    }
+}
@@ -0,0 +1,344 @@
+use crate::{info::topic_subscribers_key, History, Result};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError, Weak,
+    },
+};
+use tracing::warn;
+use zenoh::prelude::r#async::*;
+
+/// How many samples can be queued for each subscriber before new samples are dropped, unless the
+/// subscriber was configured with a [`History`]. This matches the default zenoh uses for its own
+/// subscribers.
+const SUBSCRIBER_QUEUE_SIZE: usize = 256;
+
+/// Returns the zenoh key that messages sent to a single node on a topic are published on. See
+/// [`Publisher::send_to`](crate::Publisher::send_to).
+pub(crate) fn unicast_key(target_id: &str, topic: &str) -> String {
+    format!("robotica/unicast/{target_id}/{topic}")
+}
+
+/// Returns the zenoh key that messages sent with [`Publisher::send_acked`] on a topic are queried
+/// on.
+///
+/// [`Publisher::send_acked`]: crate::Publisher::send_acked
+pub(crate) fn acked_key(topic: &str) -> String {
+    format!("robotica/acked/{topic}")
+}
+
+// Senders are shared through an Arc rather than cloned, since a clone of a sender keeping the last
+// samples holds another receiver on the queue, which would keep it from ever looking disconnected
+type Senders = Arc<Mutex<Vec<Arc<QueueSender>>>>;
+
+/// A zenoh subscriber shared between all the subscribers a node has on the same topic. Every
+/// sample received on the topic is forwarded to each of them, so they all see the same sequence
+/// of messages. Samples sent to this node only, on its unicast key for the topic, are forwarded
+/// the same way, as are messages sent with acknowledgement, which arrive as queries on the acked
+/// key for the topic and are answered with the ID of this node. The subscription also answers
+/// queries about the subscribers of the topic, so the node shows up in
+/// [`Node::graph`](crate::Node::graph). The zenoh subscribers are undeclared once all of them are
+/// dropped.
+pub(crate) struct SharedSubscription {
+    key_expr: String,
+    _subscriber: zenoh::subscriber::Subscriber<'static, ()>,
+    _unicast_subscriber: zenoh::subscriber::Subscriber<'static, ()>,
+    _acked_queryable: zenoh::queryable::Queryable<'static, ()>,
+    _info_queryable: zenoh::queryable::Queryable<'static, ()>,
+    senders: Senders,
+}
+
+impl SharedSubscription {
+    async fn new(session: &Arc<Session>, topic: &str) -> Result<SharedSubscription> {
+        let senders: Senders = Arc::default();
+        let callback_senders = senders.clone();
+        let subscriber = session
+            .declare_subscriber(topic)
+            .callback(move |sample| forward(&callback_senders, &sample))
+            .res()
+            .await?;
+        let callback_senders = senders.clone();
+        let unicast_subscriber = session
+            .declare_subscriber(unicast_key(&session.zid().to_string(), topic))
+            .callback(move |sample| forward(&callback_senders, &sample))
+            .res()
+            .await?;
+        let callback_senders = senders.clone();
+        let node_id = session.zid().to_string();
+        let acked_queryable = session
+            .declare_queryable(acked_key(topic))
+            .callback(move |query| {
+                use zenoh::prelude::sync::SyncResolve;
+
+                let Some(value) = query.value() else {
+                    return;
+                };
+                forward(
+                    &callback_senders,
+                    &Sample::new(query.key_expr().clone(), value.clone()),
+                );
+                let ack = Sample::new(query.key_expr().clone(), node_id.clone());
+                if let Err(e) = query.reply(Ok(ack)).res_sync() {
+                    warn!(msg = "ack_reply_failed", error = %e);
+                }
+            })
+            .res()
+            .await?;
+        let info_queryable = session
+            .declare_queryable(topic_subscribers_key(topic))
+            .callback(move |query| {
+                use zenoh::prelude::sync::SyncResolve;
+
+                let reply = Sample::new(query.key_expr().clone(), Vec::<u8>::new());
+                if let Err(e) = query.reply(Ok(reply)).res_sync() {
+                    warn!(msg = "topic_subscribers_reply_failed", error = %e);
+                }
+            })
+            .res()
+            .await?;
+        Ok(SharedSubscription {
+            key_expr: topic.into(),
+            _subscriber: subscriber,
+            _unicast_subscriber: unicast_subscriber,
+            _acked_queryable: acked_queryable,
+            _info_queryable: info_queryable,
+            senders,
+        })
+    }
+
+    /// Returns the zenoh key expression the subscription is declared on.
+    pub(crate) fn key_expr(&self) -> &str {
+        &self.key_expr
+    }
+
+    /// Returns a new receiver that gets every sample received on the topic from now on, queued
+    /// according to the given history. Samples received while `paused` is set are discarded.
+    fn receiver(
+        &self,
+        history: Option<History>,
+        paused: Arc<AtomicBool>,
+    ) -> flume::Receiver<Sample> {
+        let (sender, receiver) = QueueSender::new(&self.key_expr, history, paused);
+        self.senders
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(Arc::new(sender));
+        receiver
+    }
+}
+
+/// Sends a sample to every receiver of a subscription, dropping those that were disconnected.
+fn forward(senders: &Senders, sample: &Sample) {
+    // We don't hold the lock while sending, so new subscribers never wait on delivery
+    let senders_snapshot = senders
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    let mut disconnected = false;
+    for sender in &senders_snapshot {
+        disconnected |= !sender.send(sample.clone());
+    }
+    if disconnected {
+        senders
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|sender| !sender.is_disconnected());
+    }
+}
+
+/// The sending end of the queue of a single subscriber.
+struct QueueSender {
+    key_expr: String,
+    sender: flume::Sender<Sample>,
+    /// A receiver on the same queue, used to drop the oldest sample when keeping the last samples
+    oldest: Option<flume::Receiver<Sample>>,
+    paused: Arc<AtomicBool>,
+    /// Whether the queue was full when the last sample arrived, so a subscriber falling behind is
+    /// only reported once until it catches up
+    overflowing: AtomicBool,
+}
+
+impl QueueSender {
+    fn new(
+        key_expr: &str,
+        history: Option<History>,
+        paused: Arc<AtomicBool>,
+    ) -> (QueueSender, flume::Receiver<Sample>) {
+        let (sender, receiver, oldest) = match history {
+            None => {
+                let (sender, receiver) = flume::bounded(SUBSCRIBER_QUEUE_SIZE);
+                (sender, receiver, None)
+            }
+            Some(History::KeepAll) => {
+                let (sender, receiver) = flume::unbounded();
+                (sender, receiver, None)
+            }
+            Some(History::KeepLast(depth)) => {
+                let (sender, receiver) = flume::bounded(depth.max(1));
+                let oldest = receiver.clone();
+                (sender, receiver, Some(oldest))
+            }
+        };
+        (
+            QueueSender {
+                key_expr: key_expr.into(),
+                sender,
+                oldest,
+                paused,
+                overflowing: AtomicBool::new(false),
+            },
+            receiver,
+        )
+    }
+
+    /// Queues a sample without blocking, so a slow subscriber never holds up the others or the
+    /// zenoh callback. If the queue is full, the oldest queued sample is dropped if we're keeping
+    /// the last samples, and the new sample is dropped otherwise. The sample is discarded if the
+    /// subscriber is paused. Returns false if the subscriber was dropped.
+    fn send(&self, mut sample: Sample) -> bool {
+        if self.is_disconnected() {
+            return false;
+        }
+        if self.paused.load(Ordering::Relaxed) {
+            return true;
+        }
+        loop {
+            match self.sender.try_send(sample) {
+                Ok(()) => {
+                    self.overflowing.store(false, Ordering::Relaxed);
+                    return true;
+                }
+                Err(flume::TrySendError::Full(rejected)) => {
+                    let Some(oldest) = &self.oldest else {
+                        if !self.overflowing.swap(true, Ordering::Relaxed) {
+                            warn!(msg = "subscriber_queue_full", topic = self.key_expr);
+                        }
+                        return true;
+                    };
+                    let _ = oldest.try_recv();
+                    sample = rejected;
+                }
+                Err(flume::TrySendError::Disconnected(_)) => return false,
+            }
+        }
+    }
+
+    fn is_disconnected(&self) -> bool {
+        // We hold a receiver ourselves when keeping the last samples, so the queue never looks
+        // disconnected to flume and we need to count receivers instead
+        let own_receivers = usize::from(self.oldest.is_some());
+        self.sender.receiver_count() <= own_receivers
+    }
+}
+
+/// The shared subscription of a topic, if any subscriber of the node is using it. The async mutex
+/// is held while declaring the subscription, so concurrent subscribers to the same topic share a
+/// single one instead of declaring one each.
+type SubscriptionSlot = Arc<tokio::sync::Mutex<Weak<SharedSubscription>>>;
+
+/// Keeps track of the shared subscriptions of a node, by topic.
+#[derive(Default)]
+pub(crate) struct Subscriptions {
+    by_topic: Mutex<HashMap<String, SubscriptionSlot>>,
+}
+
+impl Subscriptions {
+    /// Returns the subscription for a topic, declaring it in zenoh if no subscriber of this node is
+    /// currently using it, along with a new receiver for it queued according to the given history.
+    /// Samples received while `paused` is set are discarded instead of queued.
+    pub(crate) async fn subscribe(
+        &self,
+        session: &Arc<Session>,
+        topic: &str,
+        history: Option<History>,
+        paused: Arc<AtomicBool>,
+    ) -> Result<(Arc<SharedSubscription>, flume::Receiver<Sample>)> {
+        let slot = self
+            .by_topic
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(topic.into())
+            .or_default()
+            .clone();
+        let mut slot = slot.lock().await;
+        let subscription = if let Some(subscription) = slot.upgrade() {
+            subscription
+        } else {
+            let subscription = Arc::new(SharedSubscription::new(session, topic).await?);
+            *slot = Arc::downgrade(&subscription);
+            subscription
+        };
+        let receiver = subscription.receiver(history, paused);
+        Ok((subscription, receiver))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{QueueSender, SUBSCRIBER_QUEUE_SIZE};
+    use crate::History;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+    use zenoh::prelude::r#async::*;
+
+    fn sample(index: usize) -> Sample {
+        Sample::new(KeyExpr::try_from("test/topic").unwrap(), index.to_string())
+    }
+
+    fn index(sample: &Sample) -> usize {
+        String::from_utf8(sample.value.payload.contiguous().to_vec())
+            .unwrap()
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn full_default_queue_drops_new_samples_without_blocking() {
+        let (sender, receiver) = QueueSender::new("test/topic", None, Arc::default());
+        for i in 0..SUBSCRIBER_QUEUE_SIZE + 10 {
+            assert!(sender.send(sample(i)));
+        }
+        let received: Vec<usize> = receiver.drain().map(|sample| index(&sample)).collect();
+        assert_eq!(received, (0..SUBSCRIBER_QUEUE_SIZE).collect::<Vec<_>>());
+
+        // Once the subscriber caught up, samples are queued again
+        assert!(sender.send(sample(1000)));
+        assert_eq!(index(&receiver.try_recv().unwrap()), 1000);
+    }
+
+    #[test]
+    fn keep_last_drops_the_oldest_samples() {
+        let (sender, receiver) =
+            QueueSender::new("test/topic", Some(History::KeepLast(2)), Arc::default());
+        for i in 0..5 {
+            assert!(sender.send(sample(i)));
+        }
+        let received: Vec<usize> = receiver.drain().map(|sample| index(&sample)).collect();
+        assert_eq!(received, vec![3, 4]);
+    }
+
+    #[test]
+    fn paused_subscribers_discard_samples() {
+        let paused = Arc::new(AtomicBool::new(true));
+        let (sender, receiver) = QueueSender::new("test/topic", None, paused.clone());
+        assert!(sender.send(sample(0)));
+        assert!(receiver.is_empty());
+
+        paused.store(false, Ordering::Relaxed);
+        assert!(sender.send(sample(1)));
+        assert_eq!(index(&receiver.try_recv().unwrap()), 1);
+    }
+
+    #[test]
+    fn dropped_subscribers_are_reported_as_disconnected() {
+        for history in [None, Some(History::KeepAll), Some(History::KeepLast(1))] {
+            let (sender, receiver) = QueueSender::new("test/topic", history, Arc::default());
+            drop(receiver);
+            assert!(sender.is_disconnected());
+            assert!(!sender.send(sample(0)));
+        }
+    }
+}
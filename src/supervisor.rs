@@ -0,0 +1,95 @@
+use crate::Result;
+use futures::FutureExt;
+use std::{
+    any::Any,
+    future::Future,
+    panic::AssertUnwindSafe,
+    time::{Duration, Instant},
+};
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// How long a supervised task waits before its first restart. The wait doubles on every
+/// consecutive failure.
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+
+/// The longest a supervised task waits before restarting. Tasks that ran for longer than this
+/// before failing are restarted after [`MIN_BACKOFF`] again.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// A task restarted whenever it fails, created with
+/// [`Node::supervise`](crate::Node::supervise). Supervision stops, and the task with it, when this
+/// is dropped.
+#[must_use = "supervision stops as soon as the task is dropped"]
+pub struct SupervisedTask {
+    task: JoinHandle<()>,
+}
+
+impl SupervisedTask {
+    pub(crate) fn start<F, Fut>(node_name: String, task_name: String, mut task_factory: F) -> Self
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send,
+    {
+        let task = tokio::spawn(async move {
+            let mut backoff = MIN_BACKOFF;
+            loop {
+                let started = Instant::now();
+                let error = match AssertUnwindSafe(task_factory()).catch_unwind().await {
+                    Ok(Ok(())) => {
+                        info!(
+                            msg = "supervised_task_finished",
+                            name = node_name,
+                            task = task_name
+                        );
+                        return;
+                    }
+                    Ok(Err(e)) => e.to_string(),
+                    Err(panic) => panic_message(&*panic),
+                };
+                if started.elapsed() > MAX_BACKOFF {
+                    backoff = MIN_BACKOFF;
+                }
+                warn!(
+                    msg = "supervised_task_restarting",
+                    name = node_name,
+                    task = task_name,
+                    error = error,
+                    backoff = ?backoff,
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        });
+        SupervisedTask { task }
+    }
+
+    /// Returns whether the task finished successfully, in which case it's not restarted.
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Stops the task and its supervision. This is the same as dropping it, but makes the intent
+    /// explicit.
+    pub fn stop(self) {
+        self.task.abort();
+    }
+}
+
+impl Drop for SupervisedTask {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Returns the message a task panicked with, if it's a string.
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        format!("panicked: {message}")
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        format!("panicked: {message}")
+    } else {
+        "panicked".into()
+    }
+}
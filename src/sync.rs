@@ -0,0 +1,195 @@
+use crate::{subscriber::ReceivedMessage, Result, UntypedSubscriber};
+use futures::future::select_all;
+use prost_reflect::DynamicMessage;
+use std::{
+    collections::VecDeque,
+    time::{Duration, SystemTime},
+};
+use tracing::{instrument, warn};
+
+/// How many messages are kept for each subscriber while waiting for matching messages on the
+/// others. Once full, the oldest message is dropped.
+const SYNC_QUEUE_SIZE: usize = 100;
+
+/// This struct matches messages received on several topics by their header timestamps, similar to
+/// the approximate time policy of ROS' `message_filters`. Each set of messages it returns has one
+/// message per subscriber, all with timestamps within the configured slop of each other. This is
+/// useful to combine data from sensors that publish at roughly the same time, like the cameras of
+/// a stereo pair. Messages that never get matched are dropped.
+pub struct TimeSynchronizer {
+    subscribers: Vec<UntypedSubscriber>,
+    queues: SyncQueues<ReceivedMessage<DynamicMessage>>,
+}
+
+impl TimeSynchronizer {
+    /// Creates a synchronizer for the given subscribers. Sets of messages are returned in the same
+    /// order as the subscribers, and are considered matching if the difference between their
+    /// oldest and newest timestamps is at most `slop`.
+    #[must_use]
+    pub fn new(subscribers: Vec<UntypedSubscriber>, slop: Duration) -> TimeSynchronizer {
+        let queues = SyncQueues::new(subscribers.len(), slop);
+        TimeSynchronizer {
+            subscribers,
+            queues,
+        }
+    }
+
+    /// This function blocks until a matching set of messages is received, with one message for
+    /// each subscriber, in the order they were given when creating the synchronizer. Messages
+    /// without a valid timestamp in their header can't be matched and are dropped.
+    ///
+    /// # Errors
+    /// This function will return an error if any of the subscribers fails to receive a message.
+    /// See [`UntypedSubscriber::recv`] for details.
+    #[instrument(level = "trace", skip_all)]
+    pub async fn recv(&mut self) -> Result<Vec<ReceivedMessage<DynamicMessage>>> {
+        if self.subscribers.is_empty() {
+            return Ok(Vec::new());
+        }
+        loop {
+            if let Some(messages) = self.queues.try_match() {
+                return Ok(messages);
+            }
+            let (message, idx, _) = select_all(
+                self.subscribers
+                    .iter_mut()
+                    .map(|subscriber| Box::pin(subscriber.recv())),
+            )
+            .await;
+            let message = message?;
+            let Some(Ok(timestamp)) = message
+                .header
+                .message_timestamp
+                .clone()
+                .map(SystemTime::try_from)
+            else {
+                warn!(
+                    msg = "unsynchronizable_message",
+                    topic = self.subscribers[idx].topic(),
+                    error = "missing or invalid timestamp"
+                );
+                continue;
+            };
+            self.queues.push(idx, timestamp, message);
+        }
+    }
+}
+
+/// The messages of each subscriber of a [`TimeSynchronizer`] waiting to be matched, oldest first.
+struct SyncQueues<T> {
+    queues: Vec<VecDeque<(SystemTime, T)>>,
+    slop: Duration,
+}
+
+impl<T> SyncQueues<T> {
+    fn new(len: usize, slop: Duration) -> SyncQueues<T> {
+        SyncQueues {
+            queues: (0..len).map(|_| VecDeque::new()).collect(),
+            slop,
+        }
+    }
+
+    /// Adds a message to the queue of the given subscriber, dropping its oldest message if full.
+    fn push(&mut self, idx: usize, timestamp: SystemTime, message: T) {
+        let queue = &mut self.queues[idx];
+        if queue.len() >= SYNC_QUEUE_SIZE {
+            queue.pop_front();
+        }
+        queue.push_back((timestamp, message));
+    }
+
+    /// Returns a matching set of messages from the queues, if there is one, dropping any messages
+    /// that can no longer be matched.
+    fn try_match(&mut self) -> Option<Vec<T>> {
+        loop {
+            let heads = self
+                .queues
+                .iter()
+                .map(|queue| queue.front().map(|(timestamp, _)| *timestamp))
+                .collect::<Option<Vec<_>>>()?;
+            let (oldest_idx, oldest) = heads
+                .iter()
+                .copied()
+                .enumerate()
+                .min_by_key(|(_, timestamp)| *timestamp)?;
+            let newest = heads.iter().copied().max()?;
+            if newest.duration_since(oldest).unwrap_or_default() <= self.slop {
+                return self
+                    .queues
+                    .iter_mut()
+                    .map(|queue| queue.pop_front().map(|(_, message)| message))
+                    .collect();
+            }
+            // Every other message on the topic with the newest head is newer still, so the oldest
+            // head can't be part of any future match
+            self.queues[oldest_idx].pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SyncQueues, SYNC_QUEUE_SIZE};
+    use std::time::{Duration, SystemTime};
+
+    fn at(millis: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_millis(millis)
+    }
+
+    /// Returns queues with a slop of 10ms, holding the given messages for each subscriber, each
+    /// message being its timestamp in milliseconds.
+    fn sync_queues(messages: &[&[u64]]) -> SyncQueues<u64> {
+        let mut queues = SyncQueues::new(messages.len(), Duration::from_millis(10));
+        for (idx, messages) in messages.iter().enumerate() {
+            for &millis in *messages {
+                queues.push(idx, at(millis), millis);
+            }
+        }
+        queues
+    }
+
+    #[test]
+    fn messages_within_the_slop_match() {
+        let mut queues = sync_queues(&[&[100, 200], &[110, 195], &[105, 205]]);
+        assert_eq!(queues.try_match(), Some(vec![100, 110, 105]));
+        assert_eq!(queues.try_match(), Some(vec![200, 195, 205]));
+        assert_eq!(queues.try_match(), None);
+    }
+
+    #[test]
+    fn nothing_matches_until_every_queue_has_a_message() {
+        let mut queues = sync_queues(&[&[100], &[]]);
+        assert_eq!(queues.try_match(), None);
+        queues.push(1, at(100), 100);
+        assert_eq!(queues.try_match(), Some(vec![100, 100]));
+    }
+
+    #[test]
+    fn the_oldest_head_is_dropped_when_it_cant_match() {
+        let mut queues = sync_queues(&[&[100, 150], &[111, 160]]);
+        assert_eq!(queues.try_match(), Some(vec![150, 160]));
+        // The heads at 100ms and 111ms were dropped on the way
+        assert_eq!(queues.queues[0].len(), 0);
+        assert_eq!(queues.queues[1].len(), 0);
+
+        let mut queues = sync_queues(&[&[100], &[200]]);
+        assert_eq!(queues.try_match(), None);
+        assert_eq!(queues.queues[0].len(), 0);
+        assert_eq!(queues.queues[1].len(), 1);
+    }
+
+    #[test]
+    fn full_queues_drop_their_oldest_message() {
+        let mut queues = sync_queues(&[&[], &[]]);
+        let count = u64::try_from(SYNC_QUEUE_SIZE).expect("usize should always fit in u64") + 1;
+        for millis in 0..count {
+            queues.push(0, at(millis * 100), millis * 100);
+        }
+        assert_eq!(queues.queues[0].len(), SYNC_QUEUE_SIZE);
+        queues.push(1, at(0), 0);
+        // The message at 0ms was dropped, so the other queue's message can't be matched
+        assert_eq!(queues.try_match(), None);
+        queues.push(1, at(100), 100);
+        assert_eq!(queues.try_match(), Some(vec![100, 100]));
+    }
+}
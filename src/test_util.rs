@@ -0,0 +1,113 @@
+use prost::Message;
+use prost_reflect::{DescriptorPool, DynamicMessage};
+use std::sync::OnceLock;
+
+/// A file with messages covering every kind of field, for tests going through messages by
+/// reflection. It's written in protobuf text format, and stands for the following:
+///
+/// ```proto
+/// syntax = "proto3";
+/// package test;
+///
+/// message Joint {
+///   string name = 1;
+///   double position = 2;
+/// }
+///
+/// message Robot {
+///   string name = 1;
+///   Joint base = 2;
+///   repeated Joint joints = 3;
+///   map<string, double> gains = 4;
+///   map<string, Joint> tools = 5;
+///   repeated double values = 6;
+///   optional double speed = 7;
+/// }
+/// ```
+pub(crate) const ROBOT_FILE: &str = r#"
+    file {
+      name: "test.proto"
+      package: "test"
+      syntax: "proto3"
+      message_type {
+        name: "Joint"
+        field { name: "name" number: 1 label: LABEL_OPTIONAL type: TYPE_STRING }
+        field { name: "position" number: 2 label: LABEL_OPTIONAL type: TYPE_DOUBLE }
+      }
+      message_type {
+        name: "Robot"
+        field { name: "name" number: 1 label: LABEL_OPTIONAL type: TYPE_STRING }
+        field {
+          name: "base" number: 2 label: LABEL_OPTIONAL type: TYPE_MESSAGE type_name: ".test.Joint"
+        }
+        field {
+          name: "joints" number: 3 label: LABEL_REPEATED type: TYPE_MESSAGE
+          type_name: ".test.Joint"
+        }
+        field {
+          name: "gains" number: 4 label: LABEL_REPEATED type: TYPE_MESSAGE
+          type_name: ".test.Robot.GainsEntry"
+        }
+        field {
+          name: "tools" number: 5 label: LABEL_REPEATED type: TYPE_MESSAGE
+          type_name: ".test.Robot.ToolsEntry"
+        }
+        field { name: "values" number: 6 label: LABEL_REPEATED type: TYPE_DOUBLE }
+        field {
+          name: "speed" number: 7 label: LABEL_OPTIONAL type: TYPE_DOUBLE oneof_index: 0
+          proto3_optional: true
+        }
+        nested_type {
+          name: "GainsEntry"
+          field { name: "key" number: 1 label: LABEL_OPTIONAL type: TYPE_STRING }
+          field { name: "value" number: 2 label: LABEL_OPTIONAL type: TYPE_DOUBLE }
+          options { map_entry: true }
+        }
+        nested_type {
+          name: "ToolsEntry"
+          field { name: "key" number: 1 label: LABEL_OPTIONAL type: TYPE_STRING }
+          field {
+            name: "value" number: 2 label: LABEL_OPTIONAL type: TYPE_MESSAGE
+            type_name: ".test.Joint"
+          }
+          options { map_entry: true }
+        }
+        oneof_decl { name: "_speed" }
+      }
+    }
+"#;
+
+/// Returns a pool with the well-known types and [`ROBOT_FILE`]. It's built once, since messages
+/// and descriptors from different pools never compare equal.
+pub(crate) fn robot_pool() -> DescriptorPool {
+    static POOL: OnceLock<DescriptorPool> = OnceLock::new();
+    POOL.get_or_init(|| pool(ROBOT_FILE)).clone()
+}
+
+/// Returns a pool with the well-known types and the files of the given file descriptor set, in
+/// protobuf text format.
+pub(crate) fn pool(file_descriptor_set: &str) -> DescriptorPool {
+    let mut pool = DescriptorPool::global();
+    add_files(&mut pool, file_descriptor_set);
+    pool
+}
+
+/// Adds the files of the given file descriptor set, in protobuf text format, to a pool. Options
+/// can use extensions defined by files already in the pool.
+pub(crate) fn add_files(pool: &mut DescriptorPool, file_descriptor_set: &str) {
+    let descriptor = pool
+        .get_message_by_name("google.protobuf.FileDescriptorSet")
+        .expect("the pool should have the well-known types");
+    let file_descriptor_set = DynamicMessage::parse_text_format(descriptor, file_descriptor_set)
+        .expect("file descriptor set should be valid text format");
+    pool.decode_file_descriptor_set(file_descriptor_set.encode_to_vec().as_slice())
+        .expect("file descriptor set should be valid");
+}
+
+/// Parses a message of the given type from protobuf text format.
+pub(crate) fn message(pool: &DescriptorPool, name: &str, text: &str) -> DynamicMessage {
+    let descriptor = pool
+        .get_message_by_name(name)
+        .expect("message type should be in the pool");
+    DynamicMessage::parse_text_format(descriptor, text).expect("message should be valid")
+}
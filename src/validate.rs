@@ -0,0 +1,503 @@
+use crate::{Error, Result};
+use prost_reflect::{DynamicMessage, ExtensionDescriptor, FieldDescriptor, Value};
+use std::cmp::Ordering;
+
+/// The extension of `google.protobuf.FieldOptions` that protovalidate stores field constraints in.
+const FIELD_CONSTRAINTS_EXTENSION: &str = "buf.validate.field";
+
+/// The protovalidate rules that apply to numeric fields, one per numeric type.
+const NUMERIC_RULES: [&str; 12] = [
+    "float", "double", "int32", "int64", "uint32", "uint64", "sint32", "sint64", "fixed32",
+    "fixed64", "sfixed32", "sfixed64",
+];
+
+/// Checks a message, and every message nested in it, against the protovalidate constraints on its
+/// fields. Only a subset of protovalidate is supported: `required`, the `const`, `lt`, `lte`, `gt`
+/// and `gte` rules of numeric fields, the `len`, `min_len`, `max_len`, `prefix`, `suffix` and
+/// `contains` rules of strings, and the `min_items`, `max_items` and `items` rules of repeated
+/// fields. Other rules are ignored, as are messages whose file descriptors don't include
+/// protovalidate's `buf/validate/validate.proto`.
+pub(crate) fn validate(message: &DynamicMessage) -> Result<()> {
+    let descriptor = message.descriptor();
+    match descriptor
+        .parent_pool()
+        .get_extension_by_name(FIELD_CONSTRAINTS_EXTENSION)
+    {
+        Some(extension) => validate_message(message, &extension, ""),
+        None => Ok(()),
+    }
+}
+
+fn validate_message(
+    message: &DynamicMessage,
+    extension: &ExtensionDescriptor,
+    path: &str,
+) -> Result<()> {
+    for field in message.descriptor().fields() {
+        let field_path = if path.is_empty() {
+            field.name().to_string()
+        } else {
+            format!("{path}.{}", field.name())
+        };
+        let options = field.options();
+        if options.has_extension(extension) {
+            if let Some(constraints) = options.get_extension(extension).as_message() {
+                check_field(message, &field, constraints, &field_path)?;
+            }
+        }
+        if message.has_field(&field) {
+            for nested in nested_messages(&message.get_field(&field)) {
+                validate_message(nested, extension, &field_path)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the messages held by a field value, if any.
+fn nested_messages(value: &Value) -> Vec<&DynamicMessage> {
+    match value {
+        Value::Message(message) => vec![message],
+        Value::List(items) => items.iter().filter_map(Value::as_message).collect(),
+        Value::Map(entries) => entries.values().filter_map(Value::as_message).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn check_field(
+    message: &DynamicMessage,
+    field: &FieldDescriptor,
+    constraints: &DynamicMessage,
+    path: &str,
+) -> Result<()> {
+    if !message.has_field(field) {
+        let required = constraints
+            .get_field_by_name("required")
+            .is_some_and(|required| required.as_bool() == Some(true));
+        if required {
+            return Err(violation(path, "required"));
+        }
+        // Fields without presence always have a value, which rules must be checked against
+        if field.supports_presence() {
+            return Ok(());
+        }
+    }
+    check_value(&message.get_field(field), constraints, path)
+}
+
+fn check_value(value: &Value, constraints: &DynamicMessage, path: &str) -> Result<()> {
+    if let Value::List(items) = value {
+        if let Some(rules) = nested_rules(constraints, "repeated") {
+            check_len(
+                items.len(),
+                &rules,
+                "repeated",
+                "min_items",
+                "max_items",
+                path,
+            )?;
+            if let Some(item_constraints) = nested_rules(&rules, "items") {
+                for (i, item) in items.iter().enumerate() {
+                    check_value(item, &item_constraints, &format!("{path}[{i}]"))?;
+                }
+            }
+        }
+        return Ok(());
+    }
+    for kind in NUMERIC_RULES {
+        if let Some(rules) = nested_rules(constraints, kind) {
+            check_number(value, &rules, kind, path)?;
+        }
+    }
+    if let (Some(rules), Some(string)) = (nested_rules(constraints, "string"), value.as_str()) {
+        check_string(string, &rules, path)?;
+    }
+    Ok(())
+}
+
+fn check_number(value: &Value, rules: &DynamicMessage, kind: &str, path: &str) -> Result<()> {
+    let checks: [(&str, fn(Ordering) -> bool); 5] = [
+        ("const", Ordering::is_eq),
+        ("lt", Ordering::is_lt),
+        ("lte", Ordering::is_le),
+        ("gt", Ordering::is_gt),
+        ("gte", Ordering::is_ge),
+    ];
+    for (rule, holds) in checks {
+        if !rules.has_field_by_name(rule) {
+            continue;
+        }
+        let Some(bound) = rules.get_field_by_name(rule) else {
+            continue;
+        };
+        if !compare(value, &bound).is_some_and(holds) {
+            return Err(violation(path, &format!("{kind}.{rule}")));
+        }
+    }
+    Ok(())
+}
+
+/// Compares two numeric values of the same type. Returns `None` if they aren't comparable, such
+/// as when either of them is NaN.
+fn compare(value: &Value, bound: &Value) -> Option<Ordering> {
+    match (value, bound) {
+        (Value::I32(a), Value::I32(b)) => Some(a.cmp(b)),
+        (Value::I64(a), Value::I64(b)) => Some(a.cmp(b)),
+        (Value::U32(a), Value::U32(b)) => Some(a.cmp(b)),
+        (Value::U64(a), Value::U64(b)) => Some(a.cmp(b)),
+        (Value::F32(a), Value::F32(b)) => a.partial_cmp(b),
+        (Value::F64(a), Value::F64(b)) => a.partial_cmp(b),
+        _ => None,
+    }
+}
+
+fn check_string(string: &str, rules: &DynamicMessage, path: &str) -> Result<()> {
+    let len = string.chars().count();
+    if let Some(expected) = rule_u64(rules, "len") {
+        if u64::try_from(len).map_or(true, |len| len != expected) {
+            return Err(violation(path, "string.len"));
+        }
+    }
+    check_len(len, rules, "string", "min_len", "max_len", path)?;
+    let checks: [(&str, fn(&str, &str) -> bool); 3] = [
+        ("prefix", |string, pattern| string.starts_with(pattern)),
+        ("suffix", |string, pattern| string.ends_with(pattern)),
+        ("contains", |string, pattern| string.contains(pattern)),
+    ];
+    for (rule, holds) in checks {
+        if !rules.has_field_by_name(rule) {
+            continue;
+        }
+        let pattern = rules.get_field_by_name(rule);
+        if let Some(pattern) = pattern.as_deref().and_then(Value::as_str) {
+            if !holds(string, pattern) {
+                return Err(violation(path, &format!("string.{rule}")));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Checks a length against the minimum and maximum set by the given rules, if any.
+fn check_len(
+    len: usize,
+    rules: &DynamicMessage,
+    kind: &str,
+    min_rule: &str,
+    max_rule: &str,
+    path: &str,
+) -> Result<()> {
+    let len = u64::try_from(len).unwrap_or(u64::MAX);
+    if rule_u64(rules, min_rule).is_some_and(|min| len < min) {
+        return Err(violation(path, &format!("{kind}.{min_rule}")));
+    }
+    if rule_u64(rules, max_rule).is_some_and(|max| len > max) {
+        return Err(violation(path, &format!("{kind}.{max_rule}")));
+    }
+    Ok(())
+}
+
+/// Returns the nested rules of the given name, if they are set.
+fn nested_rules(constraints: &DynamicMessage, name: &str) -> Option<DynamicMessage> {
+    if !constraints.has_field_by_name(name) {
+        return None;
+    }
+    constraints.get_field_by_name(name)?.as_message().cloned()
+}
+
+/// Returns the value of an unsigned integer rule, if it is set.
+fn rule_u64(rules: &DynamicMessage, name: &str) -> Option<u64> {
+    if !rules.has_field_by_name(name) {
+        return None;
+    }
+    rules.get_field_by_name(name)?.as_u64()
+}
+
+fn violation(path: &str, rule: &str) -> Error {
+    Error::ValidationFailed(format!("field \"{path}\" violates rule \"{rule}\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::validate;
+    use crate::test_util::{add_files, message, robot_pool};
+    use crate::Error;
+    use prost_reflect::{DescriptorPool, DynamicMessage, Value};
+
+    /// The subset of protovalidate's `buf/validate/validate.proto` that the tests use, in protobuf
+    /// text format.
+    const VALIDATE_FILE: &str = r#"
+        file {
+          name: "buf/validate/validate.proto"
+          package: "buf.validate"
+          dependency: "google/protobuf/descriptor.proto"
+          syntax: "proto2"
+          message_type {
+            name: "FieldConstraints"
+            field {
+              name: "double" number: 2 label: LABEL_OPTIONAL type: TYPE_MESSAGE
+              type_name: ".buf.validate.DoubleRules" oneof_index: 0
+            }
+            field {
+              name: "int32" number: 3 label: LABEL_OPTIONAL type: TYPE_MESSAGE
+              type_name: ".buf.validate.Int32Rules" oneof_index: 0
+            }
+            field {
+              name: "string" number: 14 label: LABEL_OPTIONAL type: TYPE_MESSAGE
+              type_name: ".buf.validate.StringRules" oneof_index: 0
+            }
+            field {
+              name: "repeated" number: 18 label: LABEL_OPTIONAL type: TYPE_MESSAGE
+              type_name: ".buf.validate.RepeatedRules" oneof_index: 0
+            }
+            field { name: "required" number: 25 label: LABEL_OPTIONAL type: TYPE_BOOL }
+            oneof_decl { name: "type" }
+          }
+          message_type {
+            name: "DoubleRules"
+            field { name: "const" number: 1 label: LABEL_OPTIONAL type: TYPE_DOUBLE }
+            field { name: "lt" number: 2 label: LABEL_OPTIONAL type: TYPE_DOUBLE }
+            field { name: "lte" number: 3 label: LABEL_OPTIONAL type: TYPE_DOUBLE }
+            field { name: "gt" number: 4 label: LABEL_OPTIONAL type: TYPE_DOUBLE }
+            field { name: "gte" number: 5 label: LABEL_OPTIONAL type: TYPE_DOUBLE }
+          }
+          message_type {
+            name: "Int32Rules"
+            field { name: "const" number: 1 label: LABEL_OPTIONAL type: TYPE_INT32 }
+            field { name: "lt" number: 2 label: LABEL_OPTIONAL type: TYPE_INT32 }
+            field { name: "lte" number: 3 label: LABEL_OPTIONAL type: TYPE_INT32 }
+            field { name: "gt" number: 4 label: LABEL_OPTIONAL type: TYPE_INT32 }
+            field { name: "gte" number: 5 label: LABEL_OPTIONAL type: TYPE_INT32 }
+          }
+          message_type {
+            name: "StringRules"
+            field { name: "min_len" number: 2 label: LABEL_OPTIONAL type: TYPE_UINT64 }
+            field { name: "max_len" number: 3 label: LABEL_OPTIONAL type: TYPE_UINT64 }
+            field { name: "prefix" number: 5 label: LABEL_OPTIONAL type: TYPE_STRING }
+            field { name: "suffix" number: 6 label: LABEL_OPTIONAL type: TYPE_STRING }
+            field { name: "contains" number: 7 label: LABEL_OPTIONAL type: TYPE_STRING }
+            field { name: "len" number: 19 label: LABEL_OPTIONAL type: TYPE_UINT64 }
+          }
+          message_type {
+            name: "RepeatedRules"
+            field { name: "min_items" number: 1 label: LABEL_OPTIONAL type: TYPE_UINT64 }
+            field { name: "max_items" number: 2 label: LABEL_OPTIONAL type: TYPE_UINT64 }
+            field {
+              name: "items" number: 4 label: LABEL_OPTIONAL type: TYPE_MESSAGE
+              type_name: ".buf.validate.FieldConstraints"
+            }
+          }
+          extension {
+            name: "field" number: 1159 label: LABEL_OPTIONAL type: TYPE_MESSAGE
+            type_name: ".buf.validate.FieldConstraints" extendee: ".google.protobuf.FieldOptions"
+          }
+        }
+    "#;
+
+    /// Messages with constraints on their fields, standing for the following:
+    ///
+    /// ```proto
+    /// syntax = "proto3";
+    /// package test;
+    /// import "buf/validate/validate.proto";
+    ///
+    /// message Limits {
+    ///   double speed = 1 [(buf.validate.field).double = {gte: 0, lt: 10}];
+    /// }
+    ///
+    /// message Command {
+    ///   Limits limits = 1 [(buf.validate.field).required = true];
+    ///   optional int32 priority = 2 [(buf.validate.field).int32 = {gt: 0, lte: 5}];
+    ///   optional int32 mode = 3 [(buf.validate.field).int32.const = 1];
+    ///   optional string frame = 4 [(buf.validate.field).string = {
+    ///     min_len: 7, max_len: 16, prefix: "/", suffix: "_link"
+    ///   }];
+    ///   optional string code = 5 [(buf.validate.field).string.len = 3];
+    ///   repeated string tags = 6 [(buf.validate.field).repeated = {
+    ///     max_items: 2, items: {string: {contains: "-"}}
+    ///   }];
+    /// }
+    /// ```
+    const COMMAND_FILE: &str = r#"
+        file {
+          name: "command.proto"
+          package: "test"
+          dependency: "buf/validate/validate.proto"
+          syntax: "proto3"
+          message_type {
+            name: "Limits"
+            field {
+              name: "speed" number: 1 label: LABEL_OPTIONAL type: TYPE_DOUBLE
+              options { [buf.validate.field] { double { gte: 0 lt: 10 } } }
+            }
+          }
+          message_type {
+            name: "Command"
+            field {
+              name: "limits" number: 1 label: LABEL_OPTIONAL type: TYPE_MESSAGE
+              type_name: ".test.Limits"
+              options { [buf.validate.field] { required: true } }
+            }
+            field {
+              name: "priority" number: 2 label: LABEL_OPTIONAL type: TYPE_INT32
+              oneof_index: 0 proto3_optional: true
+              options { [buf.validate.field] { int32 { gt: 0 lte: 5 } } }
+            }
+            field {
+              name: "mode" number: 3 label: LABEL_OPTIONAL type: TYPE_INT32
+              oneof_index: 1 proto3_optional: true
+              options { [buf.validate.field] { int32 { const: 1 } } }
+            }
+            field {
+              name: "frame" number: 4 label: LABEL_OPTIONAL type: TYPE_STRING
+              oneof_index: 2 proto3_optional: true
+              options {
+                [buf.validate.field] {
+                  string { min_len: 7 max_len: 16 prefix: "/" suffix: "_link" }
+                }
+              }
+            }
+            field {
+              name: "code" number: 5 label: LABEL_OPTIONAL type: TYPE_STRING
+              oneof_index: 3 proto3_optional: true
+              options { [buf.validate.field] { string { len: 3 } } }
+            }
+            field {
+              name: "tags" number: 6 label: LABEL_REPEATED type: TYPE_STRING
+              options {
+                [buf.validate.field] {
+                  repeated { max_items: 2 items { string { contains: "-" } } }
+                }
+              }
+            }
+            oneof_decl { name: "_priority" }
+            oneof_decl { name: "_mode" }
+            oneof_decl { name: "_frame" }
+            oneof_decl { name: "_code" }
+          }
+        }
+    "#;
+
+    /// Parses a command, setting its required limits if the text doesn't.
+    fn command(text: &str) -> DynamicMessage {
+        let mut pool = DescriptorPool::global();
+        add_files(&mut pool, VALIDATE_FILE);
+        add_files(&mut pool, COMMAND_FILE);
+        let mut command = message(&pool, "test.Command", text);
+        if !command.has_field_by_name("limits") {
+            let limits = message(&pool, "test.Limits", "");
+            command.set_field_by_name("limits", Value::Message(limits));
+        }
+        command
+    }
+
+    /// Returns the violation reported for a command, or `None` if it's valid.
+    fn violation(text: &str) -> Option<String> {
+        match validate(&command(text)) {
+            Ok(()) => None,
+            Err(Error::ValidationFailed(violation)) => Some(violation),
+            Err(e) => panic!("unexpected error: {e}"),
+        }
+    }
+
+    #[test]
+    fn valid_messages_pass() {
+        assert_eq!(violation(""), None);
+        assert_eq!(
+            violation(r#"limits { speed: 9.5 } tags: ["a-b", "c-d"]"#),
+            None
+        );
+        assert_eq!(
+            violation(r#"priority: 1 mode: 1 frame: "/base_link" code: "é€ü""#),
+            None
+        );
+    }
+
+    #[test]
+    fn messages_without_constraints_pass() {
+        let robot = message(&robot_pool(), "test.Robot", r#"name: "r1""#);
+        assert!(validate(&robot).is_ok());
+    }
+
+    #[test]
+    fn required_fields_must_be_set() {
+        let mut command = command("");
+        command.clear_field_by_name("limits");
+        assert_eq!(
+            validate(&command).unwrap_err().to_string(),
+            r#"message failed validation: field "limits" violates rule "required""#
+        );
+    }
+
+    #[test]
+    fn numbers_must_be_within_bounds() {
+        let cases = [
+            (
+                "limits { speed: 10 }",
+                r#"field "limits.speed" violates rule "double.lt""#,
+            ),
+            (
+                "limits { speed: -1 }",
+                r#"field "limits.speed" violates rule "double.gte""#,
+            ),
+            (
+                "priority: 0",
+                r#"field "priority" violates rule "int32.gt""#,
+            ),
+            (
+                "priority: 6",
+                r#"field "priority" violates rule "int32.lte""#,
+            ),
+            ("mode: 2", r#"field "mode" violates rule "int32.const""#),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(violation(text).as_deref(), Some(expected), "{text}");
+        }
+        assert_eq!(violation("priority: 5 limits { speed: 0 }"), None);
+    }
+
+    #[test]
+    fn strings_must_follow_their_rules() {
+        let cases = [
+            (
+                r#"frame: "/_link""#,
+                r#"field "frame" violates rule "string.min_len""#,
+            ),
+            (
+                r#"frame: "/a_very_long_link""#,
+                r#"field "frame" violates rule "string.max_len""#,
+            ),
+            (
+                r#"frame: "base_link""#,
+                r#"field "frame" violates rule "string.prefix""#,
+            ),
+            (
+                r#"frame: "/base_lnk""#,
+                r#"field "frame" violates rule "string.suffix""#,
+            ),
+            (
+                r#"code: "ab""#,
+                r#"field "code" violates rule "string.len""#,
+            ),
+            (
+                r#"code: "abcd""#,
+                r#"field "code" violates rule "string.len""#,
+            ),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(violation(text).as_deref(), Some(expected), "{text}");
+        }
+    }
+
+    #[test]
+    fn repeated_fields_check_their_items() {
+        assert_eq!(
+            violation(r#"tags: ["a-b", "c-d", "e-f"]"#).as_deref(),
+            Some(r#"field "tags" violates rule "repeated.max_items""#)
+        );
+        assert_eq!(
+            violation(r#"tags: ["a-b", "cd"]"#).as_deref(),
+            Some(r#"field "tags[1]" violates rule "string.contains""#)
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use crate::{subscriber::ReceivedMessage, Error, UntypedSubscriber};
+use prost_reflect::DynamicMessage;
+use std::{
+    collections::VecDeque,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex, PoisonError},
+};
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+/// The messages buffered by a [`WindowSubscriber`], oldest first.
+type Window = VecDeque<Arc<ReceivedMessage<DynamicMessage>>>;
+
+/// This struct keeps a rolling window of the last messages received on a topic, which can be read
+/// at any time without blocking, such as from the render loop of a live plotter. Messages are
+/// received on a background task, and once the window is full, each new message drops the oldest
+/// one. Messages that fail to decode are logged and skipped. Receiving stops when this is dropped.
+pub struct WindowSubscriber {
+    topic: String,
+    capacity: usize,
+    window: Arc<Mutex<Window>>,
+    task: JoinHandle<()>,
+}
+
+impl WindowSubscriber {
+    /// Starts buffering the messages received by the given subscriber, keeping up to `capacity` of
+    /// them.
+    ///
+    /// # Panics
+    /// This function panics if called outside of a tokio runtime.
+    #[must_use]
+    pub fn new(mut subscriber: UntypedSubscriber, capacity: NonZeroUsize) -> WindowSubscriber {
+        let capacity = capacity.get();
+        let topic = subscriber.topic().to_string();
+        let window = Arc::new(Mutex::new(VecDeque::with_capacity(capacity)));
+        let task_window = window.clone();
+        let task = tokio::spawn(async move {
+            loop {
+                match subscriber.recv().await {
+                    Ok(message) => {
+                        let mut window = task_window.lock().unwrap_or_else(PoisonError::into_inner);
+                        if window.len() == capacity {
+                            window.pop_front();
+                        }
+                        window.push_back(Arc::new(message));
+                    }
+                    Err(e) if matches!(e.root(), Error::Flume(_)) => {
+                        warn!(
+                            msg = "window_subscriber_closed",
+                            topic = subscriber.topic(),
+                            error = %e
+                        );
+                        return;
+                    }
+                    Err(e) => {
+                        warn!(
+                            msg = "window_message_dropped",
+                            topic = subscriber.topic(),
+                            error = %e
+                        );
+                    }
+                }
+            }
+        });
+        WindowSubscriber {
+            topic,
+            capacity,
+            window,
+            task,
+        }
+    }
+
+    /// Returns the topic this subscriber receives messages on.
+    #[must_use]
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// Returns the maximum number of messages kept in the window.
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the last `n` messages received, oldest first. Fewer messages are returned if not
+    /// that many were received yet. Messages are shared with the window, so this doesn't copy them.
+    #[must_use]
+    pub fn latest(&self, n: usize) -> Vec<Arc<ReceivedMessage<DynamicMessage>>> {
+        let window = self.window.lock().unwrap_or_else(PoisonError::into_inner);
+        window
+            .iter()
+            .skip(window.len().saturating_sub(n))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns every message currently in the window, oldest first.
+    #[must_use]
+    pub fn snapshot(&self) -> Vec<Arc<ReceivedMessage<DynamicMessage>>> {
+        self.latest(self.capacity)
+    }
+
+    /// Stops receiving messages. This is the same as dropping the subscriber, but makes the intent
+    /// explicit.
+    pub fn stop(self) {
+        self.task.abort();
+    }
+}
+
+impl Drop for WindowSubscriber {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
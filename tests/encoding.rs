@@ -0,0 +1,30 @@
+use robotica::{Node, Publisher, Subscriber, SubscriberOptions};
+use robotica_types::StringMessage;
+use std::time::Duration;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn acked_messages_pass_encoding_verification() -> anyhow::Result<()> {
+    let node = Node::builder("encoding_test")
+        .multicast_scouting(false)
+        .build()
+        .await?;
+    let subscriber: Subscriber<StringMessage> = node
+        .subscribe_with_options(
+            "encoding_test/acked",
+            SubscriberOptions::new().verify_encoding(true),
+        )
+        .await?;
+    let publisher: Publisher<StringMessage> = node.publish("encoding_test/acked").await?;
+
+    let message = StringMessage {
+        data: "hello".into(),
+    };
+    let acks = publisher
+        .send_acked(&message, Duration::from_secs(1))
+        .await?;
+    assert!(acks.contains(&node.id()));
+
+    let received = tokio::time::timeout(Duration::from_secs(1), subscriber.recv()).await??;
+    assert_eq!(received.message, message);
+    Ok(())
+}